@@ -1,6 +1,5 @@
 use std::path::PathBuf;
-use env_logger;
-use tsg_indexer::{IndexerConfig, run_indexer};
+use tsg_indexer::{IndexerConfig, OutputFormat, run_indexer};
 
 fn main() {
     // Initialize the logger
@@ -12,7 +11,10 @@ fn main() {
         path: PathBuf::from("examples"),
         
         // Output in JSON format
-        format: "json".to_string(),
+        format: OutputFormat::Json,
+        
+        // Use the built-in writer for that format
+        writer: None,
         
         // Print to stdout
         output: None,
@@ -25,11 +27,41 @@ fn main() {
         
         // Don't overwrite existing TSG files
         force_overwrite: false,
+        
+        // Name files relative to the indexed directory
+        root: None,
+        
+        // Record files that fail to index and keep going
+        fail_fast: false,
+        
+        // Rebuild the graph in memory instead of using an index database
+        db: None,
+        
+        // Use one worker thread per CPU
+        threads: 0,
+        
+        // Index every supported file that is not ignored
+        include: Vec::new(),
+        
+        // Skip the usual dependency and build directories
+        exclude: tsg_indexer::discovery::default_excludes(),
+        
+        // Index files in every supported language
+        languages: Vec::new(),
+        
+        // Draw the whole graph if DOT output is selected
+        dot_filter: tsg_indexer::output::DotFilter::default(),
     };
     
     // Run the indexer
     match run_indexer(config) {
-        Ok(_) => println!("Indexing completed successfully!"),
+        Ok(report) => {
+            println!("Indexing completed successfully!");
+            println!("{}", report.summary());
+            for failure in &report.failed {
+                println!("  failed: {} ({})", failure.file, failure.reason);
+            }
+        }
         Err(e) => eprintln!("Error during indexing: {}", e),
     }
 }
\ No newline at end of file
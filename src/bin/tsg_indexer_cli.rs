@@ -0,0 +1,738 @@
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use stack_graphs::graph::StackGraph;
+
+use tsg_indexer::discovery::{self, FileDiscovery};
+use tsg_indexer::languages::Language;
+use tsg_indexer::output::{DotFilter, OutputFormat};
+use tsg_indexer::query::QueryEngine;
+use tsg_indexer::stats::GraphStats;
+use tsg_indexer::storage::IndexDatabase;
+use tsg_indexer::symbols::{self, MatchMode, OutlineItem};
+use tsg_indexer::tsg_generator::TsgGenerator;
+use tsg_indexer::watch::{IndexWatcher, WatchEvent, DEFAULT_DEBOUNCE};
+use tsg_indexer::{build_stack_graph, indexer, run_indexer, IndexReport, IndexerConfig};
+
+/// Some files failed to index, but the rest of the run completed
+const EXIT_INDEX_FAILURES: u8 = 3;
+/// A query ran but found nothing
+const EXIT_NOT_FOUND: u8 = 4;
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  error
+  2  invalid arguments
+  3  some files failed to index
+  4  query found no results";
+
+/// Index code repositories into stack graphs and query them
+#[derive(Parser)]
+#[command(name = "tsg_indexer_cli", version, about, after_help = EXIT_CODES_HELP)]
+struct Cli {
+    /// Log progress information
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Index a file or directory and write its stack graph
+    Index(IndexArgs),
+    /// Look up definitions and references
+    #[command(subcommand)]
+    Query(QueryCommand),
+    /// Search the symbols defined in a project by name
+    Search(SearchArgs),
+    /// Print the definitions in a file as a tree of nested definitions
+    Outline(OutlineArgs),
+    /// Print counts of files, nodes and edges in a stack graph
+    Stats(StatsArgs),
+    /// Generate TSG files for languages without bundled stack graph rules
+    GenerateTsg(GenerateTsgArgs),
+    /// Write the stack graph of a path or of an existing index database
+    Export(ExportArgs),
+    /// Watch a directory and re-index files as they change, printing an event per file
+    Watch(WatchArgs),
+}
+
+#[derive(Subcommand)]
+enum QueryCommand {
+    /// Find the definitions of the symbol at a position
+    Def(QueryArgs),
+    /// Find all references to the symbol at a position, grouped by file
+    Refs(QueryArgs),
+}
+
+/// Which files to index
+#[derive(Args)]
+struct WalkArgs {
+    /// Project root that file names are relative to [default: the path itself for
+    /// directories, its parent for files, the current directory for search]
+    #[arg(long)]
+    root: Option<PathBuf>,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Number of worker threads; 0 uses one per CPU
+    #[arg(short = 'j', long, default_value_t = 0)]
+    threads: usize,
+
+    /// Abort on the first file that fails to index
+    #[arg(long)]
+    fail_fast: bool,
+}
+
+/// Include and exclude globs for the files under a directory
+#[derive(Args)]
+struct FilterArgs {
+    /// Only index files matching this glob; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching this glob; may be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Do not skip common dependency and build directories such as node_modules
+    #[arg(long)]
+    no_default_excludes: bool,
+}
+
+impl FilterArgs {
+    fn exclude(&self) -> Vec<String> {
+        let mut exclude = if self.no_default_excludes {
+            Vec::new()
+        } else {
+            discovery::default_excludes()
+        };
+        exclude.extend(self.exclude.iter().cloned());
+        exclude
+    }
+
+    fn discovery(&self) -> Result<FileDiscovery> {
+        FileDiscovery::new(&self.include, &self.exclude())
+    }
+}
+
+/// Where to write a graph, and in which format
+#[derive(Args)]
+struct OutputArgs {
+    /// Output format: json, ndjson (streamed file by file while indexing), dot, scip,
+    /// lsif, ctags, etags, cypher, graphml or neo4j (CSV files in the --output directory)
+    #[arg(short, long, default_value = "json")]
+    format: OutputFormat,
+
+    /// Output file [default: stdout]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Only draw nodes of this file in DOT output
+    #[arg(long, value_name = "FILE")]
+    dot_file: Option<String>,
+
+    /// Only draw the neighborhood of this symbol in DOT output
+    #[arg(long, value_name = "SYMBOL")]
+    dot_symbol: Option<String>,
+
+    /// Number of edges around --dot-symbol to draw
+    #[arg(long, value_name = "EDGES", default_value_t = 2)]
+    dot_depth: usize,
+}
+
+impl OutputArgs {
+    fn dot_filter(&self) -> DotFilter {
+        DotFilter {
+            file: self.dot_file.clone(),
+            symbol: self.dot_symbol.clone(),
+            depth: self.dot_depth,
+        }
+    }
+
+    /// Fail before any indexing is done if the format cannot be written
+    fn check_format(&self) -> Result<()> {
+        if self.format == OutputFormat::Neo4j && self.output.is_none() {
+            anyhow::bail!("The neo4j format writes CSV files to a directory given with --output");
+        }
+        Ok(())
+    }
+
+    /// Write the graph; `root` is the directory its file names are relative to
+    fn write(&self, stack_graph: &StackGraph, root: &Path) -> Result<()> {
+        self.format.writer(&self.dot_filter()).write(stack_graph, root, self.output.as_deref())
+    }
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    /// File or directory to index
+    path: PathBuf,
+
+    #[command(flatten)]
+    walk: WalkArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// SQLite index database; only files changed since the last run are re-indexed
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Generate TSG files for detected languages without bundled rules
+    #[arg(long)]
+    generate_tsg: bool,
+
+    /// Overwrite existing TSG files when generating them
+    #[arg(long)]
+    force_overwrite: bool,
+}
+
+#[derive(Args)]
+struct QueryArgs {
+    /// Position to query, as FILE:LINE:COLUMN with 1-based line and column
+    location: String,
+
+    #[command(flatten)]
+    walk: WalkArgs,
+
+    /// Query this index database, updating it first, instead of indexing in memory
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Print results as JSON, with zero-based lines and columns
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct SearchArgs {
+    /// Text to match against symbol names
+    query: String,
+
+    /// How to match: fuzzy, prefix or regex
+    #[arg(short, long, default_value = "fuzzy")]
+    mode: MatchMode,
+
+    /// Most results to print
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+
+    #[command(flatten)]
+    walk: WalkArgs,
+
+    /// Search this index database, updating it first, instead of indexing in memory
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Print results as JSON, with zero-based lines and columns
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct OutlineArgs {
+    /// File to outline
+    file: PathBuf,
+
+    #[command(flatten)]
+    walk: WalkArgs,
+
+    /// Read this index database, updating it first, instead of indexing in memory
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Print the outline as JSON, with zero-based lines and columns
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    /// File or directory to index [default: only read --db]
+    path: Option<PathBuf>,
+
+    #[command(flatten)]
+    walk: WalkArgs,
+
+    /// Index database to update from PATH, or to read if PATH is not given
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Print the counts as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct GenerateTsgArgs {
+    /// Languages to generate TSG files for, by name or file extension
+    languages: Vec<String>,
+
+    /// Also generate for languages found in this file or directory that have no
+    /// bundled stack graph rules
+    #[arg(long)]
+    path: Option<PathBuf>,
+
+    /// Directory to write the generated language definitions to
+    #[arg(long, default_value = "languages")]
+    tsg_root: PathBuf,
+
+    /// Overwrite existing TSG files
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// File or directory to index [default: only read --db]
+    path: Option<PathBuf>,
+
+    #[command(flatten)]
+    walk: WalkArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Index database to update from PATH, or to read if PATH is not given
+    #[arg(long)]
+    db: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    /// Directory to watch
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    /// Number of worker threads; 0 uses one per CPU
+    #[arg(short = 'j', long, default_value_t = 0)]
+    threads: usize,
+
+    /// Keep the index in this SQLite database instead of in memory
+    #[arg(long)]
+    db: Option<PathBuf>,
+
+    /// Milliseconds to wait for changes to settle before re-indexing
+    #[arg(long, value_name = "MS", default_value_t = DEFAULT_DEBOUNCE.as_millis() as u64)]
+    debounce: u64,
+
+    /// Print events as JSON lines
+    #[arg(long)]
+    json: bool,
+
+    /// Output format for --output: json, ndjson, dot, scip, lsif, ctags, etags,
+    /// cypher, graphml or neo4j
+    #[arg(short, long, default_value = "json")]
+    format: OutputFormat,
+
+    /// Rewrite the stack graph to this file after every update
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let level = if cli.verbose { "info" } else { "warn" };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+
+    match run(cli) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<ExitCode> {
+    match cli.command {
+        Command::Index(args) => index(args, cli.verbose),
+        Command::Query(QueryCommand::Def(args)) => query_definitions(args),
+        Command::Query(QueryCommand::Refs(args)) => query_references(args),
+        Command::Search(args) => search(args),
+        Command::Outline(args) => outline(args),
+        Command::Stats(args) => stats(args),
+        Command::GenerateTsg(args) => generate_tsg(args),
+        Command::Export(args) => export(args),
+        Command::Watch(args) => watch(args),
+    }
+}
+
+fn index(args: IndexArgs, verbose: bool) -> Result<ExitCode> {
+    args.output.check_format()?;
+
+    let config = IndexerConfig {
+        path: args.path.clone(),
+        format: args.output.format,
+        writer: None,
+        output: args.output.output.clone(),
+        verbose,
+        generate_tsg: args.generate_tsg,
+        force_overwrite: args.force_overwrite,
+        root: args.walk.root.clone(),
+        fail_fast: args.walk.fail_fast,
+        db: args.db.clone(),
+        threads: args.walk.threads,
+        include: args.walk.filter.include.clone(),
+        exclude: args.walk.filter.exclude(),
+        languages: Vec::new(),
+        dot_filter: args.output.dot_filter(),
+    };
+
+    let report = run_indexer(config)?;
+    Ok(report_exit_code(&report))
+}
+
+fn query_definitions(args: QueryArgs) -> Result<ExitCode> {
+    let (path, line, column) = parse_location(&args.location)?;
+    let (root, file_name) = query_target(&args.walk, &path);
+
+    let definitions = with_query_engine(&args.walk, args.db.as_deref(), &root, |engine| engine.find_definitions(&file_name, line, column))?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&definitions)?);
+    } else {
+        for definition in &definitions {
+            let kind = definition.syntax_type.as_deref().unwrap_or("definition");
+            println!("{}:{}:{}: {}", definition.file,
+                definition.span.start_line + 1, definition.span.start_column + 1, kind);
+        }
+    }
+
+    Ok(if definitions.is_empty() { ExitCode::from(EXIT_NOT_FOUND) } else { ExitCode::SUCCESS })
+}
+
+fn query_references(args: QueryArgs) -> Result<ExitCode> {
+    let (path, line, column) = parse_location(&args.location)?;
+    let (root, file_name) = query_target(&args.walk, &path);
+
+    let files = with_query_engine(&args.walk, args.db.as_deref(), &root, |engine| engine.find_references(&file_name, line, column))?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&files)?);
+    } else {
+        for file in &files {
+            println!("{}:", file.file);
+            for reference in &file.references {
+                let span = &reference.location.span;
+                match &reference.snippet {
+                    Some(snippet) => println!("  {}:{}: {}", span.start_line + 1, span.start_column + 1, snippet),
+                    None => println!("  {}:{}", span.start_line + 1, span.start_column + 1),
+                }
+            }
+        }
+    }
+
+    Ok(if files.is_empty() { ExitCode::from(EXIT_NOT_FOUND) } else { ExitCode::SUCCESS })
+}
+
+fn search(args: SearchArgs) -> Result<ExitCode> {
+    let root = args.walk.root.clone().unwrap_or_else(|| PathBuf::from("."));
+    let symbols = with_query_engine(&args.walk, args.db.as_deref(), &root, |engine| engine.symbols())?;
+
+    let mut matches = symbols::search(&symbols, &args.query, args.mode)?;
+    if let Some(limit) = args.limit {
+        matches.truncate(limit);
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&matches)?);
+    } else {
+        for symbol in &matches {
+            let kind = symbol.kind.as_deref().unwrap_or("definition");
+            println!("{}:{}:{}: {} {}", symbol.file,
+                symbol.span.start_line + 1, symbol.span.start_column + 1, kind, symbol.qualified_name());
+        }
+    }
+
+    Ok(if matches.is_empty() { ExitCode::from(EXIT_NOT_FOUND) } else { ExitCode::SUCCESS })
+}
+
+fn outline(args: OutlineArgs) -> Result<ExitCode> {
+    let (root, file_name) = query_target(&args.walk, &args.file);
+    let outline = with_query_engine(&args.walk, args.db.as_deref(), &root, |engine| engine.outline(&file_name))?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&outline)?);
+    } else {
+        print_outline(&outline, 0);
+    }
+
+    Ok(if outline.is_empty() { ExitCode::from(EXIT_NOT_FOUND) } else { ExitCode::SUCCESS })
+}
+
+/// Print an outline as an indented tree, with the lines each definition spans
+fn print_outline(items: &[OutlineItem], depth: usize) {
+    for item in items {
+        let kind = item.kind.as_deref().unwrap_or("definition");
+        let lines = match item.range {
+            Some(range) if range.end_line > range.start_line => format!("{}-{}", range.start_line + 1, range.end_line + 1),
+            _ => format!("{}", item.span.start_line + 1),
+        };
+        println!("{:indent$}{} {} ({})", "", kind, item.name, lines, indent = depth * 2);
+        print_outline(&item.children, depth + 1);
+    }
+}
+
+/// Project root to index for a query, and the queried file's name within it
+fn query_target(walk: &WalkArgs, path: &Path) -> (PathBuf, String) {
+    let root = walk.root.clone().unwrap_or_else(|| indexer::default_root(path));
+    let file_name = indexer::file_name_for(&root, path);
+    (root, file_name)
+}
+
+/// Run a query against the database if one is given, or against the root indexed in memory
+fn with_query_engine<T>(
+    walk: &WalkArgs,
+    db: Option<&Path>,
+    root: &Path,
+    query: impl FnOnce(&mut QueryEngine) -> Result<T>,
+) -> Result<T> {
+    let discovery = walk.filter.discovery()?;
+
+    match db {
+        Some(db_path) => {
+            let mut database = IndexDatabase::open(db_path)?;
+            database.update(root, root, &discovery, walk.fail_fast, walk.threads)?;
+
+            let mut engine = QueryEngine::from_database(&database)?;
+            query(&mut engine)
+        }
+        None => {
+            let mut stack_graph = StackGraph::new();
+            indexer::index_directory_parallel(
+                &mut stack_graph, root, root, &discovery, walk.fail_fast, walk.threads,
+            )?;
+
+            let mut engine = QueryEngine::new(&stack_graph)?;
+            query(&mut engine)
+        }
+    }
+}
+
+fn stats(args: StatsArgs) -> Result<ExitCode> {
+    let (stack_graph, report) = load_graph(args.path.as_deref(), args.db.as_deref(), &args.walk)?;
+    let stats = GraphStats::from_graph(&stack_graph);
+
+    if args.json {
+        let output = serde_json::json!({ "graph": stats, "report": report });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        println!("files:       {}", stats.files);
+        println!("nodes:       {}", stats.nodes);
+        println!("edges:       {}", stats.edges);
+        println!("definitions: {}", stats.definitions);
+        println!("references:  {}", stats.references);
+        for (kind, count) in &stats.nodes_by_kind {
+            println!("  {:<19} {}", kind, count);
+        }
+        if let Some(report) = &report {
+            println!("{}", report.summary());
+        }
+    }
+
+    Ok(report.as_ref().map(report_exit_code).unwrap_or(ExitCode::SUCCESS))
+}
+
+fn generate_tsg(args: GenerateTsgArgs) -> Result<ExitCode> {
+    let mut languages = Vec::new();
+    for name in &args.languages {
+        match Language::from_name(name) {
+            Language::Unknown => anyhow::bail!("Unknown language: {}", name),
+            language => languages.push(language),
+        }
+    }
+
+    if let Some(path) = &args.path {
+        let detected = FileDiscovery::default().detect_languages(path)?;
+        languages.extend(detected.into_iter().filter(|language| language.get_stack_graphs_language().is_none()));
+    }
+
+    if languages.is_empty() {
+        anyhow::bail!("No languages to generate TSG files for; name some or pass --path");
+    }
+
+    let generator = TsgGenerator::new(&args.tsg_root, args.force);
+    for language in &languages {
+        generator.generate(language)
+            .with_context(|| format!("Failed to generate TSG files for {}", language.name()))?;
+        println!("{}: {}", language.name(), args.tsg_root.display());
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn export(args: ExportArgs) -> Result<ExitCode> {
+    args.output.check_format()?;
+    let (stack_graph, report) = load_graph(args.path.as_deref(), args.db.as_deref(), &args.walk)?;
+    let root = args.walk.root.clone()
+        .or_else(|| args.path.as_deref().map(indexer::default_root))
+        .unwrap_or_else(|| PathBuf::from("."));
+    args.output.write(&stack_graph, &root)?;
+
+    Ok(report.as_ref().map(report_exit_code).unwrap_or(ExitCode::SUCCESS))
+}
+
+fn watch(args: WatchArgs) -> Result<ExitCode> {
+    let output = OutputArgs {
+        format: args.format,
+        output: args.output.clone(),
+        dot_file: None,
+        dot_symbol: None,
+        dot_depth: DotFilter::default().depth,
+    };
+    output.check_format()?;
+
+    let debounce = Duration::from_millis(args.debounce);
+    let mut watcher = IndexWatcher::new(&args.path, args.filter.discovery()?, debounce)?;
+    // Writing the graph or the database must not trigger another update
+    for path in args.output.iter().chain(&args.db) {
+        watcher.ignore(path)?;
+    }
+    eprintln!("Watching {}", watcher.root().display());
+    let root = watcher.root().to_path_buf();
+
+    // Failing to write the graph is reported without stopping the watch
+    let report_error = |result: Result<()>| {
+        if let Err(e) = result {
+            eprintln!("error: {:#}", e);
+        }
+    };
+
+    match &args.db {
+        Some(db) => {
+            let mut database = IndexDatabase::open(db)?;
+            watcher.watch_database(&mut database, args.threads, |database, events| {
+                print_events(events, args.json);
+                if output.output.is_some() {
+                    report_error(database.load_stack_graph().and_then(|stack_graph| output.write(&stack_graph, &root)));
+                }
+                ControlFlow::Continue(())
+            })?;
+        }
+        None => {
+            watcher.watch_graph(args.threads, |stack_graph, events| {
+                print_events(events, args.json);
+                if output.output.is_some() {
+                    report_error(output.write(stack_graph, &root));
+                }
+                ControlFlow::Continue(())
+            })?;
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Print one line per watch event; unchanged files are only listed in JSON
+fn print_events(events: &[WatchEvent], json: bool) {
+    for event in events {
+        if json {
+            if let Ok(line) = serde_json::to_string(event) {
+                println!("{}", line);
+            }
+            continue;
+        }
+
+        match event {
+            WatchEvent::Indexed { file, parse_errors: 0 } => println!("indexed {}", file),
+            WatchEvent::Indexed { file, parse_errors } => println!("indexed {} ({} parse error(s))", file, parse_errors),
+            WatchEvent::Unchanged { .. } => {}
+            WatchEvent::Skipped { file, reason } => println!("skipped {}: {}", file, reason),
+            WatchEvent::Failed { file, error } => println!("failed {}: {}", file, error),
+            WatchEvent::Removed { file } => println!("removed {}", file),
+        }
+    }
+}
+
+/// Index `path` if given, through `db` if that is given too, or else read the graph
+/// stored in `db`
+fn load_graph(path: Option<&Path>, db: Option<&Path>, walk: &WalkArgs) -> Result<(StackGraph, Option<IndexReport>)> {
+    match (path, db) {
+        (Some(path), db) => {
+            let config = IndexerConfig {
+                path: path.to_path_buf(),
+                root: walk.root.clone(),
+                fail_fast: walk.fail_fast,
+                db: db.map(Path::to_path_buf),
+                threads: walk.threads,
+                include: walk.filter.include.clone(),
+                exclude: walk.filter.exclude(),
+                ..IndexerConfig::default()
+            };
+
+            let (stack_graph, report) = build_stack_graph(&config)?;
+            Ok((stack_graph, Some(report)))
+        }
+        (None, Some(db)) => {
+            if !db.exists() {
+                anyhow::bail!("Index database does not exist: {}", db.display());
+            }
+            Ok((IndexDatabase::open(db)?.load_stack_graph()?, None))
+        }
+        (None, None) => anyhow::bail!("Either a path to index or --db is required"),
+    }
+}
+
+/// Parse a `FILE:LINE:COLUMN` location with 1-based line and column into a path and
+/// zero-based position
+fn parse_location(location: &str) -> Result<(PathBuf, usize, usize)> {
+    let invalid = || anyhow::anyhow!("Invalid location `{}`, expected FILE:LINE:COLUMN", location);
+
+    let mut parts = location.rsplitn(3, ':');
+    let column: usize = parts.next().and_then(|c| c.parse().ok()).ok_or_else(invalid)?;
+    let line: usize = parts.next().and_then(|l| l.parse().ok()).ok_or_else(invalid)?;
+    let path = parts.next().filter(|p| !p.is_empty()).ok_or_else(invalid)?;
+
+    if line == 0 || column == 0 {
+        return Err(invalid());
+    }
+
+    Ok((PathBuf::from(path), line - 1, column - 1))
+}
+
+/// Exit code for a finished indexing run
+fn report_exit_code(report: &IndexReport) -> ExitCode {
+    if report.has_failures() {
+        ExitCode::from(EXIT_INDEX_FAILURES)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_is_one_based() {
+        let (path, line, column) = parse_location("src/app.js:3:7").unwrap();
+        assert_eq!((path, line, column), (PathBuf::from("src/app.js"), 2, 6));
+    }
+
+    #[test]
+    fn location_path_may_contain_colons() {
+        let (path, line, column) = parse_location("C:\\src\\app.js:1:1").unwrap();
+        assert_eq!((path, line, column), (PathBuf::from("C:\\src\\app.js"), 0, 0));
+    }
+
+    #[test]
+    fn invalid_locations_are_errors() {
+        for location in ["app.js", "app.js:3", ":3:7", "app.js:0:1", "app.js:1:0", "app.js:x:1", "app.js:1:-2"] {
+            let error = parse_location(location).unwrap_err();
+            assert!(error.to_string().contains("expected FILE:LINE:COLUMN"), "{}", location);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::process::ExitCode;
+
+/// Language server for the TSG indexer, speaking LSP over stdin and stdout
+fn main() -> ExitCode {
+    // Logs go to stderr, since stdout carries the protocol
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    match tsg_indexer::lsp::run_stdio_server() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
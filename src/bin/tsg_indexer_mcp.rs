@@ -0,0 +1,50 @@
+use std::process::ExitCode;
+use tsg_indexer::mcp::ServerOptions;
+
+const USAGE: &str = "\
+MCP server for the TSG indexer, speaking JSON-RPC over stdin and stdout
+
+Usage: tsg_indexer_mcp [ROOT] [--db FILE]
+
+Arguments:
+  [ROOT]     Repository to index on the first query, unless index_repository is called
+
+Options:
+  --db FILE  Index database to keep graphs in
+  -h, --help Print help";
+
+fn main() -> ExitCode {
+    // Logs go to stderr, since stdout carries the protocol
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let mut options = ServerOptions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            "--db" => match args.next() {
+                Some(db) => options.db = Some(db.into()),
+                None => return usage_error("--db requires a file"),
+            },
+            _ if arg.starts_with('-') => return usage_error(&format!("unexpected option '{}'", arg)),
+            _ if options.root.is_none() => options.root = Some(arg.into()),
+            _ => return usage_error(&format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    match tsg_indexer::mcp::run_stdio_server(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    ExitCode::from(2)
+}
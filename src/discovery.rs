@@ -0,0 +1,300 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use log::{debug, warn};
+
+use crate::languages::Language;
+
+/// Project-level ignore file, read alongside `.gitignore` and `.ignore`
+pub const TSG_IGNORE_FILE: &str = ".tsgignore";
+
+/// Dependency, build output and virtual environment directories excluded by default
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "node_modules", "target", "build", "__pycache__", "dist", "venv", "bin", "obj",
+];
+
+/// Default exclude globs as owned strings, for use in configuration
+pub fn default_excludes() -> Vec<String> {
+    DEFAULT_EXCLUDES.iter().map(|glob| glob.to_string()).collect()
+}
+
+/// Finds the files to index under a directory
+///
+/// Honors `.gitignore`, `.ignore` and `.tsgignore` files, skips hidden files and
+/// directories, and applies include and exclude globs. A glob without a `/` matches a
+/// file or directory name at any depth; any other glob matches the path relative to
+/// the directory being walked. Excluded directories are not descended into, and when
+/// include globs are given, only files matching one of them are returned. Source files
+/// can further be limited to a set of languages.
+#[derive(Debug, Clone)]
+pub struct FileDiscovery {
+    include: Globs,
+    exclude: Globs,
+    languages: Vec<Language>,
+}
+
+impl FileDiscovery {
+    /// Create a file discovery with the given include and exclude globs
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: Globs::new(include).context("Invalid include glob")?,
+            exclude: Globs::new(exclude).context("Invalid exclude glob")?,
+            languages: Vec::new(),
+        })
+    }
+
+    /// Only find source files in these languages; an empty list allows every supported language
+    pub fn with_languages(mut self, languages: &[Language]) -> Self {
+        self.languages = languages.to_vec();
+        self
+    }
+
+    /// Whether files in a language are indexed
+    fn allows(&self, language: Language) -> bool {
+        language != Language::Unknown && (self.languages.is_empty() || self.languages.contains(&language))
+    }
+
+    /// Recursively find all files under a directory that are not ignored or excluded
+    pub fn find_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        self.walk(dir, &[])
+    }
+
+    /// Find the source files under a directory like [`FileDiscovery::find_source_files`],
+    /// but only among `paths` and the files below them
+    ///
+    /// Only the directories leading to `paths` are read, so this is cheap for a few
+    /// paths deep in a large tree. Paths that no longer exist are allowed.
+    pub fn find_source_files_among(&self, dir: &Path, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let mut files = self.walk(dir, paths)?;
+        files.retain(|path| self.is_source_file(path));
+        Ok(files)
+    }
+
+    /// Walk a directory, only descending towards `scope` unless it is empty
+    fn walk(&self, dir: &Path, scope: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        if !dir.is_dir() {
+            anyhow::bail!("Failed to read directory: {}", dir.display());
+        }
+
+        // The filter runs on the walker, so it needs its own copy of the excludes
+        let exclude = self.exclude.clone();
+        let walk_root = dir.to_path_buf();
+        let scope = scope.to_vec();
+
+        let walker = WalkBuilder::new(dir)
+            .hidden(true)
+            .git_ignore(true)
+            .git_exclude(true)
+            .ignore(true)
+            .require_git(false)
+            .add_custom_ignore_filename(TSG_IGNORE_FILE)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| {
+                let relative = entry.path().strip_prefix(&walk_root).unwrap_or(entry.path());
+                let in_scope = scope.is_empty()
+                    || scope.iter().any(|path| path.starts_with(entry.path()) || entry.path().starts_with(path));
+                entry.depth() == 0 || (in_scope && !exclude.matches(relative))
+            })
+            .build();
+
+        let mut files = Vec::new();
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Skipping unreadable path under {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                continue;
+            }
+
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            if !self.include.is_empty() && !self.include.matches(relative) {
+                continue;
+            }
+
+            files.push(entry.into_path());
+        }
+
+        debug!("Found {} file(s) under {}", files.len(), dir.display());
+        Ok(files)
+    }
+
+    /// Recursively find the files under a directory that are in a supported language
+    pub fn find_source_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = self.find_files(dir)?;
+        files.retain(|path| self.is_source_file(path));
+        Ok(files)
+    }
+
+    /// Whether a file is in a language that is indexed, judging by its extension
+    fn is_source_file(&self, path: &Path) -> bool {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        self.allows(Language::from_extension(extension))
+    }
+
+    /// Languages of the files at a path, which may be a single file or a directory
+    pub fn detect_languages(&self, path: &Path) -> Result<Vec<Language>> {
+        let files = if path.is_file() {
+            vec![path.to_path_buf()]
+        } else {
+            self.find_files(path)?
+        };
+
+        let mut detected = Vec::new();
+        for file in &files {
+            let extension = file.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+            let language = Language::from_extension(extension);
+            if self.allows(language) && !detected.contains(&language) {
+                detected.push(language);
+            }
+        }
+
+        Ok(detected)
+    }
+}
+
+impl Default for FileDiscovery {
+    /// Honor ignore files and exclude [`DEFAULT_EXCLUDES`], with no include globs
+    fn default() -> Self {
+        Self::new(&[], &default_excludes()).expect("default exclude globs are valid")
+    }
+}
+
+/// A list of globs, split by whether they match a single name or a relative path
+#[derive(Debug, Clone, Default)]
+struct Globs {
+    names: GlobSet,
+    paths: GlobSet,
+    len: usize,
+}
+
+impl Globs {
+    fn new(globs: &[String]) -> Result<Self> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for glob in globs {
+            // Trailing slashes are accepted for directories, as in ignore files
+            let pattern = glob.trim_end_matches('/');
+            let compiled = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()?;
+
+            if pattern.contains('/') {
+                paths.add(compiled);
+            } else {
+                names.add(compiled);
+            }
+        }
+
+        Ok(Self {
+            names: names.build()?,
+            paths: paths.build()?,
+            len: globs.len(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        relative.file_name().is_some_and(|name| self.names.is_match(name))
+            || self.paths.is_match(relative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::test_support::project;
+
+    fn found(discovery: &FileDiscovery, dir: &Path) -> Vec<String> {
+        let files = discovery.find_files(dir).unwrap();
+        files.iter()
+            .map(|path| path.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect()
+    }
+
+    fn globs(globs: &[&str]) -> Vec<String> {
+        globs.iter().map(|glob| glob.to_string()).collect()
+    }
+
+    #[test]
+    fn name_glob_excludes_at_any_depth() {
+        let dir = project(&[("src/gen/a.py", ""), ("src/b.py", ""), ("gen/c.py", "")]);
+        let discovery = FileDiscovery::new(&[], &globs(&["gen"])).unwrap();
+        assert_eq!(found(&discovery, dir.path()), ["src/b.py"]);
+
+        // A trailing slash alone does not make it a path glob
+        let discovery = FileDiscovery::new(&[], &globs(&["gen/"])).unwrap();
+        assert_eq!(found(&discovery, dir.path()), ["src/b.py"]);
+    }
+
+    #[test]
+    fn path_glob_excludes_relative_to_root() {
+        let dir = project(&[("src/gen/a.py", ""), ("src/b.py", ""), ("gen/c.py", "")]);
+        let discovery = FileDiscovery::new(&[], &globs(&["src/gen"])).unwrap();
+        assert_eq!(found(&discovery, dir.path()), ["gen/c.py", "src/b.py"]);
+    }
+
+    #[test]
+    fn name_glob_includes_at_any_depth() {
+        let dir = project(&[("a.py", ""), ("lib/b.py", ""), ("lib/c.js", "")]);
+        let discovery = FileDiscovery::new(&globs(&["*.py"]), &[]).unwrap();
+        assert_eq!(found(&discovery, dir.path()), ["a.py", "lib/b.py"]);
+    }
+
+    #[test]
+    fn path_glob_includes_relative_to_root() {
+        let dir = project(&[("a.py", ""), ("lib/b.py", ""), ("lib/deep/c.py", "")]);
+        let discovery = FileDiscovery::new(&globs(&["lib/*.py"]), &[]).unwrap();
+        // `*` does not cross a `/`
+        assert_eq!(found(&discovery, dir.path()), ["lib/b.py"]);
+
+        let discovery = FileDiscovery::new(&globs(&["lib/**/*.py"]), &[]).unwrap();
+        assert_eq!(found(&discovery, dir.path()), ["lib/b.py", "lib/deep/c.py"]);
+    }
+
+    #[test]
+    fn honors_tsgignore() {
+        let dir = project(&[("a.py", ""), ("fixtures/b.py", ""), ("c.generated.py", "")]);
+        fs::write(dir.path().join(TSG_IGNORE_FILE), "fixtures/\n*.generated.py\n").unwrap();
+        assert_eq!(found(&FileDiscovery::default(), dir.path()), ["a.py"]);
+    }
+
+    #[test]
+    fn default_excludes_dependencies_and_hidden_files() {
+        let dir = project(&[("a.py", ""), ("node_modules/b.js", ""), (".hidden/c.py", ""), ("target/d.rs", "")]);
+        assert_eq!(found(&FileDiscovery::default(), dir.path()), ["a.py"]);
+    }
+
+    #[test]
+    fn invalid_glob_is_an_error() {
+        assert!(FileDiscovery::new(&globs(&["a["]), &[]).is_err());
+        assert!(FileDiscovery::new(&[], &globs(&["a["])).is_err());
+    }
+
+    #[test]
+    fn source_files_are_limited_to_languages() {
+        let dir = project(&[("a.py", ""), ("b.js", ""), ("notes.txt", "")]);
+        let discovery = FileDiscovery::default().with_languages(&[Language::Python]);
+        let files = discovery.find_source_files(dir.path()).unwrap();
+        assert_eq!(files, [dir.path().join("a.py")]);
+    }
+
+    #[test]
+    fn source_files_among_only_walks_paths() {
+        let dir = project(&[("a/x.py", ""), ("a/y.py", ""), ("b/z.py", "")]);
+        let discovery = FileDiscovery::default();
+        let paths = [dir.path().join("a/y.py"), dir.path().join("b"), dir.path().join("gone.py")];
+        let files = discovery.find_source_files_among(dir.path(), &paths).unwrap();
+        assert_eq!(files, [dir.path().join("a/y.py"), dir.path().join("b/z.py")]);
+    }
+}
@@ -1,99 +1,404 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use anyhow::{Context, Result};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use anyhow::{anyhow, Context, Result};
 use log::{debug, warn};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use lsp_positions::SpanCalculator;
 use tree_sitter as ts;
-use stack_graphs::graph::{StackGraph, File};
+use stack_graphs::graph::{StackGraph, File, Node, NodeID};
 use stack_graphs::arena::Handle;
 use streaming_iterator::StreamingIterator;
+use tree_sitter_stack_graphs::{NoCancellation, Variables};
 
+use crate::discovery::FileDiscovery;
 use crate::languages::Language;
+use crate::report::{FileOutcome, IndexReport};
+use crate::symbols;
+use crate::tsg_loader::{self, TsgLanguage};
 
 /// Index a single file and add its contents to the stack graph database
-pub fn index_file(stack_graph: &mut StackGraph, path: &Path) -> Result<()> {
-    debug!("Indexing file: {}", path.display());
+///
+/// The file is registered in the graph under its path relative to `root`.
+pub fn index_file(stack_graph: &mut StackGraph, root: &Path, path: &Path) -> Result<FileOutcome> {
+    let (language, outcome) = build_file(stack_graph, root, path)?;
+    if matches!(outcome, FileOutcome::Indexed { .. }) {
+        add_builtins(stack_graph, &language);
+    }
+
+    Ok(outcome)
+}
+
+/// A file's own stack graph, with its language and how indexing it went
+pub type IsolatedFile = (StackGraph, Language, FileOutcome);
+
+/// Index a single file into its own stack graph, for merging into a larger graph later
+///
+/// The graph holds only the file itself; the language's builtins are left out so that
+/// they are not duplicated across graphs and can be added once when merging.
+pub fn index_file_isolated(root: &Path, path: &Path) -> Result<IsolatedFile> {
+    let mut stack_graph = StackGraph::new();
+    let (language, outcome) = build_file(&mut stack_graph, root, path)?;
+    Ok((stack_graph, language, outcome))
+}
+
+/// Like [`index_file_isolated`], but indexes `content` instead of reading the file, e.g.
+/// for an editor buffer with unsaved changes
+pub fn index_source_isolated(root: &Path, path: &Path, content: &str) -> Result<IsolatedFile> {
+    let mut stack_graph = StackGraph::new();
+    let (language, outcome) = build_source(&mut stack_graph, root, path, content)?;
+    Ok((stack_graph, language, outcome))
+}
+
+/// Parse a file and add its nodes to the stack graph, without the language's builtins
+fn build_file(stack_graph: &mut StackGraph, root: &Path, path: &Path) -> Result<(Language, FileOutcome)> {
+    // Skip unknown languages before reading the file
+    if language_for(path) == Language::Unknown {
+        return build_source(stack_graph, root, path, "");
+    }
 
-    // Get file extension
+    // Read file content
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+    build_source(stack_graph, root, path, &content)
+}
+
+/// Language of a file, determined by its extension
+fn language_for(path: &Path) -> Language {
     let extension = path.extension()
         .and_then(|ext| ext.to_str())
         .unwrap_or("");
 
+    Language::from_extension(extension)
+}
+
+/// Parse the content of a file and add its nodes to the stack graph, without the
+/// language's builtins
+fn build_source(stack_graph: &mut StackGraph, root: &Path, path: &Path, content: &str) -> Result<(Language, FileOutcome)> {
+    debug!("Indexing file: {}", path.display());
+
     // Determine language
-    let language = Language::from_extension(extension);
+    let language = language_for(path);
 
     // Skip unknown languages
     if matches!(language, Language::Unknown) {
         warn!("Skipping file with unsupported extension: {}", path.display());
-        return Ok(());
+        return Ok((language, FileOutcome::Skipped("unsupported extension".to_string())));
     }
 
-    // Read file content
-    let content = fs::read_to_string(path)
-        .with_context(|| format!("Failed to read file: {}", path.display()))?;
-
     // Get language parser
     let mut parser = match language.get_parser() {
         Some(parser) => parser,
         None => {
             warn!("No parser available for language: {}", language.name());
-            return Ok(());
+            return Ok((language, FileOutcome::Skipped(format!("no parser available for {}", language.name()))));
         }
     };
 
     // Parse the file
-    let tree = parser.parse(&content, None)
+    let tree = parser.parse(content, None)
         .with_context(|| format!("Failed to parse file: {}", path.display()))?;
 
     // Process the syntax tree with tree-sitter-stack-graphs
-    let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let file_name = file_name_for(root, path);
 
     // Use files API to add to the database
-    process_syntax_tree(stack_graph, &language, &file_name, &tree, &content)?;
+    build_syntax_tree(stack_graph, &language, &file_name, &tree, content)?;
+
+    // Tree-sitter recovers from syntax errors, so the file is still indexed
+    let parse_errors = if tree.root_node().has_error() {
+        let count = count_parse_errors(&tree);
+        warn!("{} syntax error(s) in {}", count, path.display());
+        count
+    } else {
+        0
+    };
 
     debug!("Successfully indexed file: {}", path.display());
-    Ok(())
+    Ok((language, FileOutcome::Indexed { parse_errors }))
+}
+
+/// Count the error and missing nodes in a syntax tree
+fn count_parse_errors(tree: &ts::Tree) -> usize {
+    let mut count = 0;
+    let mut cursor = tree.walk();
+
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            count += 1;
+        }
+
+        // Only descend into subtrees that contain errors
+        if node.has_error() && !node.is_error() && cursor.goto_first_child() {
+            continue;
+        }
+
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return count;
+            }
+        }
+    }
+}
+
+/// Name under which a file is registered in the stack graph: its path relative to
+/// the project root, with `/` as separator on every platform
+///
+/// Paths outside the root keep their full path.
+pub fn file_name_for(root: &Path, path: &Path) -> String {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => match (root.canonicalize(), path.canonicalize()) {
+            (Ok(root), Ok(path)) => path.strip_prefix(&root)
+                .map(Path::to_path_buf)
+                .unwrap_or(path),
+            _ => path.to_path_buf(),
+        },
+    };
+
+    // A file indexed as its own root is named after itself
+    if relative.as_os_str().is_empty() {
+        return path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    }
+
+    relative.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Default project root for a path: the path itself for directories, its parent for files
+pub fn default_root(path: &Path) -> PathBuf {
+    if path.is_file() {
+        // A bare file name has an empty parent, which stands for the current directory
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    } else {
+        path.to_path_buf()
+    }
 }
 
 /// Recursively index a directory and add its contents to the stack graph
-pub fn index_directory(stack_graph: &mut StackGraph, dir: &Path) -> Result<()> {
+///
+/// Files are found with `discovery` and registered under their paths relative to
+/// `root`, which is usually the directory itself or one of its ancestors. Files that
+/// fail to index are recorded in the report and skipped, unless `fail_fast` is set, in
+/// which case the first failure aborts the run.
+pub fn index_directory(
+    stack_graph: &mut StackGraph,
+    root: &Path,
+    dir: &Path,
+    discovery: &FileDiscovery,
+    fail_fast: bool,
+) -> Result<IndexReport> {
     debug!("Indexing directory: {}", dir.display());
 
-    for entry in fs::read_dir(dir)
-        .with_context(|| format!("Failed to read directory: {}", dir.display()))? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_file() {
-            // Skip files we don't want to process
-            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                // Expanded list of supported extensions
-                let supported_extensions = [
-                    "rs", "py", "js", "jsx", "ts", "tsx", "java", "swift",
-                    "m", "mm", "css", "scala", "zig", "yaml", "yml",
-                    "go", "php", "xml", "sh", "bash", "json", "html",
-                    "htm", "cs", "rb", "md", "markdown", "lua", "dart"
-                ];
-
-                if supported_extensions.contains(&ext.to_lowercase().as_str()) {
-                    index_file(stack_graph, &path)?;
+    let mut report = IndexReport::default();
+    for path in discovery.find_source_files(dir)? {
+        let result = index_file(stack_graph, root, &path);
+        if fail_fast {
+            let outcome = result.with_context(|| format!("Failed to index file: {}", path.display()))?;
+            report.record(file_name_for(root, &path), Ok(outcome));
+        } else {
+            report.record(file_name_for(root, &path), result);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Recursively index a directory on a pool of worker threads
+///
+/// Each file is parsed into its own stack graph on a worker, and the graphs are merged
+/// into `stack_graph` in path order, so the result is the same as with
+/// [`index_directory`]. A `threads` count of 0 uses one thread per CPU.
+pub fn index_directory_parallel(
+    stack_graph: &mut StackGraph,
+    root: &Path,
+    dir: &Path,
+    discovery: &FileDiscovery,
+    fail_fast: bool,
+    threads: usize,
+) -> Result<IndexReport> {
+    index_directory_parallel_with(stack_graph, root, dir, discovery, fail_fast, threads, |_, _| Ok(()))
+}
+
+/// [`index_directory_parallel`], calling `on_file` with each file's own graph as soon as
+/// it has been built, before it is merged into the stack graph
+///
+/// Files are merged in path order while the workers go on parsing later ones, so only
+/// the graphs of a few files that wait on an earlier one are held apart at any time. An
+/// error from `on_file` aborts the run.
+pub fn index_directory_parallel_with<F>(
+    stack_graph: &mut StackGraph,
+    root: &Path,
+    dir: &Path,
+    discovery: &FileDiscovery,
+    fail_fast: bool,
+    threads: usize,
+    mut on_file: F,
+) -> Result<IndexReport>
+where
+    F: FnMut(&StackGraph, Handle<File>) -> Result<()>,
+{
+    debug!("Indexing directory in parallel: {}", dir.display());
+
+    let files = discovery.find_source_files(dir)?;
+    let pool = thread_pool(threads)?;
+    let mut report = IndexReport::default();
+
+    build_in_order(&pool, files.len(), |index| index_file_isolated(root, &files[index]), |index, result| {
+        let path = &files[index];
+        let file_name = file_name_for(root, path);
+        let result = match result {
+            Ok((file_graph, language, outcome)) => {
+                if let Some(file) = file_graph.get_file(&file_name) {
+                    on_file(&file_graph, file)?;
                 }
+                merge_graph(stack_graph, &file_graph, &language).map(|()| outcome)
             }
-        } else if path.is_dir() {
-            // Skip hidden directories and typical build directories
-            if let Some(dir_name) = path.file_name() {
-                let dir_name = dir_name.to_string_lossy();
-                if !dir_name.starts_with(".") &&
-                   !["node_modules", "target", "build", "__pycache__", "dist", "venv", ".git", ".svn", ".hg", "bin", "obj"].contains(&dir_name.as_ref()) {
-                    index_directory(stack_graph, &path)?;
+            Err(e) => Err(e),
+        };
+
+        if fail_fast {
+            let outcome = result.with_context(|| format!("Failed to index file: {}", path.display()))?;
+            report.record(file_name, Ok(outcome));
+        } else {
+            report.record(file_name, result);
+        }
+        Ok(())
+    })?;
+
+    Ok(report)
+}
+
+/// Number of files each worker may build ahead of the next one to be consumed
+const BUILD_AHEAD_PER_THREAD: usize = 2;
+
+/// Build the items `0..count` on the pool's workers and pass each result to `consume`
+/// in index order, on the calling thread
+///
+/// Workers take items in index order and stay within a window of
+/// [`BUILD_AHEAD_PER_THREAD`] items per thread past the next one to be consumed, so a
+/// slow item holds up at most that many built results. An error from `consume` stops
+/// the workers and is returned.
+pub(crate) fn build_in_order<T, B, C>(pool: &ThreadPool, count: usize, build: B, mut consume: C) -> Result<()>
+where
+    T: Send,
+    B: Fn(usize) -> T + Sync,
+    C: FnMut(usize, T) -> Result<()>,
+{
+    let threads = pool.current_num_threads();
+    let window = threads * BUILD_AHEAD_PER_THREAD;
+    let (sender, receiver) = mpsc::sync_channel::<(usize, T)>(window);
+    let claimed = AtomicUsize::new(0);
+    // Index of the next item to be consumed, or `usize::MAX` once the run has stopped
+    let consumed = Mutex::new(0usize);
+    let progress = Condvar::new();
+
+    pool.in_place_scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (build, claimed, consumed, progress) = (&build, &claimed, &consumed, &progress);
+            scope.spawn(move |_| loop {
+                let index = claimed.fetch_add(1, Ordering::Relaxed);
+                if index >= count {
+                    break;
+                }
+
+                let next = *progress.wait_while(consumed.lock().unwrap(), |next| index >= next.saturating_add(window))
+                    .unwrap();
+                if next == usize::MAX || sender.send((index, build(index))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut run = || -> Result<()> {
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (index, result) in receiver.iter() {
+                pending.insert(index, result);
+                while let Some(result) = pending.remove(&next) {
+                    consume(next, result)?;
+                    next += 1;
+                    *consumed.lock().unwrap() = next;
+                    progress.notify_all();
                 }
             }
+            Ok(())
+        };
+
+        let result = run();
+        // Release waiting workers and make any further sends fail, so the scope can end
+        *consumed.lock().unwrap() = usize::MAX;
+        progress.notify_all();
+        drop(receiver);
+        result
+    })
+}
+
+/// Add a graph built by [`index_file_isolated`] to the stack graph, along with the
+/// builtins of its language
+pub fn merge_graph(stack_graph: &mut StackGraph, file_graph: &StackGraph, language: &Language) -> Result<()> {
+    if file_graph.iter_files().next().is_none() {
+        return Ok(());
+    }
+
+    stack_graph.add_from_graph(file_graph)
+        .map_err(|file| anyhow!("File already present in stack graph: {}", stack_graph[file]))?;
+
+    // Copying a graph leaves out fully qualified names, so carry them over by node id
+    for file in file_graph.iter_files() {
+        let Some(merged_file) = stack_graph.get_file(file_graph[file].name()) else {
+            continue;
+        };
+        for node in file_graph.nodes_for_file(file) {
+            let Some(name) = file_graph.source_info(node)
+                .and_then(|info| info.fully_qualified_name.into_option()) else {
+                continue;
+            };
+            let id = NodeID::new_in_file(merged_file, file_graph[node].id().local_id());
+            if let Some(merged) = stack_graph.node_for_id(id) {
+                let name = stack_graph.add_string(&file_graph[name]);
+                stack_graph.source_info_mut(merged).fully_qualified_name = name.into();
+            }
         }
     }
 
+    add_builtins(stack_graph, language);
+
     Ok(())
 }
 
+/// Create a pool of worker threads for indexing; 0 threads means one per CPU
+pub(crate) fn thread_pool(threads: usize) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("tsg-indexer-{}", index))
+        .build()
+        .context("Failed to create indexing thread pool")
+}
+
+/// Recursively collect the files under a directory that have a supported extension,
+/// honoring ignore files and the default excludes
+pub fn find_source_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    FileDiscovery::default().find_source_files(dir)
+}
+
 /// Process a syntax tree and add nodes to the stack graph
+///
+/// Uses the language's bundled TSG rules when they load, and falls back to the
+/// query-based processors otherwise.
 pub fn process_syntax_tree(
     stack_graph: &mut StackGraph,
     language: &Language,
@@ -101,82 +406,225 @@ pub fn process_syntax_tree(
     tree: &ts::Tree,
     source: &str,
 ) -> Result<()> {
-    debug!("Processing syntax tree for file: {}", file_name);
+    build_syntax_tree(stack_graph, language, file_name, tree, source)?;
+    add_builtins(stack_graph, language);
+    Ok(())
+}
 
-    // Check if we have a built-in stack graphs language
-    if let Some(stack_graphs_lang) = language.get_stack_graphs_language() {
-        // Use the built-in stack graphs language support when available
-        debug!("Using built-in stack graphs language: {}", stack_graphs_lang);
+/// Copy the builtins of a language with TSG rules into the stack graph
+fn add_builtins(stack_graph: &mut StackGraph, language: &Language) {
+    if let Some(tsg_language) = tsg_loader::get_tsg_language(language) {
+        tsg_language.add_builtins_to(stack_graph);
+    }
+}
 
-        // Create a new file in the database with the specified name
-        let file_handle = stack_graph.get_or_create_file(file_name);
+/// Add the nodes for a syntax tree to the stack graph, without the language's builtins
+fn build_syntax_tree(
+    stack_graph: &mut StackGraph,
+    language: &Language,
+    file_name: &str,
+    tree: &ts::Tree,
+    source: &str,
+) -> Result<()> {
+    debug!("Processing syntax tree for file: {}", file_name);
 
-        match stack_graphs_lang {
-            "javascript" => {
-                process_javascript_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            "python" => {
-                process_python_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            "typescript" => {
-                process_typescript_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            "java" => {
-                process_java_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            _ => {
-                // Fallback for unknown languages
-                process_generic_syntax(stack_graph, file_handle, source, language)?;
+    // Create a file for the stack graph
+    let file_handle = stack_graph.get_or_create_file(file_name);
+
+    let built_with_tsg = match tsg_loader::get_tsg_language(language) {
+        Some(tsg_language) => {
+            debug!("Using TSG rules for {}", language.name());
+            match build_with_tsg(stack_graph, &tsg_language, file_handle, source) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("TSG build failed for {}, falling back to query-based indexing: {}", file_name, e);
+                    false
+                }
             }
         }
+        None => false,
+    };
+
+    if built_with_tsg {
+        annotate_declarations(stack_graph, language, file_handle, tree, source);
     } else {
-        // Create a file for the stack graph
-        let file_handle = stack_graph.get_or_create_file(file_name);
+        process_fallback_syntax(stack_graph, language, file_handle, tree, source)?;
+    }
 
-        // Process based on language
-        match language {
-            Language::Rust => {
-                process_rust_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            Language::Go => {
-                process_go_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            Language::Ruby => {
-                process_ruby_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
-            }
-            _ => {
-                // Generic processing for other languages
-                process_generic_syntax(stack_graph, file_handle, source, language)?;
-            }
+    // Neither TSG rules nor the fallback processors name definitions by their containers
+    symbols::qualify_definitions(stack_graph, file_handle);
+    Ok(())
+}
+
+/// Give definitions made by TSG rules the syntax type and definiens of the declaration
+/// they name, where the rules leave them out
+///
+/// Java's rules record neither, and JavaScript's give a class its constructor as its
+/// definiens, so without this their members would not nest inside their classes.
+fn annotate_declarations(
+    stack_graph: &mut StackGraph,
+    language: &Language,
+    file_handle: Handle<File>,
+    tree: &ts::Tree,
+    source: &str,
+) {
+    let mut spans = SpanCalculator::new(source);
+    let nodes: Vec<Handle<Node>> = stack_graph.nodes_for_file(file_handle)
+        .filter(|&node| stack_graph[node].is_definition())
+        .collect();
+
+    for node in nodes {
+        let Some(source_info) = stack_graph.source_info(node) else {
+            continue;
+        };
+        let start = source_info.span.start.containing_line.start + source_info.span.start.column.utf8_offset;
+        let end = source_info.span.end.containing_line.start + source_info.span.end.column.utf8_offset;
+        let Some(name) = tree.root_node().descendant_for_byte_range(start, end)
+            .filter(|name| name.byte_range() == (start..end) && start < end) else {
+            continue;
+        };
+        let Some((syntax_type, declaration)) = declaration_of(language, &name) else {
+            continue;
+        };
+
+        let has_syntax_type = source_info.syntax_type.into_option().is_some();
+        let syntax_type = stack_graph.add_string(syntax_type);
+        let definiens_span = spans.for_node(&declaration);
+        let source_info = stack_graph.source_info_mut(node);
+        if !has_syntax_type {
+            source_info.syntax_type = syntax_type.into();
         }
+        source_info.definiens_span = definiens_span;
     }
+}
+
+/// Syntax type and syntax node of the declaration that a definition's name belongs to,
+/// if it is a class, function, method or field, or a variable at the top of a file
+fn declaration_of<'tree>(language: &Language, name: &ts::Node<'tree>) -> Option<(&'static str, ts::Node<'tree>)> {
+    let parent = name.parent()?;
+    let names = |field: &str| parent.child_by_field_name(field).is_some_and(|child| child == *name);
+    let top_level = |node: ts::Node| node.parent()
+        .is_some_and(|parent| matches!(parent.kind(), "program" | "module" | "export_statement"));
+
+    let syntax_type = match (language, parent.kind()) {
+        (Language::Java, "class_declaration" | "record_declaration") => "class",
+        (Language::Java, "interface_declaration" | "annotation_type_declaration") => "interface",
+        (Language::Java, "enum_declaration") => "enum",
+        (Language::Java, "enum_constant") => "constant",
+        (Language::Java, "method_declaration") => "method",
+        (Language::Java, "constructor_declaration") => "constructor",
+        (Language::Java, "variable_declarator") if names("name") => {
+            // Fields are declared with their type, so the whole declaration is the definiens
+            let declaration = parent.parent()?;
+            return match declaration.kind() {
+                "field_declaration" => Some(("field", declaration)),
+                "constant_declaration" => Some(("constant", declaration)),
+                _ => None,
+            };
+        }
+        (Language::JavaScript, "class_declaration" | "class") => "class",
+        (Language::JavaScript, "method_definition") => "method",
+        (Language::JavaScript, "function_declaration" | "generator_function_declaration") => "function",
+        (Language::JavaScript, "variable_declarator") if names("name") => {
+            let declaration = parent.parent()?;
+            return top_level(declaration).then_some(("variable", declaration));
+        }
+        (Language::Python, "class_definition") => "class",
+        (Language::Python, "function_definition") => "function",
+        (Language::Python, "assignment") if names("left") => {
+            let statement = parent.parent()?;
+            return top_level(statement).then_some(("variable", statement));
+        }
+        _ => return None,
+    };
+
+    names("name").then_some((syntax_type, parent))
+}
+
+/// Add the nodes for a syntax tree using the query-based processor for its language
+fn process_fallback_syntax(
+    stack_graph: &mut StackGraph,
+    language: &Language,
+    file_handle: Handle<File>,
+    tree: &ts::Tree,
+    source: &str,
+) -> Result<()> {
+    match language {
+        Language::Rust => {
+            process_rust_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        Language::JavaScript => {
+            process_javascript_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        Language::TypeScript => {
+            process_typescript_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        Language::Python => {
+            process_python_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        Language::Java => {
+            process_java_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        Language::Go => {
+            process_go_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        Language::Ruby => {
+            process_ruby_syntax(stack_graph, file_handle, &tree.root_node(), source)?;
+        }
+        _ => {
+            // Generic processing for other languages
+            process_generic_syntax(stack_graph, file_handle, &tree.root_node(), source, language)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the stack graph for a file by executing the language's TSG rules
+fn build_with_tsg(
+    stack_graph: &mut StackGraph,
+    tsg_language: &TsgLanguage,
+    file_handle: Handle<File>,
+    source: &str,
+) -> Result<()> {
+    let globals = Variables::new();
+    tsg_language.sgl
+        .build_stack_graph_into(stack_graph, file_handle, source, &globals, &NoCancellation)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
 
     Ok(())
 }
 
 fn process_rust_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
+    file_handle: Handle<File>,
     root_node: &ts::Node,
     source: &str,
 ) -> Result<()> {
-    // Find function and struct definitions in Rust
+    // Find function, type and field definitions in Rust
     let mut cursor = ts::QueryCursor::new();
     let query = ts::Query::new(
         &tree_sitter_rust::LANGUAGE.into(),
         "(function_item name: (identifier) @function_name) @function_def
          (struct_item name: (type_identifier) @struct_name) @struct_def
-         (impl_item type: (type_identifier) @impl_type) @impl_def
+         (field_declaration name: (field_identifier) @field_name) @field_def
          (trait_item name: (type_identifier) @trait_name) @trait_def
          (mod_item name: (identifier) @mod_name) @mod_def
          (enum_item name: (type_identifier) @enum_name) @enum_def"
     )?;
+    let mut spans = SpanCalculator::new(source);
+    let module = module_name(stack_graph, file_handle, &["lib", "main"], "mod");
+    let scopes = add_file_scopes(stack_graph, file_handle, &Language::Rust, None, module.as_deref());
+    let mut containers = Containers::new();
+    let mut names = HashSet::new();
 
     // Process matches
     let mut matches = cursor.matches(&query, *root_node, source.as_bytes());
 
     // Process each match using the streaming iterator pattern
     while let Some(match_) = matches.next() {
+        let definiens = definiens_capture(&query, match_);
+
         for i in 0..match_.captures.len() {
             let capture = &match_.captures[i];
             let capture_name = &query.capture_names()[capture.index as usize];
@@ -185,37 +633,143 @@ fn process_rust_syntax(
 
             debug!("Found Rust {}: {}", capture_name, node_text);
 
-            // Add to stack graph
-            if ["function_name", "struct_name", "impl_type", "trait_name", "mod_name", "enum_name"].contains(&capture_name.as_ref()) {
-                // Create a symbol for the definition
-                let symbol = stack_graph.add_symbol(node_text);
+            let syntax_type = match *capture_name {
+                "function_name" => {
+                    let container = definiens.and_then(|def| nearest_ancestor(&def, &["impl_item", "trait_item", "function_item"]));
+                    if matches!(container, Some("impl_item" | "trait_item")) { "method" } else { "function" }
+                }
+                "struct_name" => "struct",
+                "field_name" => "field",
+                "trait_name" => "trait",
+                "mod_name" => "module",
+                "enum_name" => "enum",
+                _ => continue,
+            };
+
+            // Create a symbol for the definition
+            let symbol = stack_graph.add_symbol(node_text);
+
+            // Create a node ID for this definition
+            let node_id = stack_graph.new_node_id(file_handle);
+
+            // Create a pop symbol node (definition)
+            let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
+                .expect("Failed to create pop symbol node");
+            set_source_info(stack_graph, pop_node, &mut spans, source, &node, syntax_type, definiens.as_ref());
+
+            // Items of impl blocks, traits, modules and structs are only reached through
+            // them, so that `A::new` and `B::new` are told apart
+            let impl_item = definiens
+                .and_then(|def| enclosing(&def, RUST_CONTAINERS))
+                .filter(|container| container.kind() == "impl_item");
+            if let Some(impl_item) = impl_item
+                && let Some(type_name) = rust_impl_type(&impl_item, source)
+            {
+                add_impl_scope(stack_graph, &scopes, &mut containers, &impl_item, type_name);
+                let qualified_name = stack_graph.add_string(&format!("{}.{}", type_name, node_text));
+                stack_graph.source_info_mut(pop_node).fully_qualified_name = qualified_name.into();
+            }
+            let placement = placement(definiens.as_ref(), RUST_LOCALS, RUST_CONTAINERS, &containers);
+            link_definition(stack_graph, &scopes, pop_node, placement, matches!(syntax_type, "method" | "field"));
+            names.insert(node.byte_range());
+
+            // Create a scope node for this definition
+            let scope_id = stack_graph.new_node_id(file_handle);
+            let scope_node = stack_graph.add_scope_node(scope_id, true)
+                .expect("Failed to create scope node");
+
+            // Connect the pop node to the scope node
+            stack_graph.add_edge(pop_node, scope_node, 0);
+            if let Some(definiens) = definiens {
+                containers.insert(definiens.byte_range(), scope_node);
+            }
 
-                // Create a node ID for this definition
-                let node_id = stack_graph.new_node_id(_file_handle);
+            debug!("Created stack graph nodes for Rust {}: {}", capture_name, node_text);
+        }
+    }
 
-                // Create a pop symbol node (definition)
-                let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
-                    .expect("Failed to create pop symbol node");
+    // Calls, type usages and field accesses resolve by name through the file's scope; the
+    // type an impl block is for is such a usage too, and a call through a path such as
+    // `Type::new` resolves inside the definitions the path names
+    let references = ts::Query::new(
+        &tree_sitter_rust::LANGUAGE.into(),
+        "(call_expression function: (identifier) @call)
+         (call_expression function: (scoped_identifier name: (identifier) @call))
+         (scoped_identifier path: (identifier) @type)
+         (type_identifier) @type
+         (field_expression field: (field_identifier) @field)"
+    )?;
+    add_references(stack_graph, &scopes, root_node, source, &references, &names, |node, _| {
+        let path = node.parent().filter(|path| path.kind() == "scoped_identifier")?;
+        if path.child_by_field_name("name") != Some(*node) {
+            return None;
+        }
 
-                // Create a scope node for this definition
-                let scope_id = stack_graph.new_node_id(_file_handle);
-                let scope_node = stack_graph.add_scope_node(scope_id, true)
-                    .expect("Failed to create scope node");
+        // `Self` is the type of the impl block the path is in
+        let impl_type = enclosing(node, &["impl_item"]).and_then(|impl_item| rust_impl_type(&impl_item, source));
+        let segments = rust_path(path.child_by_field_name("path"), source).into_iter()
+            .map(|segment| match (segment, impl_type) {
+                ("Self", Some(impl_type)) => impl_type.to_string(),
+                (segment, _) => segment.to_string(),
+            })
+            .collect();
+        Some(Lookup::Path(segments))
+    });
 
-                // Connect the pop node to the scope node
-                stack_graph.add_edge(pop_node, scope_node, 0);
+    Ok(())
+}
 
-                debug!("Created stack graph nodes for Rust {}: {}", capture_name, node_text);
-            }
+/// Kinds of Rust syntax nodes whose definitions are local to them
+const RUST_LOCALS: &[&str] = &["function_item", "closure_expression"];
+
+/// Kinds of Rust syntax nodes whose items are reached through them
+const RUST_CONTAINERS: &[&str] = &["impl_item", "trait_item", "mod_item", "struct_item"];
+
+/// Name of the type an impl block is for, without its type arguments
+fn rust_impl_type<'a>(impl_item: &ts::Node, source: &'a str) -> Option<&'a str> {
+    let mut type_node = impl_item.child_by_field_name("type")?;
+    if type_node.kind() == "generic_type" {
+        type_node = type_node.child_by_field_name("type")?;
+    }
+    (type_node.kind() == "type_identifier").then(|| &source[type_node.byte_range()])
+}
+
+/// Segments of a Rust path that name modules and types, outermost first, leaving out
+/// `crate`, `self` and `super`, which only say where the path starts
+fn rust_path<'a>(path: Option<ts::Node>, source: &'a str) -> Vec<&'a str> {
+    let Some(path) = path else {
+        return Vec::new();
+    };
+    match path.kind() {
+        "scoped_identifier" | "scoped_type_identifier" => {
+            let mut segments = rust_path(path.child_by_field_name("path"), source);
+            segments.extend(rust_path(path.child_by_field_name("name"), source));
+            segments
         }
+        "generic_type" => rust_path(path.child_by_field_name("type"), source),
+        "identifier" | "type_identifier" => vec![&source[path.byte_range()]],
+        _ => Vec::new(),
+    }
+}
+
+/// Give an impl block a scope for its items, reached through a pop node for the type it
+/// is for, where the type's own definition would be
+fn add_impl_scope(stack_graph: &mut StackGraph, scopes: &FileScopes, containers: &mut Containers, impl_item: &ts::Node, type_name: &str) {
+    if containers.contains_key(&impl_item.byte_range()) {
+        return;
     }
 
-    Ok(())
+    let type_node = add_scope_symbol(stack_graph, scopes.file, type_name, true);
+    let scope = add_scope(stack_graph, scopes.file, true);
+    stack_graph.add_edge(type_node, scope, 0);
+    let placement = placement(Some(impl_item), RUST_LOCALS, RUST_CONTAINERS, containers);
+    link_definition(stack_graph, scopes, type_node, placement, false);
+    containers.insert(impl_item.byte_range(), scope);
 }
 
 fn process_javascript_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
+    file_handle: Handle<File>,
     root_node: &ts::Node,
     source: &str,
 ) -> Result<()> {
@@ -228,15 +782,21 @@ fn process_javascript_syntax(
          (method_definition name: (property_identifier) @method_name) @method_def
          (lexical_declaration
            (variable_declarator name: (identifier) @const_name
-             value: [(function) (arrow_function)]) @const_fn_def)
+             value: [(function_expression) (arrow_function)]) @const_fn_def)
          (import_statement source: (string) @import_source) @import_def"
     )?;
+    let mut spans = SpanCalculator::new(source);
+    let scopes = add_file_scopes(stack_graph, file_handle, &Language::JavaScript, None, None);
+    let mut containers = Containers::new();
+    let mut names = HashSet::new();
 
     // Process matches
     let mut matches = cursor.matches(&query, *root_node, source.as_bytes());
 
     // Process each match using the streaming iterator pattern
     while let Some(match_) = matches.next() {
+        let definiens = definiens_capture(&query, match_);
+
         for i in 0..match_.captures.len() {
             let capture = &match_.captures[i];
             let capture_name = &query.capture_names()[capture.index as usize];
@@ -245,49 +805,78 @@ fn process_javascript_syntax(
 
             debug!("Found JavaScript {}: {}", capture_name, node_text);
 
-            // Add to stack graph
-            if ["function_name", "class_name", "method_name", "const_name", "import_source"].contains(&capture_name.as_ref()) {
-                // Create a symbol for the definition
-                let symbol = stack_graph.add_symbol(node_text);
-
-                // Create a node ID for this definition
-                let node_id = stack_graph.new_node_id(_file_handle);
-
-                // Create a pop symbol node (definition)
-                let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
-                    .expect("Failed to create pop symbol node");
-
-                // Create a scope node for this definition
-                let scope_id = stack_graph.new_node_id(_file_handle);
-                let scope_node = stack_graph.add_scope_node(scope_id, true)
-                    .expect("Failed to create scope node");
-
-                // Connect the pop node to the scope node
-                stack_graph.add_edge(pop_node, scope_node, 0);
-
-                // For imports, create a push symbol node to reference the imported module
-                if ["import_source"].contains(&capture_name.as_ref()) {
-                    let push_id = stack_graph.new_node_id(_file_handle);
-                    let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
-                        .expect("Failed to create push symbol node");
+            let syntax_type = match *capture_name {
+                "function_name" | "const_name" => "function",
+                "class_name" => "class",
+                "method_name" => "method",
+                "import_source" => "import",
+                _ => continue,
+            };
+
+            // Create a symbol for the definition
+            let symbol = stack_graph.add_symbol(node_text);
+
+            // Create a node ID for this definition
+            let node_id = stack_graph.new_node_id(file_handle);
+
+            // Create a pop symbol node (definition)
+            let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
+                .expect("Failed to create pop symbol node");
+            set_source_info(stack_graph, pop_node, &mut spans, source, &node, syntax_type, definiens.as_ref());
+
+            // Definitions inside functions are only visible in their own file, and methods
+            // are reached through their class
+            let placement = match syntax_type {
+                "import" => Placement::Local,
+                _ => placement(definiens.as_ref(), &["function_declaration", "generator_function_declaration", "function_expression", "arrow_function", "method_definition"], &["class_declaration"], &containers),
+            };
+            link_definition(stack_graph, &scopes, pop_node, placement, syntax_type == "method");
+            names.insert(node.byte_range());
+
+            // Create a scope node for this definition
+            let scope_id = stack_graph.new_node_id(file_handle);
+            let scope_node = stack_graph.add_scope_node(scope_id, true)
+                .expect("Failed to create scope node");
+
+            // Connect the pop node to the scope node
+            stack_graph.add_edge(pop_node, scope_node, 0);
+            if let Some(definiens) = definiens {
+                containers.insert(definiens.byte_range(), scope_node);
+            }
 
-                    // Connect the scope node to the push node
-                    stack_graph.add_edge(scope_node, push_node, 0);
-                }
+            // For imports, create a push symbol node to reference the imported module
+            if ["import_source"].contains(capture_name) {
+                let push_id = stack_graph.new_node_id(file_handle);
+                let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
+                    .expect("Failed to create push symbol node");
+                set_source_info(stack_graph, push_node, &mut spans, source, &node, syntax_type, None);
 
-                debug!("Created stack graph nodes for JavaScript {}: {}", capture_name, node_text);
+                // Connect the scope node to the push node
+                stack_graph.add_edge(scope_node, push_node, 0);
             }
+
+            debug!("Created stack graph nodes for JavaScript {}: {}", capture_name, node_text);
         }
     }
 
+    // Calls, type usages and field accesses resolve by name through the file's scope
+    let references = ts::Query::new(
+        &tree_sitter_javascript::LANGUAGE.into(),
+        "(call_expression function: (identifier) @call)
+         (new_expression constructor: (identifier) @call)
+         (member_expression property: (property_identifier) @field)
+         (class_heritage (identifier) @type)"
+    )?;
+    add_references(stack_graph, &scopes, root_node, source, &references, &names, |_, _| None);
+
     Ok(())
 }
 
 fn process_typescript_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
-    _root_node: &ts::Node,
-    _source: &str,
+    file_handle: Handle<File>,
+    root_node: &ts::Node,
+    source: &str,
 ) -> Result<()> {
     // TypeScript processing - create a module node for the file
     debug!("Processing TypeScript syntax");
@@ -296,14 +885,15 @@ fn process_typescript_syntax(
     let symbol = stack_graph.add_symbol("module");
 
     // Create a node ID for this module
-    let node_id = stack_graph.new_node_id(_file_handle);
+    let node_id = stack_graph.new_node_id(file_handle);
 
     // Create a pop symbol node (definition)
     let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
         .expect("Failed to create pop symbol node");
+    set_module_source_info(stack_graph, pop_node, source, root_node);
 
     // Create a scope node for this module
-    let scope_id = stack_graph.new_node_id(_file_handle);
+    let scope_id = stack_graph.new_node_id(file_handle);
     let scope_node = stack_graph.add_scope_node(scope_id, true)
         .expect("Failed to create scope node");
 
@@ -317,7 +907,7 @@ fn process_typescript_syntax(
 
 fn process_python_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
+    file_handle: Handle<File>,
     root_node: &ts::Node,
     source: &str,
 ) -> Result<()> {
@@ -328,14 +918,22 @@ fn process_python_syntax(
         "(function_definition name: (identifier) @function_name) @function_def
          (class_definition name: (identifier) @class_name) @class_def
          (import_statement name: (dotted_name) @import_name) @import_def
-         (import_from_statement module_name: (dotted_name) @module_name) @import_from"
+         (import_from_statement module_name: (dotted_name) @module_name) @import_from_def"
     )?;
+    let mut spans = SpanCalculator::new(source);
+    let module = module_name(stack_graph, file_handle, &[], "__init__");
+    let scopes = add_file_scopes(stack_graph, file_handle, &Language::Python, None, module.as_deref());
+    let mut containers = Containers::new();
+    let mut imported_modules = HashSet::new();
+    let mut names = HashSet::new();
 
     // Process matches
     let mut matches = cursor.matches(&query, *root_node, source.as_bytes());
 
     // Process each match using the streaming iterator pattern
     while let Some(match_) = matches.next() {
+        let definiens = definiens_capture(&query, match_);
+
         for i in 0..match_.captures.len() {
             let capture = &match_.captures[i];
             let capture_name = &query.capture_names()[capture.index as usize];
@@ -344,49 +942,88 @@ fn process_python_syntax(
 
             debug!("Found Python {}: {}", capture_name, node_text);
 
-            // Add to stack graph
-            if ["function_name", "class_name", "import_name", "module_name"].contains(&capture_name.as_ref()) {
-                // Create a symbol for the definition
-                let symbol = stack_graph.add_symbol(node_text);
-
-                // Create a node ID for this definition
-                let node_id = stack_graph.new_node_id(_file_handle);
-
-                // Create a pop symbol node (definition)
-                let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
-                    .expect("Failed to create pop symbol node");
-
-                // Create a scope node for this definition
-                let scope_id = stack_graph.new_node_id(_file_handle);
-                let scope_node = stack_graph.add_scope_node(scope_id, true)
-                    .expect("Failed to create scope node");
+            let syntax_type = match *capture_name {
+                "function_name" => {
+                    let container = definiens.and_then(|def| nearest_ancestor(&def, &["class_definition", "function_definition"]));
+                    if container == Some("class_definition") { "method" } else { "function" }
+                }
+                "class_name" => "class",
+                "import_name" | "module_name" => "import",
+                _ => continue,
+            };
+
+            // Create a symbol for the definition
+            let symbol = stack_graph.add_symbol(node_text);
+
+            // Create a node ID for this definition
+            let node_id = stack_graph.new_node_id(file_handle);
+
+            // Create a pop symbol node (definition)
+            let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
+                .expect("Failed to create pop symbol node");
+            set_source_info(stack_graph, pop_node, &mut spans, source, &node, syntax_type, definiens.as_ref());
+
+            // Definitions inside functions are only visible in their own file, and methods
+            // are reached through their class
+            let placement = match syntax_type {
+                "import" => Placement::Local,
+                _ => placement(definiens.as_ref(), &["function_definition", "lambda"], &["class_definition"], &containers),
+            };
+            link_definition(stack_graph, &scopes, pop_node, placement, syntax_type == "method");
+            if *capture_name == "import_name" {
+                imported_modules.insert(node_text.to_string());
+            }
+            names.insert(node.byte_range());
 
-                // Connect the pop node to the scope node
-                stack_graph.add_edge(pop_node, scope_node, 0);
+            // Create a scope node for this definition
+            let scope_id = stack_graph.new_node_id(file_handle);
+            let scope_node = stack_graph.add_scope_node(scope_id, true)
+                .expect("Failed to create scope node");
 
-                // For imports, create a push symbol node to reference the imported module
-                if ["import_name", "module_name"].contains(&capture_name.as_ref()) {
-                    let push_id = stack_graph.new_node_id(_file_handle);
-                    let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
-                        .expect("Failed to create push symbol node");
+            // Connect the pop node to the scope node
+            stack_graph.add_edge(pop_node, scope_node, 0);
+            if let Some(definiens) = definiens {
+                containers.insert(definiens.byte_range(), scope_node);
+            }
 
-                    // Connect the scope node to the push node
-                    stack_graph.add_edge(scope_node, push_node, 0);
-                }
+            // For imports, create a push symbol node to reference the imported module
+            if ["import_name", "module_name"].contains(capture_name) {
+                let push_id = stack_graph.new_node_id(file_handle);
+                let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
+                    .expect("Failed to create push symbol node");
+                set_source_info(stack_graph, push_node, &mut spans, source, &node, syntax_type, None);
 
-                debug!("Created stack graph nodes for Python {}: {}", capture_name, node_text);
+                // Connect the scope node to the push node
+                stack_graph.add_edge(scope_node, push_node, 0);
             }
+
+            debug!("Created stack graph nodes for Python {}: {}", capture_name, node_text);
         }
     }
 
+    // Calls, type usages and field accesses resolve by name through the file's scope,
+    // and an attribute of an imported module inside that module
+    let references = ts::Query::new(
+        &tree_sitter_python::LANGUAGE.into(),
+        "(call function: (identifier) @call)
+         (attribute attribute: (identifier) @field)
+         (type (identifier) @type)
+         (class_definition superclasses: (argument_list (identifier) @type))"
+    )?;
+    add_references(stack_graph, &scopes, root_node, source, &references, &names, |node, _| {
+        let object = node.parent()?.child_by_field_name("object")?;
+        let module = &source[object.byte_range()];
+        imported_modules.contains(module).then(|| Lookup::Path(vec![module.to_string()]))
+    });
+
     Ok(())
 }
 
 fn process_java_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
-    _root_node: &ts::Node,
-    _source: &str,
+    file_handle: Handle<File>,
+    root_node: &ts::Node,
+    source: &str,
 ) -> Result<()> {
     // Java processing - create a module node for the file
     debug!("Processing Java syntax");
@@ -395,14 +1032,15 @@ fn process_java_syntax(
     let symbol = stack_graph.add_symbol("java_module");
 
     // Create a node ID for this module
-    let node_id = stack_graph.new_node_id(_file_handle);
+    let node_id = stack_graph.new_node_id(file_handle);
 
     // Create a pop symbol node (definition)
     let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
         .expect("Failed to create pop symbol node");
+    set_module_source_info(stack_graph, pop_node, source, root_node);
 
     // Create a scope node for this module
-    let scope_id = stack_graph.new_node_id(_file_handle);
+    let scope_id = stack_graph.new_node_id(file_handle);
     let scope_node = stack_graph.add_scope_node(scope_id, true)
         .expect("Failed to create scope node");
 
@@ -416,25 +1054,38 @@ fn process_java_syntax(
 
 fn process_go_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
+    file_handle: Handle<File>,
     root_node: &ts::Node,
     source: &str,
 ) -> Result<()> {
-    // Find function and type definitions in Go
+    // Find function, type and field definitions in Go
     let mut cursor = ts::QueryCursor::new();
     let query = ts::Query::new(
         &tree_sitter_go::LANGUAGE.into(),
         "(function_declaration name: (identifier) @function_name) @function_def
          (method_declaration name: (field_identifier) @method_name) @method_def
          (type_declaration (type_spec name: (type_identifier) @type_name)) @type_def
+         (field_declaration name: (field_identifier) @field_name) @field_def
          (import_declaration (import_spec path: (interpreted_string_literal) @import_path)) @import_def"
     )?;
+    let mut spans = SpanCalculator::new(source);
+    // Files declare their package, and definitions are shared by the package's files
+    let package = root_node.children(&mut root_node.walk())
+        .find(|child| child.kind() == "package_clause")
+        .and_then(|clause| clause.named_child(0))
+        .map(|name| &source[name.byte_range()]);
+    let scopes = add_file_scopes(stack_graph, file_handle, &Language::Go, Some(package.unwrap_or("main")), None);
+    let mut containers = Containers::new();
+    let mut imported_packages = HashSet::new();
+    let mut names = HashSet::new();
 
     // Process matches
     let mut matches = cursor.matches(&query, *root_node, source.as_bytes());
 
     // Process each match using the streaming iterator pattern
     while let Some(match_) = matches.next() {
+        let mut definiens = definiens_capture(&query, match_);
+
         for i in 0..match_.captures.len() {
             let capture = &match_.captures[i];
             let capture_name = &query.capture_names()[capture.index as usize];
@@ -443,47 +1094,109 @@ fn process_go_syntax(
 
             debug!("Found Go {}: {}", capture_name, node_text);
 
-            // Add to stack graph
-            if ["function_name", "method_name", "type_name", "import_path"].contains(&capture_name.as_ref()) {
-                // Create a symbol for the definition
-                let symbol = stack_graph.add_symbol(node_text);
-
-                // Create a node ID for this definition
-                let node_id = stack_graph.new_node_id(_file_handle);
-
-                // Create a pop symbol node (definition)
-                let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
-                    .expect("Failed to create pop symbol node");
-
-                // Create a scope node for this definition
-                let scope_id = stack_graph.new_node_id(_file_handle);
-                let scope_node = stack_graph.add_scope_node(scope_id, true)
-                    .expect("Failed to create scope node");
-
-                // Connect the pop node to the scope node
-                stack_graph.add_edge(pop_node, scope_node, 0);
-
-                // For imports, create a push symbol node to reference the imported module
-                if ["import_path"].contains(&capture_name.as_ref()) {
-                    let push_id = stack_graph.new_node_id(_file_handle);
-                    let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
-                        .expect("Failed to create push symbol node");
-
-                    // Connect the scope node to the push node
-                    stack_graph.add_edge(scope_node, push_node, 0);
+            let syntax_type = match *capture_name {
+                "function_name" => "function",
+                "method_name" => "method",
+                "type_name" => {
+                    // A type declaration can group several types, each defined by its own spec
+                    let spec = node.parent();
+                    definiens = spec.or(definiens);
+                    match spec.and_then(|spec| spec.child_by_field_name("type")).map(|ty| ty.kind()) {
+                        Some("struct_type") => "struct",
+                        Some("interface_type") => "interface",
+                        _ => "type",
+                    }
                 }
+                "field_name" => "field",
+                "import_path" => "import",
+                _ => continue,
+            };
+
+            // Create a symbol for the definition
+            let symbol = stack_graph.add_symbol(node_text);
+
+            // Create a node ID for this definition
+            let node_id = stack_graph.new_node_id(file_handle);
+
+            // Create a pop symbol node (definition)
+            let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
+                .expect("Failed to create pop symbol node");
+            set_source_info(stack_graph, pop_node, &mut spans, source, &node, syntax_type, definiens.as_ref());
+
+            // Definitions inside functions are only visible in their own file, methods
+            // only to member accesses, and fields to member accesses and through their type
+            let placement = match syntax_type {
+                "import" => {
+                    imported_packages.insert(go_import_name(&node, source));
+                    Placement::Local
+                }
+                "method" => Placement::Contained(scopes.members),
+                _ => placement(definiens.as_ref(), &["function_declaration", "method_declaration", "func_literal"], &["type_spec"], &containers),
+            };
+            link_definition(stack_graph, &scopes, pop_node, placement, syntax_type == "field");
+            names.insert(node.byte_range());
+
+            // Create a scope node for this definition
+            let scope_id = stack_graph.new_node_id(file_handle);
+            let scope_node = stack_graph.add_scope_node(scope_id, true)
+                .expect("Failed to create scope node");
+
+            // Connect the pop node to the scope node
+            stack_graph.add_edge(pop_node, scope_node, 0);
+            if let Some(definiens) = definiens {
+                containers.insert(definiens.byte_range(), scope_node);
+            }
 
-                debug!("Created stack graph nodes for Go {}: {}", capture_name, node_text);
+            // For imports, create a push symbol node to reference the imported module
+            if ["import_path"].contains(capture_name) {
+                let push_id = stack_graph.new_node_id(file_handle);
+                let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
+                    .expect("Failed to create push symbol node");
+                set_source_info(stack_graph, push_node, &mut spans, source, &node, syntax_type, None);
+
+                // Connect the scope node to the push node
+                stack_graph.add_edge(scope_node, push_node, 0);
             }
+
+            debug!("Created stack graph nodes for Go {}: {}", capture_name, node_text);
         }
     }
 
+    // Calls, type usages and field accesses resolve by name through the file's scope, and
+    // names qualified with an imported package inside that package
+    let references = ts::Query::new(
+        &tree_sitter_go::LANGUAGE.into(),
+        "(call_expression function: (identifier) @call)
+         (selector_expression field: (field_identifier) @field)
+         (type_identifier) @type"
+    )?;
+    add_references(stack_graph, &scopes, root_node, source, &references, &names, |node, _| {
+        let parent = node.parent()?;
+        let qualifier = match parent.kind() {
+            "selector_expression" => parent.child_by_field_name("operand").filter(|operand| operand.kind() == "identifier")?,
+            "qualified_type" => parent.child_by_field_name("package")?,
+            _ => return None,
+        };
+        let package = &source[qualifier.byte_range()];
+        imported_packages.contains(package).then(|| Lookup::Path(vec![package.to_string()]))
+    });
+
     Ok(())
 }
 
+/// Name an imported Go package is referred to by: its alias, or the last segment of its
+/// import path
+fn go_import_name(path: &ts::Node, source: &str) -> String {
+    if let Some(alias) = path.parent().and_then(|spec| spec.child_by_field_name("name")) {
+        return source[alias.byte_range()].to_string();
+    }
+    let path = source[path.byte_range()].trim_matches('"');
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
 fn process_ruby_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
+    file_handle: Handle<File>,
     root_node: &ts::Node,
     source: &str,
 ) -> Result<()> {
@@ -492,17 +1205,25 @@ fn process_ruby_syntax(
     let query = ts::Query::new(
         &tree_sitter_ruby::LANGUAGE.into(),
         "(method name: (identifier) @method_name) @method_def
+         (singleton_method name: (identifier) @singleton_method_name) @singleton_method_def
          (class name: (constant) @class_name) @class_def
          (module name: (constant) @module_name) @module_def
-         (require call: (identifier) @require_call
-           argument: (string (string_content) @require_path)) @require_def"
+         ((call method: (identifier) @require_call
+           arguments: (argument_list (string (string_content) @require_path))) @require_def
+          (#match? @require_call \"^require(_relative)?$\"))"
     )?;
+    let mut spans = SpanCalculator::new(source);
+    let scopes = add_file_scopes(stack_graph, file_handle, &Language::Ruby, None, None);
+    let mut containers = Containers::new();
+    let mut names = HashSet::new();
 
     // Process matches
     let mut matches = cursor.matches(&query, *root_node, source.as_bytes());
 
     // Process each match using the streaming iterator pattern
     while let Some(match_) = matches.next() {
+        let definiens = definiens_capture(&query, match_);
+
         for i in 0..match_.captures.len() {
             let capture = &match_.captures[i];
             let capture_name = &query.capture_names()[capture.index as usize];
@@ -511,48 +1232,113 @@ fn process_ruby_syntax(
 
             debug!("Found Ruby {}: {}", capture_name, node_text);
 
-            // Add to stack graph
-            if ["method_name", "class_name", "module_name", "require_path"].contains(&capture_name.as_ref()) {
-                // Create a symbol for the definition
-                let symbol = stack_graph.add_symbol(node_text);
-
-                // Create a node ID for this definition
-                let node_id = stack_graph.new_node_id(_file_handle);
-
-                // Create a pop symbol node (definition)
-                let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
-                    .expect("Failed to create pop symbol node");
-
-                // Create a scope node for this definition
-                let scope_id = stack_graph.new_node_id(_file_handle);
-                let scope_node = stack_graph.add_scope_node(scope_id, true)
-                    .expect("Failed to create scope node");
+            let syntax_type = match *capture_name {
+                "method_name" => "method",
+                "singleton_method_name" => "singleton_method",
+                "class_name" => "class",
+                "module_name" => "module",
+                "require_path" => "import",
+                _ => continue,
+            };
+
+            // Create a symbol for the definition
+            let symbol = stack_graph.add_symbol(node_text);
+
+            // Create a node ID for this definition
+            let node_id = stack_graph.new_node_id(file_handle);
+
+            // Create a pop symbol node (definition)
+            let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
+                .expect("Failed to create pop symbol node");
+            set_source_info(stack_graph, pop_node, &mut spans, source, &node, syntax_type, definiens.as_ref());
+
+            // Definitions inside methods are only visible in their own file, and those in
+            // classes and modules are reached through them, like `Shop::User`; the
+            // file's own are in scope unqualified too, as methods called on `self` are
+            let placement = match syntax_type {
+                "import" => Placement::Local,
+                _ => placement(definiens.as_ref(), &["method", "singleton_method"], &["class", "module"], &containers),
+            };
+            link_definition(stack_graph, &scopes, pop_node, placement, matches!(syntax_type, "method" | "singleton_method"));
+            if let Placement::Contained(_) = placement {
+                stack_graph.add_edge(scopes.scope, pop_node, 1);
+            }
+            names.insert(node.byte_range());
 
-                // Connect the pop node to the scope node
-                stack_graph.add_edge(pop_node, scope_node, 0);
+            // Create a scope node for this definition
+            let scope_id = stack_graph.new_node_id(file_handle);
+            let scope_node = stack_graph.add_scope_node(scope_id, true)
+                .expect("Failed to create scope node");
 
-                // For requires, create a push symbol node to reference the required module
-                if ["require_path"].contains(&capture_name.as_ref()) {
-                    let push_id = stack_graph.new_node_id(_file_handle);
-                    let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
-                        .expect("Failed to create push symbol node");
+            // Connect the pop node to the scope node
+            stack_graph.add_edge(pop_node, scope_node, 0);
+            if let Some(definiens) = definiens {
+                containers.insert(definiens.byte_range(), scope_node);
+            }
 
-                    // Connect the scope node to the push node
-                    stack_graph.add_edge(scope_node, push_node, 0);
-                }
+            // For requires, create a push symbol node to reference the required module
+            if ["require_path"].contains(capture_name) {
+                let push_id = stack_graph.new_node_id(file_handle);
+                let push_node = stack_graph.add_push_symbol_node(push_id, symbol, true)
+                    .expect("Failed to create push symbol node");
+                set_source_info(stack_graph, push_node, &mut spans, source, &node, syntax_type, None);
 
-                debug!("Created stack graph nodes for Ruby {}: {}", capture_name, node_text);
+                // Connect the scope node to the push node
+                stack_graph.add_edge(scope_node, push_node, 0);
             }
+
+            debug!("Created stack graph nodes for Ruby {}: {}", capture_name, node_text);
         }
     }
 
+    // Calls and constants resolve by name through the file's scope, those qualified with
+    // a class or module inside it, and calls on any other receiver as members
+    let references = ts::Query::new(
+        &tree_sitter_ruby::LANGUAGE.into(),
+        "(call method: (identifier) @call)
+         (constant) @type"
+    )?;
+    add_references(stack_graph, &scopes, root_node, source, &references, &names, |node, _| {
+        let parent = node.parent()?;
+        let qualifier = match parent.kind() {
+            "call" => parent.child_by_field_name("receiver")?,
+            "scope_resolution" if parent.child_by_field_name("name") == Some(*node) => parent.child_by_field_name("scope")?,
+            _ => return None,
+        };
+        match ruby_path(&qualifier, source) {
+            path if path.is_empty() => Some(Lookup::Member),
+            path => Some(Lookup::Path(path)),
+        }
+    });
+
     Ok(())
 }
 
+/// Names of the classes and modules a Ruby constant path names, outermost first, or
+/// none if it is not a constant path
+fn ruby_path(node: &ts::Node, source: &str) -> Vec<String> {
+    match node.kind() {
+        "constant" => vec![source[node.byte_range()].to_string()],
+        "scope_resolution" => {
+            let Some(name) = node.child_by_field_name("name") else {
+                return Vec::new();
+            };
+            let mut path = match node.child_by_field_name("scope") {
+                Some(scope) => ruby_path(&scope, source),
+                None => Vec::new(),
+            };
+            path.push(source[name.byte_range()].to_string());
+            path
+        }
+        _ => Vec::new(),
+    }
+}
+
 fn process_generic_syntax(
     stack_graph: &mut StackGraph,
-    _file_handle: Handle<File>,
-    _source: &str,
+    file_handle: Handle<File>,
+    root_node: &ts::Node,
+    source: &str,
     language: &Language,
 ) -> Result<()> {
     // Basic processing for languages without specific handlers
@@ -562,14 +1348,15 @@ fn process_generic_syntax(
     let symbol = stack_graph.add_symbol("module");
 
     // Create a node ID for this module
-    let node_id = stack_graph.new_node_id(_file_handle);
+    let node_id = stack_graph.new_node_id(file_handle);
 
     // Create a pop symbol node (definition)
     let pop_node = stack_graph.add_pop_symbol_node(node_id, symbol, true)
         .expect("Failed to create pop symbol node");
+    set_module_source_info(stack_graph, pop_node, source, root_node);
 
     // Create a scope node for this module
-    let scope_id = stack_graph.new_node_id(_file_handle);
+    let scope_id = stack_graph.new_node_id(file_handle);
     let scope_node = stack_graph.add_scope_node(scope_id, true)
         .expect("Failed to create scope node");
 
@@ -579,4 +1366,445 @@ fn process_generic_syntax(
     debug!("Created stack graph nodes for generic module");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// The syntax node a query match defines, captured under a name ending in `_def`
+fn definiens_capture<'tree>(query: &ts::Query, match_: &ts::QueryMatch<'_, 'tree>) -> Option<ts::Node<'tree>> {
+    match_.captures.iter()
+        .find(|capture| query.capture_names()[capture.index as usize].ends_with("_def"))
+        .map(|capture| capture.node)
+}
+
+/// Kind of the nearest ancestor of a syntax node that has one of `kinds`
+fn nearest_ancestor(node: &ts::Node, kinds: &[&'static str]) -> Option<&'static str> {
+    let ancestor = enclosing(node, kinds)?;
+    kinds.iter().copied().find(|kind| *kind == ancestor.kind())
+}
+
+/// The nearest ancestor of a syntax node that has one of `kinds`
+fn enclosing<'tree>(node: &ts::Node<'tree>, kinds: &[&str]) -> Option<ts::Node<'tree>> {
+    let mut parent = node.parent();
+    while let Some(node) = parent {
+        if kinds.contains(&node.kind()) {
+            return Some(node);
+        }
+        parent = node.parent();
+    }
+    None
+}
+
+/// Symbol that member accesses such as `value.name` push before the member's name
+const MEMBER_SYMBOL: &str = ".";
+
+/// Scopes that the query-based processors connect a file's definitions and references
+/// through
+///
+/// Exported definitions are reached from the root through a pop node for the file's
+/// language and, where the language has them, its package, so that names only resolve
+/// between files of the same language and package.
+struct FileScopes {
+    file: Handle<File>,
+    /// Scope that references in the file resolve through: the file's own definitions,
+    /// and after them the definitions other files export
+    scope: Handle<Node>,
+    /// Scope the file's top-level definitions are exported through
+    exports: Handle<Node>,
+    /// Scope the file's methods and fields are exported through, for member accesses
+    members: Handle<Node>,
+}
+
+/// Add the scopes for a file in `language`, whose definitions are in `package` if the
+/// language has packages, and can be qualified with the name of their `module`, e.g.
+/// `util::helper` for a function in `util.rs`
+fn add_file_scopes(
+    stack_graph: &mut StackGraph,
+    file: Handle<File>,
+    language: &Language,
+    package: Option<&str>,
+    module: Option<&str>,
+) -> FileScopes {
+    // No identifier can be spelled like this, so references never meet other languages
+    let language = format!("<{}>", language.name().to_lowercase());
+
+    let file_scope = add_scope(stack_graph, file, false);
+    let language_entry = add_scope_symbol(stack_graph, file, &language, false);
+    stack_graph.add_edge(file_scope, language_entry, 0);
+    stack_graph.add_edge(language_entry, StackGraph::root_node(), 0);
+
+    let language_scope = add_scope(stack_graph, file, false);
+    let language_exit = add_scope_symbol(stack_graph, file, &language, true);
+    stack_graph.add_edge(StackGraph::root_node(), language_exit, 0);
+    stack_graph.add_edge(language_exit, language_scope, 0);
+
+    let exports = add_scope(stack_graph, file, true);
+    match package {
+        // Names from the file's own package need no qualifier, other packages' do
+        Some(package) => {
+            let package_entry = add_scope_symbol(stack_graph, file, package, false);
+            stack_graph.add_edge(file_scope, package_entry, 0);
+            stack_graph.add_edge(package_entry, language_entry, 0);
+            let package_exit = add_scope_symbol(stack_graph, file, package, true);
+            stack_graph.add_edge(language_scope, package_exit, 0);
+            stack_graph.add_edge(package_exit, exports, 0);
+        }
+        None => stack_graph.add_edge(language_scope, exports, 0),
+    }
+    if let Some(module) = module {
+        let module_exit = add_scope_symbol(stack_graph, file, module, true);
+        stack_graph.add_edge(language_scope, module_exit, 0);
+        stack_graph.add_edge(module_exit, exports, 0);
+    }
+
+    let members = add_scope(stack_graph, file, true);
+    let member_exit = add_scope_symbol(stack_graph, file, MEMBER_SYMBOL, true);
+    stack_graph.add_edge(language_scope, member_exit, 0);
+    stack_graph.add_edge(member_exit, members, 0);
+
+    FileScopes { file, scope: file_scope, exports, members }
+}
+
+fn add_scope(stack_graph: &mut StackGraph, file: Handle<File>, exported: bool) -> Handle<Node> {
+    let scope_id = stack_graph.new_node_id(file);
+    stack_graph.add_scope_node(scope_id, exported)
+        .expect("Failed to create scope node")
+}
+
+/// A push node, or with `pop` a pop node, for a symbol that scopes definitions rather
+/// than naming something in the source
+fn add_scope_symbol(stack_graph: &mut StackGraph, file: Handle<File>, symbol: &str, pop: bool) -> Handle<Node> {
+    let symbol = stack_graph.add_symbol(symbol);
+    let node_id = stack_graph.new_node_id(file);
+    if pop {
+        stack_graph.add_pop_symbol_node(node_id, symbol, false)
+            .expect("Failed to create pop symbol node")
+    } else {
+        stack_graph.add_push_symbol_node(node_id, symbol, false)
+            .expect("Failed to create push symbol node")
+    }
+}
+
+/// Module name that a file's definitions can be qualified with, from its file name;
+/// a file named like `lib.rs` that is the root of its package has none
+fn module_name(stack_graph: &StackGraph, file: Handle<File>, roots: &[&str], index: &str) -> Option<String> {
+    let path = Path::new(stack_graph[file].name());
+    let stem = path.file_stem()?.to_str()?;
+    if roots.contains(&stem) {
+        return None;
+    }
+    if stem == index {
+        return path.parent()?.file_name()?.to_str().map(str::to_string);
+    }
+    Some(stem.to_string())
+}
+
+/// Where a definition made by a query-based processor can be referenced from
+#[derive(Clone, Copy)]
+enum Placement {
+    /// Only its own file, like the definitions inside a function
+    Local,
+    /// Its own file and, through the file's exports, other files
+    TopLevel,
+    /// Paths through the scope of the definition or impl block it belongs to, like
+    /// `Type::name`
+    Contained(Handle<Node>),
+}
+
+/// Make a definition visible where its `placement` says and, if it is a method or
+/// field, to member accesses
+fn link_definition(stack_graph: &mut StackGraph, scopes: &FileScopes, definition: Handle<Node>, placement: Placement, member: bool) {
+    match placement {
+        Placement::Local => stack_graph.add_edge(scopes.scope, definition, 1),
+        Placement::TopLevel => {
+            stack_graph.add_edge(scopes.scope, definition, 1);
+            stack_graph.add_edge(scopes.exports, definition, 0);
+        }
+        Placement::Contained(container) => stack_graph.add_edge(container, definition, 0),
+    }
+    if member && !matches!(placement, Placement::Local) {
+        stack_graph.add_edge(scopes.members, definition, 0);
+    }
+}
+
+/// Scopes of the definitions that contain others, by the byte range of their definiens
+type Containers = HashMap<Range<usize>, Handle<Node>>;
+
+/// Placement of a definition whose definiens is `definiens`: local if it is inside one
+/// of the `locals` kinds of syntax node, and otherwise in the scope of the nearest
+/// enclosing `containers` kind that has one
+fn placement(definiens: Option<&ts::Node>, locals: &[&str], container_kinds: &[&str], containers: &Containers) -> Placement {
+    let Some(definiens) = definiens else {
+        return Placement::TopLevel;
+    };
+    if enclosing(definiens, locals).is_some() {
+        return Placement::Local;
+    }
+    enclosing(definiens, container_kinds)
+        .and_then(|container| containers.get(&container.byte_range()))
+        .map_or(Placement::TopLevel, |&scope| Placement::Contained(scope))
+}
+
+/// How the name of a reference is looked up
+enum Lookup {
+    /// Among the names in scope where the reference is
+    Name,
+    /// Among the methods and fields of every type, for accesses like `value.name`
+    Member,
+    /// Inside the definitions a path names, outermost first, like `Type::name`
+    Path(Vec<String>),
+}
+
+/// Add a push-symbol reference, resolving through the file's scope, for every
+/// identifier the query captures other than the names of the file's definitions
+///
+/// `lookup` says how a reference is looked up, or with `None` field accesses are looked
+/// up as members and everything else by name. Capture names become the references'
+/// syntax types, except that a field access that is called is a `call`.
+fn add_references<L>(
+    stack_graph: &mut StackGraph,
+    scopes: &FileScopes,
+    root_node: &ts::Node,
+    source: &str,
+    query: &ts::Query,
+    definitions: &HashSet<Range<usize>>,
+    lookup: L,
+) where
+    L: Fn(&ts::Node, &str) -> Option<Lookup>,
+{
+    let mut spans = SpanCalculator::new(source);
+    let mut seen = HashSet::new();
+    let mut cursor = ts::QueryCursor::new();
+    let mut matches = cursor.matches(query, *root_node, source.as_bytes());
+
+    while let Some(match_) = matches.next() {
+        for capture in match_.captures {
+            let node = capture.node;
+            if definitions.contains(&node.byte_range()) || !seen.insert(node.byte_range()) {
+                continue;
+            }
+
+            let capture_name = query.capture_names()[capture.index as usize];
+            let lookup = lookup(&node, capture_name).unwrap_or(match capture_name {
+                "field" => Lookup::Member,
+                _ => Lookup::Name,
+            });
+            let syntax_type = match capture_name {
+                "field" if is_callee(&node) => "call",
+                capture_name => capture_name,
+            };
+
+            let symbol = stack_graph.add_symbol(&source[node.byte_range()]);
+            let node_id = stack_graph.new_node_id(scopes.file);
+            let push_node = stack_graph.add_push_symbol_node(node_id, symbol, true)
+                .expect("Failed to create push symbol node");
+            set_source_info(stack_graph, push_node, &mut spans, source, &node, syntax_type, None);
+
+            // Qualifiers are pushed after the name, so that they are popped first
+            let qualifiers = match lookup {
+                Lookup::Name => Vec::new(),
+                Lookup::Member => vec![MEMBER_SYMBOL.to_string()],
+                Lookup::Path(path) => path,
+            };
+            let mut last = push_node;
+            for qualifier in qualifiers.iter().rev() {
+                let qualifier_node = add_scope_symbol(stack_graph, scopes.file, qualifier, false);
+                stack_graph.add_edge(last, qualifier_node, 0);
+                last = qualifier_node;
+            }
+            stack_graph.add_edge(last, scopes.scope, 0);
+        }
+    }
+}
+
+/// Whether the field of a field access is what gets called, as in `value.method()`
+fn is_callee(field: &ts::Node) -> bool {
+    let Some(access) = field.parent() else {
+        return false;
+    };
+    access.parent()
+        .and_then(|call| call.child_by_field_name("function"))
+        .is_some_and(|function| function == access)
+}
+
+/// Record where a node made by a query-based processor comes from, as TSG rules do:
+/// the span and line of the `name` it was made for, its syntax type, and for
+/// definitions the span of the whole `definiens`
+fn set_source_info(
+    stack_graph: &mut StackGraph,
+    node: Handle<Node>,
+    spans: &mut SpanCalculator,
+    source: &str,
+    name: &ts::Node,
+    syntax_type: &str,
+    definiens: Option<&ts::Node>,
+) {
+    let span = spans.for_node(name);
+    let containing_line = stack_graph.add_string(&source[span.start.containing_line.clone()]);
+    let syntax_type = stack_graph.add_string(syntax_type);
+    let definiens_span = definiens.map(|definiens| spans.for_node(definiens));
+
+    let source_info = stack_graph.source_info_mut(node);
+    source_info.span = span;
+    source_info.containing_line = containing_line.into();
+    source_info.syntax_type = syntax_type.into();
+    if let Some(definiens_span) = definiens_span {
+        source_info.definiens_span = definiens_span;
+    }
+}
+
+/// Record a file's module definition as TSG rules do, with an empty span at the start
+/// of the file and the whole file as its definiens
+fn set_module_source_info(stack_graph: &mut StackGraph, node: Handle<Node>, source: &str, root_node: &ts::Node) {
+    let definiens_span = SpanCalculator::new(source).for_node(root_node);
+    let mut span = definiens_span.clone();
+    span.end = span.start.clone();
+    let syntax_type = stack_graph.add_string("module");
+
+    let source_info = stack_graph.source_info_mut(node);
+    source_info.span = span;
+    source_info.syntax_type = syntax_type.into();
+    source_info.definiens_span = definiens_span;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryEngine;
+
+    /// A stack graph of `files` built by the query-based processors, even for languages
+    /// whose TSG rules load
+    fn fallback_graph(files: &[(&str, &str)]) -> StackGraph {
+        let mut stack_graph = StackGraph::new();
+        for (name, source) in files {
+            let language = language_for(Path::new(name));
+            let tree = language.get_parser().unwrap().parse(source, None).unwrap();
+            let file = stack_graph.get_or_create_file(name);
+            process_fallback_syntax(&mut stack_graph, &language, file, &tree, source).unwrap();
+        }
+        stack_graph
+    }
+
+    /// Line and column of `offset` bytes into the first occurrence of `pattern`
+    fn position(source: &str, pattern: &str, offset: usize) -> (usize, usize) {
+        let start = source.find(pattern).unwrap() + offset;
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        (source[..start].matches('\n').count(), start - line_start)
+    }
+
+    /// Files and lines of the definitions that the reference at `offset` bytes into the
+    /// first occurrence of `pattern` in `file` resolves to
+    fn resolve(graph: &StackGraph, files: &[(&str, &str)], file: &str, pattern: &str, offset: usize) -> Vec<(String, usize)> {
+        let source = files.iter().find(|(name, _)| *name == file).unwrap().1;
+        let (line, column) = position(source, pattern, offset);
+        let mut definitions: Vec<(String, usize)> = QueryEngine::new(graph).unwrap()
+            .find_definitions(file, line, column).unwrap().into_iter()
+            .map(|definition| (definition.file, definition.span.start_line))
+            .collect();
+        definitions.sort();
+        definitions
+    }
+
+    fn at(file: &str, line: usize) -> Vec<(String, usize)> {
+        vec![(file.to_string(), line)]
+    }
+
+    #[test]
+    fn rust_calls_types_and_fields_resolve_across_files() {
+        let files = [
+            ("shapes.rs", "pub struct Point {\n    pub x: i32,\n}\n\nimpl Point {\n    pub fn new() -> Point {\n        Point { x: 0 }\n    }\n}\n\npub fn helper() {}\n"),
+            ("main.rs", "fn main() {\n    helper();\n    let p: Point = Point::new();\n    let x = p.x;\n    shapes::helper();\n}\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "main.rs", "helper();", 0), at("shapes.rs", 10));
+        assert_eq!(resolve(&graph, &files, "main.rs", "Point =", 0), at("shapes.rs", 0));
+        assert_eq!(resolve(&graph, &files, "main.rs", "Point::new", 7), at("shapes.rs", 5));
+        assert_eq!(resolve(&graph, &files, "main.rs", "p.x", 2), at("shapes.rs", 1));
+        assert_eq!(resolve(&graph, &files, "main.rs", "shapes::helper", 8), at("shapes.rs", 10));
+    }
+
+    #[test]
+    fn rust_paths_tell_same_name_methods_apart() {
+        let files = [
+            ("types.rs", "struct A;\nstruct B;\n\nimpl A {\n    fn new() {}\n    fn make() { Self::new(); }\n}\n\nimpl B {\n    fn new() {}\n}\n"),
+            ("main.rs", "fn main() {\n    A::new();\n    B::new();\n    new();\n}\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "main.rs", "A::new", 3), at("types.rs", 4));
+        assert_eq!(resolve(&graph, &files, "main.rs", "B::new", 3), at("types.rs", 9));
+        assert_eq!(resolve(&graph, &files, "types.rs", "Self::new", 6), at("types.rs", 4));
+        // Associated functions are not in scope unqualified
+        assert_eq!(resolve(&graph, &files, "main.rs", "    new()", 4), []);
+    }
+
+    #[test]
+    fn names_do_not_resolve_across_languages() {
+        let files = [
+            ("e.rs", "fn f() {}\n\nfn g() {\n    f();\n}\n"),
+            ("g.go", "package main\n\nfunc f() {}\n\nfunc main() {\n\tf()\n}\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "g.go", "\tf()", 1), at("g.go", 2));
+        assert_eq!(resolve(&graph, &files, "e.rs", "    f()", 4), at("e.rs", 0));
+    }
+
+    #[test]
+    fn go_names_resolve_within_their_package() {
+        let files = [
+            ("shapes/shapes.go", "package shapes\n\ntype Point struct {\n\tX int\n}\n\nfunc (p Point) Len() int {\n\treturn p.X\n}\n\nfunc New() Point {\n\treturn Point{}\n}\n"),
+            ("main.go", "package main\n\nimport \"example.com/app/shapes\"\n\nfunc New() {}\n\nfunc main() {\n\tvar p shapes.Point = shapes.New()\n\t_ = p.X\n\tp.Len()\n\tNew()\n}\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "main.go", "shapes.Point", 7), at("shapes/shapes.go", 2));
+        assert_eq!(resolve(&graph, &files, "main.go", "shapes.New", 7), at("shapes/shapes.go", 10));
+        assert_eq!(resolve(&graph, &files, "main.go", "p.X", 2), at("shapes/shapes.go", 3));
+        assert_eq!(resolve(&graph, &files, "main.go", "p.Len", 2), at("shapes/shapes.go", 6));
+        // Another package's names need its qualifier
+        assert_eq!(resolve(&graph, &files, "main.go", "\tNew()", 1), at("main.go", 4));
+    }
+
+    #[test]
+    fn ruby_calls_constants_and_members_resolve_across_files() {
+        let files = [
+            ("shapes.rb", "module Geometry\n  class Shape\n    def area\n    end\n\n    def self.unit\n    end\n  end\nend\n\ndef helper\nend\n"),
+            ("main.rb", "shape = Geometry::Shape.unit\nshape.area\nhelper()\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "main.rb", "Shape.unit", 0), at("shapes.rb", 1));
+        assert_eq!(resolve(&graph, &files, "main.rb", "unit", 0), at("shapes.rb", 5));
+        assert_eq!(resolve(&graph, &files, "main.rb", "shape.area", 6), at("shapes.rb", 2));
+        assert_eq!(resolve(&graph, &files, "main.rb", "helper", 0), at("shapes.rb", 10));
+    }
+
+    #[test]
+    fn python_calls_types_and_attributes_resolve_across_files() {
+        let files = [
+            ("shapes.py", "class Shape:\n    def area(self):\n        pass\n\n\ndef helper():\n    pass\n"),
+            ("main.py", "import shapes\nfrom shapes import Shape, helper\n\n\ndef run(s: Shape):\n    helper()\n    s.area\n    shapes.helper()\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "main.py", "Shape)", 0), at("shapes.py", 0));
+        assert_eq!(resolve(&graph, &files, "main.py", "    helper()", 4), at("shapes.py", 5));
+        assert_eq!(resolve(&graph, &files, "main.py", "s.area", 2), at("shapes.py", 1));
+        assert_eq!(resolve(&graph, &files, "main.py", "shapes.helper", 7), at("shapes.py", 5));
+    }
+
+    #[test]
+    fn javascript_calls_types_and_members_resolve_across_files() {
+        let files = [
+            ("shapes.js", "class Shape {\n  area() {}\n}\n\nfunction helper() {}\n"),
+            ("main.js", "class Square extends Shape {}\nhelper();\nnew Square().area;\n"),
+        ];
+        let graph = fallback_graph(&files);
+
+        assert_eq!(resolve(&graph, &files, "main.js", "Shape", 0), at("shapes.js", 0));
+        assert_eq!(resolve(&graph, &files, "main.js", "helper", 0), at("shapes.js", 4));
+        assert_eq!(resolve(&graph, &files, "main.js", ".area", 1), at("shapes.js", 1));
+        // Methods are only reached as members
+        assert_eq!(resolve(&graph, &files, "main.js", "Square()", 0), at("main.js", 0));
+    }
+}
@@ -62,111 +62,69 @@ impl Language {
         }
     }
 
-    /// Get the appropriate tree-sitter parser for this language
-    pub fn get_parser(&self) -> Option<ts::Parser> {
-        let mut parser = ts::Parser::new();
+    /// Determine language from its name, e.g. `rust` or `c#`, or from a file extension
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "rust" => Self::Rust,
+            "python" => Self::Python,
+            "javascript" => Self::JavaScript,
+            "typescript" => Self::TypeScript,
+            "java" => Self::Java,
+            "swift" => Self::Swift,
+            "objective-c" | "objc" => Self::ObjectiveC,
+            "scala" => Self::Scala,
+            "yaml" => Self::YAML,
+            "go" => Self::Go,
+            "jsdoc" => Self::JSDoc,
+            "regex" => Self::Regex,
+            "c#" | "csharp" => Self::CSharp,
+            "ruby" => Self::Ruby,
+            "markdown" => Self::Markdown,
+            "lua" => Self::Lua,
+            "dart" => Self::Dart,
+            // CSS, Zig, PHP, XML, Bash, JSON and HTML are named like their extension
+            other => Self::from_extension(other),
+        }
+    }
 
+    /// Get the tree-sitter grammar for this language
+    pub fn get_tree_sitter_language(&self) -> Option<ts::Language> {
         match self {
-            Self::Rust => {
-                parser.set_language(&tree_sitter_rust::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Python => {
-                parser.set_language(&tree_sitter_python::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::JavaScript => {
-                parser.set_language(&tree_sitter_javascript::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::TypeScript => {
-                parser.set_language(&tree_sitter_typescript::LANGUAGE_TSX.into()).ok()?;
-                Some(parser)
-            }
-            Self::Java => {
-                parser.set_language(&tree_sitter_java::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Go => {
-                parser.set_language(&tree_sitter_go::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::PHP => {
-                parser.set_language(&tree_sitter_php::LANGUAGE_PHP.into()).ok()?;
-                Some(parser)
-            }
-            Self::XML => {
-                parser.set_language(&tree_sitter_xml::LANGUAGE_XML.into()).ok()?;
-                Some(parser)
-            }
-            Self::Swift => {
-                parser.set_language(&tree_sitter_swift::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::ObjectiveC => {
-                parser.set_language(&tree_sitter_objc::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::CSS => {
-                parser.set_language(&tree_sitter_css::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Scala => {
-                parser.set_language(&tree_sitter_scala::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Zig => {
-                parser.set_language(&tree_sitter_zig::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::YAML => {
-                parser.set_language(&tree_sitter_yaml::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::JSDoc => {
-                parser.set_language(&tree_sitter_jsdoc::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Bash => {
-                parser.set_language(&tree_sitter_bash::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::JSON => {
-                parser.set_language(&tree_sitter_json::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::HTML => {
-                parser.set_language(&tree_sitter_html::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Regex => {
-                parser.set_language(&tree_sitter_regex::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::CSharp => {
-                parser.set_language(&tree_sitter_c_sharp::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Ruby => {
-                parser.set_language(&tree_sitter_ruby::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Markdown => {
-                parser.set_language(&tree_sitter_md::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Lua => {
-                parser.set_language(&tree_sitter_lua::LANGUAGE.into()).ok()?;
-                Some(parser)
-            }
-            Self::Dart => {
-                parser.set_language(&tree_sitter_dart::language()).ok()?;
-                Some(parser)
-            }
+            Self::Rust => Some(tree_sitter_rust::LANGUAGE.into()),
+            Self::Python => Some(tree_sitter_python::LANGUAGE.into()),
+            Self::JavaScript => Some(tree_sitter_javascript::LANGUAGE.into()),
+            Self::TypeScript => Some(tree_sitter_typescript::LANGUAGE_TSX.into()),
+            Self::Java => Some(tree_sitter_java::LANGUAGE.into()),
+            Self::Go => Some(tree_sitter_go::LANGUAGE.into()),
+            Self::PHP => Some(tree_sitter_php::LANGUAGE_PHP.into()),
+            Self::XML => Some(tree_sitter_xml::LANGUAGE_XML.into()),
+            Self::Swift => Some(tree_sitter_swift::LANGUAGE.into()),
+            Self::ObjectiveC => Some(tree_sitter_objc::LANGUAGE.into()),
+            Self::CSS => Some(tree_sitter_css::LANGUAGE.into()),
+            Self::Scala => Some(tree_sitter_scala::LANGUAGE.into()),
+            Self::Zig => Some(tree_sitter_zig::LANGUAGE.into()),
+            Self::YAML => Some(tree_sitter_yaml::LANGUAGE.into()),
+            Self::JSDoc => Some(tree_sitter_jsdoc::LANGUAGE.into()),
+            Self::Bash => Some(tree_sitter_bash::LANGUAGE.into()),
+            Self::JSON => Some(tree_sitter_json::LANGUAGE.into()),
+            Self::HTML => Some(tree_sitter_html::LANGUAGE.into()),
+            Self::Regex => Some(tree_sitter_regex::LANGUAGE.into()),
+            Self::CSharp => Some(tree_sitter_c_sharp::LANGUAGE.into()),
+            Self::Ruby => Some(tree_sitter_ruby::LANGUAGE.into()),
+            Self::Markdown => Some(tree_sitter_md::LANGUAGE.into()),
+            Self::Lua => Some(tree_sitter_lua::LANGUAGE.into()),
+            Self::Dart => Some(tree_sitter_dart::language()),
             Self::Unknown => None,
         }
     }
 
+    /// Get the appropriate tree-sitter parser for this language
+    pub fn get_parser(&self) -> Option<ts::Parser> {
+        let mut parser = ts::Parser::new();
+        parser.set_language(&self.get_tree_sitter_language()?).ok()?;
+        Some(parser)
+    }
+
     /// Get the stack-graphs language definition if available in the stack-graphs/languages directory
     pub fn get_stack_graphs_language(&self) -> Option<&'static str> {
         match self {
@@ -1,12 +1,36 @@
 pub mod languages;
+pub mod discovery;
 pub mod indexer;
 pub mod output;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+#[cfg(feature = "mcp")]
+pub mod mcp;
+pub mod query;
+pub mod report;
+pub mod stats;
+pub mod storage;
+pub mod symbols;
+#[cfg(test)]
+mod test_support;
 pub mod tsg_generator;
+pub mod tsg_loader;
+#[cfg(feature = "watch")]
+pub mod watch;
 
-use std::path::PathBuf;
-use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use anyhow::{anyhow, Context, Result};
 use log::{error, info, warn};
-use stack_graphs::graph::StackGraph;
+use stack_graphs::arena::Handle;
+use stack_graphs::graph::{File, StackGraph};
+
+use languages::Language;
+use query::{FileReferences, Location, QueryEngine};
+use symbols::{MatchMode, OutlineItem, Symbol};
+
+pub use output::{GraphWriter, OutputFormat};
+pub use report::IndexReport;
 
 /// Configuration for the TSG indexer
 #[derive(Debug, Clone)]
@@ -14,10 +38,14 @@ pub struct IndexerConfig {
     /// Path to a file or directory to index
     pub path: PathBuf,
     
-    /// Output format (json or dot)
-    pub format: String,
+    /// Output format
+    pub format: OutputFormat,
+
+    /// Writer to output the graph with instead of the one for `format`
+    pub writer: Option<Arc<dyn GraphWriter>>,
     
-    /// Output file path (if not provided, will print to stdout)
+    /// Output file path (if not provided, will print to stdout); the directory to write
+    /// CSV files to for the neo4j format
     pub output: Option<PathBuf>,
     
     /// Verbose mode
@@ -28,23 +56,273 @@ pub struct IndexerConfig {
     
     /// Whether to overwrite existing TSG files
     pub force_overwrite: bool,
+
+    /// Project root that file names in the graph are relative to; defaults to `path`
+    /// for directories and its parent directory for files
+    pub root: Option<PathBuf>,
+
+    /// Abort on the first file that fails to index instead of recording it in the
+    /// report and carrying on
+    pub fail_fast: bool,
+
+    /// Path to a SQLite index database; when set, only files that changed since the
+    /// last run are re-indexed
+    pub db: Option<PathBuf>,
+
+    /// Number of worker threads that parse and build files; 0 uses one per CPU
+    pub threads: usize,
+
+    /// Globs a file must match one of to be indexed; empty means every file
+    pub include: Vec<String>,
+
+    /// Globs for files and directories to leave out, on top of `.gitignore`, `.ignore`
+    /// and `.tsgignore`; defaults to common dependency and build directories
+    pub exclude: Vec<String>,
+
+    /// Languages to index; empty means every supported language
+    pub languages: Vec<Language>,
+
+    /// Part of the graph to draw when the output format is DOT
+    pub dot_filter: output::DotFilter,
 }
 
 impl Default for IndexerConfig {
     fn default() -> Self {
         Self {
             path: PathBuf::new(),
-            format: "json".to_string(),
+            format: OutputFormat::Json,
+            writer: None,
             output: None,
             verbose: false,
             generate_tsg: false,
             force_overwrite: false,
+            root: None,
+            fail_fast: false,
+            db: None,
+            threads: 0,
+            include: Vec::new(),
+            exclude: discovery::default_excludes(),
+            languages: Vec::new(),
+            dot_filter: output::DotFilter::default(),
+        }
+    }
+}
+
+/// Indexes a project into a stack graph, keeping the graph for the caller
+///
+/// Build one with [`Indexer::builder`], or from a full [`IndexerConfig`] with
+/// [`Indexer::new`]; only the indexing options of the configuration are used.
+#[derive(Debug, Clone)]
+pub struct Indexer {
+    config: IndexerConfig,
+    discovery: discovery::FileDiscovery,
+}
+
+impl Indexer {
+    /// Start building an indexer
+    pub fn builder() -> IndexerBuilder {
+        IndexerBuilder::default()
+    }
+
+    /// Create an indexer from a configuration, failing on invalid include or exclude globs
+    pub fn new(config: IndexerConfig) -> Result<Self> {
+        let discovery = file_discovery(&config)?;
+        Ok(Self { config, discovery })
+    }
+
+    /// The configuration this indexer runs with
+    pub fn config(&self) -> &IndexerConfig {
+        &self.config
+    }
+
+    /// Index the configured path
+    ///
+    /// With a database configured, the database is brought up to date first and the
+    /// graph is loaded back out of it.
+    pub fn index(&self) -> Result<Index> {
+        self.index_with(|_, _| Ok(()))
+    }
+
+    /// [`Indexer::index`], calling `on_file` as [`build_stack_graph_with`] does
+    pub fn index_with<F>(&self, on_file: F) -> Result<Index>
+    where
+        F: FnMut(&StackGraph, Handle<File>) -> Result<()>,
+    {
+        let (stack_graph, report) = build_with(&self.config, &self.discovery, on_file)?;
+        let root = self.config.root.clone().unwrap_or_else(|| indexer::default_root(&self.config.path));
+        Ok(Index {
+            stack_graph: Arc::new(stack_graph),
+            report,
+            root,
+            engine: Mutex::new(None),
+        })
+    }
+}
+
+/// Builder for an [`Indexer`]
+#[derive(Debug, Clone, Default)]
+pub struct IndexerBuilder {
+    root: Option<PathBuf>,
+    path: Option<PathBuf>,
+    config: IndexerConfig,
+}
+
+impl IndexerBuilder {
+    /// Project root that file names in the graph are relative to; the whole root is
+    /// indexed unless [`IndexerBuilder::path`] is set
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// File or directory to index, if only part of the root should be
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Only index files in these languages
+    pub fn languages(mut self, languages: impl IntoIterator<Item = Language>) -> Self {
+        self.config.languages = languages.into_iter().collect();
+        self
+    }
+
+    /// Only index files matching one of these globs
+    pub fn include<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.config.include = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Leave out files and directories matching these globs, instead of the default
+    /// dependency and build directories
+    pub fn exclude<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.config.exclude = globs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Keep the graph in a SQLite index database, so later runs only re-index changed files
+    pub fn db(mut self, db: impl Into<PathBuf>) -> Self {
+        self.config.db = Some(db.into());
+        self
+    }
+
+    /// Number of worker threads; 0 uses one per CPU
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = threads;
+        self
+    }
+
+    /// Abort on the first file that fails to index
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    /// Create the indexer, failing if no root or path is set or a glob is invalid
+    pub fn build(self) -> Result<Indexer> {
+        let path = self.path.clone().or_else(|| self.root.clone())
+            .ok_or_else(|| anyhow!("Indexer needs a root or path to index"))?;
+
+        Indexer::new(IndexerConfig {
+            path,
+            root: self.root,
+            ..self.config
+        })
+    }
+}
+
+/// A stack graph built by an [`Indexer`], with its report and queries over it
+pub struct Index {
+    stack_graph: Arc<StackGraph>,
+    report: IndexReport,
+    root: PathBuf,
+    /// Engine that [`Index::find_definitions`] and the like share, built on first use so
+    /// that partial paths are only computed once
+    engine: Mutex<Option<QueryEngine<'static>>>,
+}
+
+impl Index {
+    /// The indexed stack graph
+    pub fn graph(&self) -> &StackGraph {
+        &self.stack_graph
+    }
+
+    /// Which files were indexed, skipped or failed
+    pub fn report(&self) -> &IndexReport {
+        &self.report
+    }
+
+    /// Directory that file names in the graph are relative to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The stack graph and report, taken out of the index
+    pub fn into_parts(self) -> (StackGraph, IndexReport) {
+        // The cached engine is the only other holder of the graph
+        drop(self.engine);
+        match Arc::try_unwrap(self.stack_graph) {
+            Ok(stack_graph) => (stack_graph, self.report),
+            Err(_) => unreachable!("the graph is only shared with the index's engine"),
+        }
+    }
+
+    /// A query engine over the graph, for answering many queries without recomputing
+    /// partial paths each time
+    pub fn query(&self) -> Result<QueryEngine<'_>> {
+        QueryEngine::new(&self.stack_graph)
+    }
+
+    /// Find the definitions of the reference at a zero-based line and column in a file
+    pub fn find_definitions(&self, path: &Path, line: usize, column: usize) -> Result<Vec<Location>> {
+        let file_name = indexer::file_name_for(&self.root, path);
+        self.with_engine(|engine| engine.find_definitions(&file_name, line, column))
+    }
+
+    /// Find every reference to the definition at a zero-based line and column in a file
+    pub fn find_references(&self, path: &Path, line: usize, column: usize) -> Result<Vec<FileReferences>> {
+        let file_name = indexer::file_name_for(&self.root, path);
+        self.with_engine(|engine| engine.find_references(&file_name, line, column))
+    }
+
+    /// Outline of a file: its definitions as a tree of nested definitions
+    pub fn outline(&self, path: &Path) -> Result<Vec<OutlineItem>> {
+        let file_name = indexer::file_name_for(&self.root, path);
+        self.with_engine(|engine| engine.outline(&file_name))
+    }
+
+    /// Run a query on the index's engine, building it if this is the first query
+    fn with_engine<T>(&self, query: impl FnOnce(&mut QueryEngine<'static>) -> Result<T>) -> Result<T> {
+        let mut engine = self.engine.lock().unwrap_or_else(PoisonError::into_inner);
+        if engine.is_none() {
+            *engine = Some(QueryEngine::shared(Arc::clone(&self.stack_graph))?);
         }
+        query(engine.as_mut().expect("engine was just built"))
+    }
+
+    /// Every definition in the graph, in file and source order
+    pub fn symbols(&self) -> Vec<Symbol> {
+        symbols::symbols(&self.stack_graph)
+    }
+
+    /// Symbols whose name matches a query, best match first
+    pub fn search(&self, query: &str, mode: MatchMode) -> Result<Vec<Symbol>> {
+        let symbols = self.symbols();
+        Ok(symbols::search(&symbols, query, mode)?.into_iter().cloned().collect())
+    }
+
+    /// Write the graph with a writer, to `output_path` or standard output
+    pub fn write(&self, writer: &dyn GraphWriter, output_path: Option<&Path>) -> Result<()> {
+        writer.write(&self.stack_graph, &self.root, output_path)
     }
 }
 
 /// Main entry point for the TSG indexer library
-pub fn run_indexer(config: IndexerConfig) -> Result<()> {
+///
+/// Indexes the configured path with an [`Indexer`], generating TSG files first if
+/// enabled, and writes the graph in the configured format. Returns a report of which
+/// files were indexed, skipped or failed.
+pub fn run_indexer(config: IndexerConfig) -> Result<IndexReport> {
     if config.verbose {
         info!("Starting TSG indexer");
         info!("Indexing path: {}", config.path.display());
@@ -55,6 +333,9 @@ pub fn run_indexer(config: IndexerConfig) -> Result<()> {
         error!("Path does not exist: {}", config.path.display());
         anyhow::bail!("Path does not exist: {}", config.path.display());
     }
+
+    let indexer = Indexer::new(config)?;
+    let config = indexer.config();
     
     // Initialize the TSG generator if enabled
     if config.generate_tsg {
@@ -66,35 +347,8 @@ pub fn run_indexer(config: IndexerConfig) -> Result<()> {
         let tsg_root = PathBuf::from("languages");
         let generator = tsg_generator::TsgGenerator::new(tsg_root, config.force_overwrite);
         
-        // Scan the path to detect languages
-        let detected_languages = if config.path.is_file() {
-            let extension = config.path.extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or("");
-            
-            let language = languages::Language::from_extension(extension);
-            if language != languages::Language::Unknown {
-                vec![language]
-            } else {
-                vec![]
-            }
-        } else {
-            // Scan the directory for all file extensions and get unique languages
-            let mut detected = std::collections::HashSet::new();
-            for entry in walkdir::WalkDir::new(&config.path)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.file_type().is_file()) {
-                
-                if let Some(extension) = entry.path().extension().and_then(|ext| ext.to_str()) {
-                    let language = languages::Language::from_extension(extension);
-                    if language != languages::Language::Unknown {
-                        detected.insert(language);
-                    }
-                }
-            }
-            detected.into_iter().collect()
-        };
+        // Scan the path to detect languages, walking the same files that get indexed
+        let detected_languages = indexer.discovery.detect_languages(&config.path)?;
         
         // Generate TSG files for detected languages
         for language in &detected_languages {
@@ -117,32 +371,170 @@ pub fn run_indexer(config: IndexerConfig) -> Result<()> {
         }
     }
     
-    // Create a new stack graph
-    let mut stack_graph = StackGraph::new();
-    
-    // Index the path
-    if config.path.is_file() {
-        indexer::index_file(&mut stack_graph, &config.path)
-            .with_context(|| format!("Failed to index file: {}", config.path.display()))?;
-    } else if config.path.is_dir() {
-        indexer::index_directory(&mut stack_graph, &config.path)
-            .with_context(|| format!("Failed to index directory: {}", config.path.display()))?;
-    }
-    
-    // Output the stack graph
-    match config.format.as_str() {
-        "json" => output::output_json(&stack_graph, config.output.as_deref())?,
-        "dot" => output::output_dot(&stack_graph, config.output.as_deref())?,
-        _ => {
-            warn!("Unsupported output format: {}. Using JSON instead.", config.format);
-            output::output_json(&stack_graph, config.output.as_deref())?
+    // NDJSON is written out a file at a time from each file's own graph as it is stored
+    // in an index database, a temporary one unless a database is configured, so no
+    // merged graph is kept while indexing; paths are resolved from the database at the
+    // end. The other formats are written once the whole graph is built.
+    if config.writer.is_none() && config.format == OutputFormat::Ndjson {
+        // The temporary database is removed when this goes out of scope
+        let mut temporary = None;
+        let db_path = match &config.db {
+            Some(db_path) => db_path.clone(),
+            None => temporary.insert(tempfile::tempdir()?).path().join("index.db"),
+        };
+
+        let mut database = storage::IndexDatabase::open(&db_path)?;
+        let mut stream = output::ndjson_stream(config.output.as_deref())?;
+        let report = update_database(&mut database, config, &indexer.discovery, |stack_graph, file| {
+            stream.write_file(stack_graph, file)
+        })?;
+        stream.finish_with(&mut QueryEngine::from_database(&database)?)?;
+
+        if config.verbose {
+            info!("Index report: {}", report.summary());
         }
+        return Ok(report);
+    }
+
+    let index = indexer.index()?;
+
+    // Output the stack graph; SCIP, LSIF and tag file paths are relative to the project root
+    match &config.writer {
+        Some(writer) => index.write(writer.as_ref(), config.output.as_deref())?,
+        None => index.write(config.format.writer(&config.dot_filter).as_ref(), config.output.as_deref())?,
     }
     
     if config.verbose {
         info!("Stack graph created successfully!");
-        info!("Number of files: {}", stack_graph.iter_files().count());
+        info!("Number of files: {}", index.graph().iter_files().count());
+        info!("Index report: {}", index.report().summary());
     }
     
-    Ok(())
-}
\ No newline at end of file
+    Ok(index.into_parts().1)
+}
+
+/// Index the configured path and return the resulting stack graph with its report
+///
+/// This is [`run_indexer`] without TSG generation or output. With `config.db` set, the
+/// database is brought up to date first and the graph is loaded back out of it.
+pub fn build_stack_graph(config: &IndexerConfig) -> Result<(StackGraph, IndexReport)> {
+    build_stack_graph_with(config, |_, _| Ok(()))
+}
+
+/// [`build_stack_graph`], calling `on_file` with each file's own graph as soon as it has
+/// been indexed
+///
+/// With `config.db` set, this is as each file is stored in the database, and files that
+/// are unchanged since they were stored are not reported.
+pub fn build_stack_graph_with<F>(config: &IndexerConfig, on_file: F) -> Result<(StackGraph, IndexReport)>
+where
+    F: FnMut(&StackGraph, Handle<File>) -> Result<()>,
+{
+    let discovery = file_discovery(config)?;
+    build_with(config, &discovery, on_file)
+}
+
+/// Discovery of the files a configuration selects
+fn file_discovery(config: &IndexerConfig) -> Result<discovery::FileDiscovery> {
+    Ok(discovery::FileDiscovery::new(&config.include, &config.exclude)?.with_languages(&config.languages))
+}
+
+/// Index the configured path, finding files with `discovery`
+fn build_with<F>(config: &IndexerConfig, discovery: &discovery::FileDiscovery, mut on_file: F) -> Result<(StackGraph, IndexReport)>
+where
+    F: FnMut(&StackGraph, Handle<File>) -> Result<()>,
+{
+    if !config.path.exists() {
+        anyhow::bail!("Path does not exist: {}", config.path.display());
+    }
+
+    let root = config.root.clone().unwrap_or_else(|| indexer::default_root(&config.path));
+
+    match &config.db {
+        Some(db_path) => {
+            // Bring the database up to date and load the graph back out of it
+            let mut database = storage::IndexDatabase::open(db_path)?;
+            let report = update_database(&mut database, config, discovery, on_file)?;
+
+            Ok((database.load_stack_graph()?, report))
+        }
+        None => {
+            // Create a new stack graph
+            let mut stack_graph = StackGraph::new();
+            let mut report = IndexReport::default();
+
+            // Index the path
+            if config.path.is_file() {
+                let file_name = indexer::file_name_for(&root, &config.path);
+                let result = match indexer::index_file_isolated(&root, &config.path) {
+                    Ok((file_graph, language, outcome)) => {
+                        if let Some(file) = file_graph.get_file(&file_name) {
+                            on_file(&file_graph, file)?;
+                        }
+                        indexer::merge_graph(&mut stack_graph, &file_graph, &language).map(|()| outcome)
+                    }
+                    Err(e) => Err(e),
+                };
+                if config.fail_fast {
+                    let outcome = result
+                        .with_context(|| format!("Failed to index file: {}", config.path.display()))?;
+                    report.record(file_name, Ok(outcome));
+                } else {
+                    report.record(file_name, result);
+                }
+            } else if config.path.is_dir() {
+                report = indexer::index_directory_parallel_with(
+                    &mut stack_graph, &root, &config.path, discovery, config.fail_fast, config.threads, on_file,
+                )
+                    .with_context(|| format!("Failed to index directory: {}", config.path.display()))?;
+            }
+
+            Ok((stack_graph, report))
+        }
+    }
+}
+
+/// Bring an index database up to date with the configured path, calling `on_file` with
+/// each file's own graph as it is stored
+fn update_database<F>(
+    database: &mut storage::IndexDatabase,
+    config: &IndexerConfig,
+    discovery: &discovery::FileDiscovery,
+    on_file: F,
+) -> Result<IndexReport>
+where
+    F: FnMut(&StackGraph, Handle<File>) -> Result<()>,
+{
+    let root = config.root.clone().unwrap_or_else(|| indexer::default_root(&config.path));
+    database.update_with(&root, &config.path, discovery, config.fail_fast, config.threads, on_file)
+        .with_context(|| format!("Failed to update index database: {}", database.path().display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::project;
+
+    #[test]
+    fn index_queries_share_one_engine() {
+        let dir = project(&[("app.py", "def greet():\n    pass\n\ngreet()\n")]);
+        let index = Indexer::builder().root(dir.path()).build().unwrap().index().unwrap();
+        assert!(index.engine.lock().unwrap().is_none());
+
+        let definitions = index.find_definitions(Path::new("app.py"), 3, 0).unwrap();
+        assert_eq!(definitions.len(), 1);
+        assert_eq!(definitions[0].span.start_line, 0);
+        assert!(index.engine.lock().unwrap().is_some());
+
+        // Absolute paths name the same file as relative ones
+        let references = index.find_references(&dir.path().join("app.py"), 0, 4).unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].references[0].location.span.start_line, 3);
+        let outline = index.outline(Path::new("app.py")).unwrap();
+        assert_eq!(outline.iter().map(|item| item.name.as_str()).collect::<Vec<_>>(), ["greet"]);
+
+        let (stack_graph, report) = index.into_parts();
+        assert!(stack_graph.get_file("app.py").is_some());
+        assert!(!report.has_failures());
+    }
+}
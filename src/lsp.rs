@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types as lsp;
+use tower_lsp::{Client, LanguageServer, LspService, Server};
+
+use crate::discovery::{self, FileDiscovery};
+use crate::indexer;
+use crate::query::{QueryEngine, Span};
+use crate::report::FileOutcome;
+use crate::storage::{self, IndexDatabase};
+use crate::symbols::{self, MatchMode, OutlineItem};
+
+/// Most results returned for a single `workspace/symbol` request
+const MAX_WORKSPACE_SYMBOLS: usize = 1000;
+
+/// How long a document has to stay unchanged before edits to it are re-indexed
+const CHANGE_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Settings a client can pass as `initializationOptions`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ServerOptions {
+    /// Index database to keep the workspace's graphs in; defaults to a file in the
+    /// system temp directory named after the workspace root
+    pub db: Option<PathBuf>,
+    /// Globs a file must match one of to be indexed
+    pub include: Vec<String>,
+    /// Globs for files and directories to leave out; defaults to common dependency and
+    /// build directories
+    pub exclude: Option<Vec<String>>,
+    /// Number of worker threads for the initial index; 0 uses one per CPU
+    pub threads: usize,
+}
+
+/// An indexed workspace and the documents the client has open in it
+struct Workspace {
+    root: PathBuf,
+    database: IndexDatabase,
+    /// Query engine over the database, recreated after the database changes
+    engine: Option<QueryEngine<'static>>,
+    /// Current text of open documents, which may differ from what is on disk
+    documents: HashMap<PathBuf, String>,
+    /// Whether the client accepted UTF-8 columns; otherwise columns are UTF-16
+    utf8_positions: bool,
+}
+
+impl Workspace {
+    /// Open the workspace's index database and bring it up to date
+    fn open(root: PathBuf, options: &ServerOptions, utf8_positions: bool) -> Result<Self> {
+        let exclude = options.exclude.clone().unwrap_or_else(discovery::default_excludes);
+        let discovery = FileDiscovery::new(&options.include, &exclude)?;
+
+        let db_path = options.db.clone().unwrap_or_else(|| storage::default_db_path(&root));
+        let mut database = IndexDatabase::open(&db_path)?;
+        let report = database.update(&root, &root, &discovery, false, options.threads)
+            .with_context(|| format!("Failed to index workspace: {}", root.display()))?;
+        info!("Indexed workspace {}: {}", root.display(), report.summary());
+
+        Ok(Self {
+            root,
+            database,
+            engine: None,
+            documents: HashMap::new(),
+            utf8_positions,
+        })
+    }
+
+    fn engine(&mut self) -> Result<&mut QueryEngine<'static>> {
+        if self.engine.is_none() {
+            self.engine = Some(QueryEngine::from_database(&self.database)?);
+        }
+        Ok(self.engine.as_mut().expect("engine was just created"))
+    }
+
+    /// Re-index a file from `content`, or from disk if no content is given
+    fn reindex(&mut self, path: &Path, content: Option<&str>) {
+        if !path.starts_with(&self.root) {
+            return;
+        }
+
+        let result = match content {
+            Some(content) => self.database.index_source(&self.root, path, content),
+            None if path.exists() => self.database.index_file(&self.root, path),
+            None => self.database.remove_file(&indexer::file_name_for(&self.root, path))
+                .map(|_| FileOutcome::Skipped("file was deleted".to_string())),
+        };
+
+        match result {
+            Ok(outcome) => info!("Re-indexed {}: {:?}", path.display(), outcome),
+            Err(e) => warn!("Failed to re-index {}: {:#}", path.display(), e),
+        }
+
+        // The engine's reader may have cached the old graph
+        self.engine = None;
+    }
+
+    /// Path, file name and zero-based UTF-8 position for an LSP document position
+    fn position(&self, params: &lsp::TextDocumentPositionParams) -> Result<(String, usize, usize)> {
+        let path = params.text_document.uri.to_file_path()
+            .map_err(|_| anyhow!("Not a file URI: {}", params.text_document.uri))?;
+        let line = params.position.line as usize;
+        let mut column = params.position.character as usize;
+
+        if !self.utf8_positions {
+            let text = self.line_text(&path, line).unwrap_or_default();
+            column = utf16_to_utf8_column(&text, column);
+        }
+
+        Ok((indexer::file_name_for(&self.root, &path), line, column))
+    }
+
+    /// LSP location for a span in an indexed file
+    fn location(&self, file: &str, span: &Span) -> Option<lsp::Location> {
+        let path = self.root.join(file);
+        let uri = lsp::Url::from_file_path(&path).ok()?;
+        Some(lsp::Location::new(uri, self.range(&path, span)))
+    }
+
+    /// LSP range for a span in a file, in the client's position encoding
+    fn range(&self, path: &Path, span: &Span) -> lsp::Range {
+        let position = |line: usize, column: usize| {
+            let column = if self.utf8_positions {
+                column
+            } else {
+                utf8_to_utf16_column(&self.line_text(path, line).unwrap_or_default(), column)
+            };
+            lsp::Position::new(line as u32, column as u32)
+        };
+
+        lsp::Range::new(
+            position(span.start_line, span.start_column),
+            position(span.end_line, span.end_column),
+        )
+    }
+
+    /// LSP document symbol for an outline item and the items nested in it
+    fn document_symbol(&self, path: &Path, item: &OutlineItem) -> lsp::DocumentSymbol {
+        // The range has to contain the name, which a definiens may not
+        let range = item.range.filter(|range| range.encloses(&item.span)).unwrap_or(item.span);
+
+        #[allow(deprecated)]
+        lsp::DocumentSymbol {
+            name: item.name.clone(),
+            detail: None,
+            kind: symbol_kind(item.kind.as_deref()),
+            tags: None,
+            deprecated: None,
+            range: self.range(path, &range),
+            selection_range: self.range(path, &item.span),
+            children: Some(item.children.iter().map(|child| self.document_symbol(path, child)).collect()),
+        }
+    }
+
+    /// Text of a line in a file, from the open document if there is one
+    fn line_text(&self, path: &Path, line: usize) -> Option<String> {
+        let on_disk;
+        let text = match self.documents.get(path) {
+            Some(text) => text.as_str(),
+            None => {
+                on_disk = fs::read_to_string(path).ok()?;
+                on_disk.as_str()
+            }
+        };
+
+        text.lines().nth(line).map(str::to_string)
+    }
+}
+
+/// Convert a UTF-16 column within a line to a UTF-8 byte offset
+fn utf16_to_utf8_column(line: &str, column: usize) -> usize {
+    let mut utf16 = 0;
+    for (offset, c) in line.char_indices() {
+        if utf16 >= column {
+            return offset;
+        }
+        utf16 += c.len_utf16();
+    }
+    line.len()
+}
+
+/// Convert a UTF-8 byte offset within a line to a UTF-16 column
+fn utf8_to_utf16_column(line: &str, column: usize) -> usize {
+    line.char_indices()
+        .take_while(|(offset, _)| *offset < column)
+        .map(|(_, c)| c.len_utf16())
+        .sum()
+}
+
+/// LSP symbol kind for a definition's syntax type
+fn symbol_kind(syntax_type: Option<&str>) -> lsp::SymbolKind {
+    match syntax_type.unwrap_or("") {
+        "function" => lsp::SymbolKind::FUNCTION,
+        "method" => lsp::SymbolKind::METHOD,
+        "constructor" => lsp::SymbolKind::CONSTRUCTOR,
+        "class" => lsp::SymbolKind::CLASS,
+        "interface" | "trait" => lsp::SymbolKind::INTERFACE,
+        "struct" => lsp::SymbolKind::STRUCT,
+        "enum" => lsp::SymbolKind::ENUM,
+        "module" | "namespace" | "package" | "import" => lsp::SymbolKind::MODULE,
+        "impl" => lsp::SymbolKind::OBJECT,
+        "field" => lsp::SymbolKind::FIELD,
+        "property" => lsp::SymbolKind::PROPERTY,
+        "constant" => lsp::SymbolKind::CONSTANT,
+        "type" => lsp::SymbolKind::TYPE_PARAMETER,
+        _ => lsp::SymbolKind::VARIABLE,
+    }
+}
+
+/// Convert an indexing or query error into a JSON-RPC error response
+fn internal_error(error: anyhow::Error) -> jsonrpc::Error {
+    jsonrpc::Error {
+        code: jsonrpc::ErrorCode::InternalError,
+        message: format!("{:#}", error),
+        data: None,
+    }
+}
+
+/// Workspace shared between request handlers; held across indexing and queries, which
+/// run on blocking threads
+type SharedWorkspace = Arc<tokio::sync::Mutex<Option<Workspace>>>;
+
+/// Run a request against the workspace on a blocking thread, failing if it has not been
+/// initialized
+async fn on_workspace<T, F>(workspace: SharedWorkspace, f: F) -> jsonrpc::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Workspace) -> Result<T> + Send + 'static,
+{
+    let mut workspace = workspace.lock_owned().await;
+    tokio::task::spawn_blocking(move || {
+        let workspace = workspace.as_mut()
+            .ok_or_else(|| jsonrpc::Error::invalid_params("Workspace is not initialized"))?;
+        f(workspace).map_err(internal_error)
+    })
+        .await
+        .map_err(|e| internal_error(anyhow!("Request was aborted: {}", e)))?
+}
+
+/// Language server answering requests from the workspace's stack graphs
+pub struct Backend {
+    client: Client,
+    workspace: SharedWorkspace,
+    /// Latest version of each document with a change waiting to be re-indexed
+    changes: Arc<Mutex<HashMap<PathBuf, i32>>>,
+}
+
+impl Backend {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            workspace: Arc::new(tokio::sync::Mutex::new(None)),
+            changes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Run a request against the workspace, failing if it has not been initialized
+    async fn with_workspace<T, F>(&self, f: F) -> jsonrpc::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Workspace) -> Result<T> + Send + 'static,
+    {
+        on_workspace(self.workspace.clone(), f).await
+    }
+
+    /// Update an open document and re-index it
+    async fn update_document(&self, uri: &lsp::Url, text: Option<String>) {
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+
+        // A pending change is superseded by this update
+        self.changes.lock().unwrap_or_else(|e| e.into_inner()).remove(&path);
+        let _ = self.with_workspace(move |workspace| {
+            match text {
+                Some(text) => {
+                    workspace.reindex(&path, Some(&text));
+                    workspace.documents.insert(path, text);
+                }
+                None => workspace.reindex(&path, None),
+            }
+            Ok(())
+        }).await;
+    }
+
+    /// Re-index a changed document once it has not changed again for [`CHANGE_DEBOUNCE`]
+    fn schedule_change(&self, uri: &lsp::Url, version: i32, text: String) {
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+        self.changes.lock().unwrap_or_else(|e| e.into_inner()).insert(path.clone(), version);
+
+        let (workspace, changes) = (self.workspace.clone(), self.changes.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(CHANGE_DEBOUNCE).await;
+            {
+                let mut changes = changes.lock().unwrap_or_else(|e| e.into_inner());
+                if changes.get(&path) != Some(&version) {
+                    return;
+                }
+                changes.remove(&path);
+            }
+
+            let _ = on_workspace(workspace, move |workspace| {
+                workspace.reindex(&path, Some(&text));
+                workspace.documents.insert(path, text);
+                Ok(())
+            }).await;
+        });
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: lsp::InitializeParams) -> jsonrpc::Result<lsp::InitializeResult> {
+        let root = params.root_uri.as_ref()
+            .or_else(|| params.workspace_folders.as_ref()?.first().map(|folder| &folder.uri))
+            .and_then(|uri| uri.to_file_path().ok())
+            .or_else(|| std::env::current_dir().ok())
+            .ok_or_else(|| jsonrpc::Error::invalid_params("No workspace root"))?;
+
+        let options = match params.initialization_options {
+            Some(value) => serde_json::from_value(value)
+                .map_err(|e| jsonrpc::Error::invalid_params(format!("Invalid initialization options: {}", e)))?,
+            None => ServerOptions::default(),
+        };
+
+        let utf8_positions = params.capabilities.general.as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .is_some_and(|encodings| encodings.contains(&lsp::PositionEncodingKind::UTF8));
+
+        // Indexing the workspace can take a while, so keep it off the async workers
+        let mut slot = self.workspace.clone().lock_owned().await;
+        let workspace = tokio::task::spawn_blocking(move || Workspace::open(root, &options, utf8_positions))
+            .await
+            .map_err(|e| internal_error(anyhow!("Indexing was aborted: {}", e)))?
+            .map_err(internal_error)?;
+        *slot = Some(workspace);
+
+        Ok(lsp::InitializeResult {
+            capabilities: lsp::ServerCapabilities {
+                position_encoding: utf8_positions.then_some(lsp::PositionEncodingKind::UTF8),
+                text_document_sync: Some(lsp::TextDocumentSyncCapability::Options(lsp::TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(lsp::TextDocumentSyncKind::FULL),
+                    save: Some(lsp::TextDocumentSyncSaveOptions::Supported(true)),
+                    ..Default::default()
+                })),
+                definition_provider: Some(lsp::OneOf::Left(true)),
+                references_provider: Some(lsp::OneOf::Left(true)),
+                workspace_symbol_provider: Some(lsp::OneOf::Left(true)),
+                document_symbol_provider: Some(lsp::OneOf::Left(true)),
+                ..Default::default()
+            },
+            server_info: Some(lsp::ServerInfo {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+        })
+    }
+
+    async fn initialized(&self, _: lsp::InitializedParams) {
+        let files = self.with_workspace(|workspace| workspace.database.stored_files()).await;
+        if let Ok(files) = files {
+            self.client.log_message(lsp::MessageType::INFO, format!("Indexed {} file(s)", files.len())).await;
+        }
+    }
+
+    async fn shutdown(&self) -> jsonrpc::Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: lsp::DidOpenTextDocumentParams) {
+        self.update_document(&params.text_document.uri, Some(params.text_document.text)).await;
+    }
+
+    async fn did_change(&self, params: lsp::DidChangeTextDocumentParams) {
+        // Full document sync, so the last change holds the whole text
+        if let Some(change) = params.content_changes.into_iter().last() {
+            self.schedule_change(&params.text_document.uri, params.text_document.version, change.text);
+        }
+    }
+
+    async fn did_save(&self, params: lsp::DidSaveTextDocumentParams) {
+        self.update_document(&params.text_document.uri, params.text).await;
+    }
+
+    async fn did_close(&self, params: lsp::DidCloseTextDocumentParams) {
+        let Ok(path) = params.text_document.uri.to_file_path() else {
+            return;
+        };
+
+        // Unsaved changes are discarded, so go back to what is on disk
+        self.changes.lock().unwrap_or_else(|e| e.into_inner()).remove(&path);
+        let _ = self.with_workspace(move |workspace| {
+            workspace.documents.remove(&path);
+            workspace.reindex(&path, None);
+            Ok(())
+        }).await;
+    }
+
+    async fn goto_definition(
+        &self,
+        params: lsp::GotoDefinitionParams,
+    ) -> jsonrpc::Result<Option<lsp::GotoDefinitionResponse>> {
+        self.with_workspace(move |workspace| {
+            let (file_name, line, column) = workspace.position(&params.text_document_position_params)?;
+            let definitions = workspace.engine()?.find_definitions(&file_name, line, column)?;
+
+            let locations: Vec<_> = definitions.iter()
+                .filter_map(|definition| workspace.location(&definition.file, &definition.span))
+                .collect();
+            Ok((!locations.is_empty()).then_some(lsp::GotoDefinitionResponse::Array(locations)))
+        }).await
+    }
+
+    async fn references(&self, params: lsp::ReferenceParams) -> jsonrpc::Result<Option<Vec<lsp::Location>>> {
+        self.with_workspace(move |workspace| {
+            let (file_name, line, column) = workspace.position(&params.text_document_position)?;
+            let engine = workspace.engine()?;
+
+            let mut found = Vec::new();
+            if params.context.include_declaration {
+                found.extend(engine.find_definitions(&file_name, line, column)?);
+            }
+            for file in engine.find_references(&file_name, line, column)? {
+                found.extend(file.references.into_iter().map(|reference| reference.location));
+            }
+
+            // An import is both a definition and a reference, so report each location once
+            let mut locations: Vec<lsp::Location> = Vec::new();
+            for location in found.iter().filter_map(|location| workspace.location(&location.file, &location.span)) {
+                if !locations.contains(&location) {
+                    locations.push(location);
+                }
+            }
+            Ok((!locations.is_empty()).then_some(locations))
+        }).await
+    }
+
+    async fn document_symbol(
+        &self,
+        params: lsp::DocumentSymbolParams,
+    ) -> jsonrpc::Result<Option<lsp::DocumentSymbolResponse>> {
+        self.with_workspace(move |workspace| {
+            let path = params.text_document.uri.to_file_path()
+                .map_err(|_| anyhow!("Not a file URI: {}", params.text_document.uri))?;
+            let file_name = indexer::file_name_for(&workspace.root, &path);
+            let outline = workspace.engine()?.outline(&file_name)?;
+
+            let symbols = outline.iter()
+                .map(|item| workspace.document_symbol(&path, item))
+                .collect();
+            Ok(Some(lsp::DocumentSymbolResponse::Nested(symbols)))
+        }).await
+    }
+
+    async fn symbol(
+        &self,
+        params: lsp::WorkspaceSymbolParams,
+    ) -> jsonrpc::Result<Option<Vec<lsp::SymbolInformation>>> {
+        self.with_workspace(move |workspace| {
+            let symbols = workspace.engine()?.symbols()?;
+            let matches = symbols::search(&symbols, &params.query, MatchMode::Fuzzy)?;
+
+            #[allow(deprecated)]
+            let symbols = matches.into_iter()
+                .take(MAX_WORKSPACE_SYMBOLS)
+                .filter_map(|symbol| Some(lsp::SymbolInformation {
+                    kind: symbol_kind(symbol.kind.as_deref()),
+                    location: workspace.location(&symbol.file, &symbol.span)?,
+                    name: symbol.name.clone(),
+                    tags: None,
+                    deprecated: None,
+                    container_name: symbol.container.clone(),
+                }))
+                .collect();
+            Ok(Some(symbols))
+        }).await
+    }
+}
+
+/// Run the language server over stdin and stdout until the client disconnects
+pub fn run_stdio_server() -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start async runtime")?;
+
+    runtime.block_on(async {
+        let (service, socket) = LspService::new(Backend::new);
+        Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
+            .serve(service)
+            .await;
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf16_columns_convert_to_byte_offsets() {
+        // `é` is two bytes and one UTF-16 unit, `😀` four bytes and a surrogate pair
+        let line = "é😀x";
+        assert_eq!(utf16_to_utf8_column(line, 0), 0);
+        assert_eq!(utf16_to_utf8_column(line, 1), 2);
+        assert_eq!(utf16_to_utf8_column(line, 3), 6);
+        // Inside a surrogate pair rounds up to the end of the character
+        assert_eq!(utf16_to_utf8_column(line, 2), 6);
+        // Past the end of the line clamps to its length
+        assert_eq!(utf16_to_utf8_column(line, 4), 7);
+        assert_eq!(utf16_to_utf8_column(line, 40), 7);
+    }
+
+    #[test]
+    fn byte_offsets_convert_to_utf16_columns() {
+        let line = "é😀x";
+        assert_eq!(utf8_to_utf16_column(line, 0), 0);
+        assert_eq!(utf8_to_utf16_column(line, 2), 1);
+        assert_eq!(utf8_to_utf16_column(line, 6), 3);
+        assert_eq!(utf8_to_utf16_column(line, 7), 4);
+        assert_eq!(utf8_to_utf16_column(line, 40), 4);
+    }
+
+    #[test]
+    fn columns_round_trip_on_character_boundaries() {
+        let line = "let 😀 = \"ü\";";
+        for (offset, _) in line.char_indices() {
+            assert_eq!(utf16_to_utf8_column(line, utf8_to_utf16_column(line, offset)), offset);
+        }
+    }
+
+    #[test]
+    fn syntax_types_map_to_symbol_kinds() {
+        assert_eq!(symbol_kind(Some("function")), lsp::SymbolKind::FUNCTION);
+        assert_eq!(symbol_kind(Some("method")), lsp::SymbolKind::METHOD);
+        assert_eq!(symbol_kind(Some("class")), lsp::SymbolKind::CLASS);
+        assert_eq!(symbol_kind(Some("trait")), lsp::SymbolKind::INTERFACE);
+        assert_eq!(symbol_kind(Some("package")), lsp::SymbolKind::MODULE);
+        assert_eq!(symbol_kind(Some("impl")), lsp::SymbolKind::OBJECT);
+        assert_eq!(symbol_kind(Some("parameter")), lsp::SymbolKind::VARIABLE);
+        assert_eq!(symbol_kind(None), lsp::SymbolKind::VARIABLE);
+    }
+}
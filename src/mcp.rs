@@ -0,0 +1,517 @@
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::discovery::{self, FileDiscovery};
+use crate::indexer;
+use crate::query::QueryEngine;
+use crate::storage::{self, IndexDatabase};
+use crate::symbols::{self, MatchMode, Symbol};
+
+/// MCP protocol revisions this server speaks, newest first
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Most symbols a tool returns unless the caller sets a `limit`
+const DEFAULT_SYMBOL_LIMIT: usize = 200;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Settings for the server, usually taken from its command line
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// Repository to index on the first query if `index_repository` was not called
+    pub root: Option<PathBuf>,
+    /// Index database to keep graphs in; defaults to a file in the system temp
+    /// directory named after the repository root
+    pub db: Option<PathBuf>,
+}
+
+/// A repository and the index database its graphs are stored in
+struct Repository {
+    root: PathBuf,
+    database: IndexDatabase,
+    /// Query engine over the database, recreated after the database changes
+    engine: Option<QueryEngine<'static>>,
+}
+
+impl Repository {
+    fn open(root: PathBuf, db_path: &Path) -> Result<Self> {
+        Ok(Self {
+            root,
+            database: IndexDatabase::open(db_path)?,
+            engine: None,
+        })
+    }
+
+    fn engine(&mut self) -> Result<&mut QueryEngine<'static>> {
+        if self.engine.is_none() {
+            self.engine = Some(QueryEngine::from_database(&self.database)?);
+        }
+        Ok(self.engine.as_mut().expect("engine was just created"))
+    }
+
+    /// Stack graph file name for a path given by a client, relative to the root or absolute
+    fn file_name(&self, file: &str) -> String {
+        indexer::file_name_for(&self.root, &self.root.join(file))
+    }
+
+    /// Symbols defined in the repository, optionally only those in one file
+    fn symbols(&mut self, file: Option<&str>) -> Result<Vec<Symbol>> {
+        let file_name = file.map(|file| self.file_name(file));
+        let mut symbols = self.engine()?.symbols()?;
+        if let Some(file_name) = file_name {
+            symbols.retain(|symbol| symbol.file == file_name);
+        }
+        Ok(symbols)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// Absent for notifications, which get no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexArgs {
+    path: PathBuf,
+    db: Option<PathBuf>,
+    #[serde(default)]
+    include: Vec<String>,
+    exclude: Option<Vec<String>>,
+    #[serde(default)]
+    threads: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct PositionArgs {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileArgs {
+    file: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListArgs {
+    file: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchArgs {
+    query: String,
+    mode: Option<String>,
+    limit: Option<usize>,
+}
+
+/// MCP server exposing the indexer as tools, answering one JSON-RPC message at a time
+pub struct McpServer {
+    options: ServerOptions,
+    repository: Option<Repository>,
+}
+
+impl McpServer {
+    pub fn new(options: ServerOptions) -> Self {
+        Self {
+            options,
+            repository: None,
+        }
+    }
+
+    /// Handle a single JSON-RPC message, returning the response to send back, if any
+    pub fn handle_message(&mut self, message: &str) -> Option<Value> {
+        let request: Request = match serde_json::from_str(message) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, format!("Invalid JSON-RPC message: {}", e))),
+        };
+
+        let Some(id) = request.id else {
+            debug!("Notification: {}", request.method);
+            return None;
+        };
+
+        Some(match self.handle_request(&request.method, request.params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, message),
+        })
+    }
+
+    fn handle_request(&mut self, method: &str, params: Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => {
+                let requested = params.get("protocolVersion").and_then(Value::as_str);
+                let version = PROTOCOL_VERSIONS.iter()
+                    .find(|version| Some(**version) == requested)
+                    .unwrap_or(&PROTOCOL_VERSIONS[0]);
+
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tools() })),
+            "tools/call" => {
+                let call: ToolCall = serde_json::from_value(params)
+                    .map_err(|e| (INVALID_PARAMS, format!("Invalid tool call: {}", e)))?;
+                if !tools().iter().any(|tool| tool["name"] == call.name.as_str()) {
+                    return Err((INVALID_PARAMS, format!("Unknown tool: {}", call.name)));
+                }
+
+                // Failures are reported to the model as tool results rather than protocol errors
+                let (text, is_error) = match self.call_tool(&call.name, call.arguments) {
+                    Ok(result) => (serde_json::to_string_pretty(&result).unwrap_or_default(), false),
+                    Err(e) => (format!("{:#}", e), true),
+                };
+
+                Ok(json!({
+                    "content": [{ "type": "text", "text": text }],
+                    "isError": is_error,
+                }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+        }
+    }
+
+    fn call_tool(&mut self, name: &str, arguments: Value) -> Result<Value> {
+        debug!("Tool call: {} {}", name, arguments);
+
+        match name {
+            "index_repository" => self.index_repository(parse_arguments(arguments)?),
+            "find_definition" => {
+                let args: PositionArgs = parse_arguments(arguments)?;
+                let repository = self.repository()?;
+                let file_name = repository.file_name(&args.file);
+                Ok(json!(repository.engine()?.find_definitions(&file_name, args.line, args.column)?))
+            }
+            "find_references" => {
+                let args: PositionArgs = parse_arguments(arguments)?;
+                let repository = self.repository()?;
+                let file_name = repository.file_name(&args.file);
+                Ok(json!(repository.engine()?.find_references(&file_name, args.line, args.column)?))
+            }
+            "list_symbols" => {
+                let args: ListArgs = parse_arguments(arguments)?;
+                let symbols = self.repository()?.symbols(args.file.as_deref())?;
+                Ok(limited(&symbols.iter().collect::<Vec<_>>(), args.limit))
+            }
+            "file_outline" => {
+                let args: FileArgs = parse_arguments(arguments)?;
+                let repository = self.repository()?;
+                let file_name = repository.file_name(&args.file);
+                Ok(json!(repository.engine()?.outline(&file_name)?))
+            }
+            "search_symbols" => {
+                let args: SearchArgs = parse_arguments(arguments)?;
+                let mode = args.mode.as_deref().map(str::parse).transpose()?.unwrap_or(MatchMode::Fuzzy);
+                let symbols = self.repository()?.symbols(None)?;
+                Ok(limited(&symbols::search(&symbols, &args.query, mode)?, args.limit))
+            }
+            _ => Err(anyhow!("Unknown tool: {}", name)),
+        }
+    }
+
+    /// Index a repository into its database, replacing the repository queried so far
+    fn index_repository(&mut self, args: IndexArgs) -> Result<Value> {
+        let root = args.path.canonicalize()
+            .with_context(|| format!("Repository not found: {}", args.path.display()))?;
+        let exclude = args.exclude.unwrap_or_else(discovery::default_excludes);
+        let discovery = FileDiscovery::new(&args.include, &exclude)?;
+        let db_path = args.db.or_else(|| self.options.db.clone())
+            .unwrap_or_else(|| storage::default_db_path(&root));
+
+        // Re-indexing the same repository only updates files that changed, and the
+        // repository queried so far is kept until a new one has opened
+        let reuse = matches!(&self.repository,
+            Some(repository) if repository.root == root && repository.database.path() == db_path);
+        if !reuse {
+            self.repository = Some(Repository::open(root, &db_path)?);
+        }
+        let repository = self.repository.as_mut().expect("repository was just opened");
+        repository.engine = None;
+
+        let report = repository.database.update(&repository.root, &repository.root, &discovery, false, args.threads)
+            .with_context(|| format!("Failed to index repository: {}", repository.root.display()))?;
+        info!("Indexed repository {}: {}", repository.root.display(), report.summary());
+
+        Ok(json!({
+            "root": repository.root,
+            "database": repository.database.path(),
+            "summary": report.summary(),
+            "indexed": report.indexed.len(),
+            "unchanged": report.unchanged.len(),
+            "removed": report.removed.len(),
+            "skipped": report.skipped,
+            "failed": report.failed,
+            "parse_errors": report.parse_errors,
+        }))
+    }
+
+    /// The indexed repository, indexing the configured root first if nothing is indexed yet
+    fn repository(&mut self) -> Result<&mut Repository> {
+        if self.repository.is_none() {
+            let root = self.options.root.clone()
+                .ok_or_else(|| anyhow!("No repository is indexed; call index_repository first"))?;
+            self.index_repository(IndexArgs {
+                path: root,
+                db: None,
+                include: Vec::new(),
+                exclude: None,
+                threads: 0,
+            })?;
+        }
+
+        Ok(self.repository.as_mut().expect("repository was just indexed"))
+    }
+}
+
+/// Deserialize a tool's arguments, treating missing arguments as an empty object
+fn parse_arguments<T: for<'de> Deserialize<'de>>(arguments: Value) -> Result<T> {
+    let arguments = if arguments.is_null() { json!({}) } else { arguments };
+    serde_json::from_value(arguments).context("Invalid tool arguments")
+}
+
+/// Up to `limit` symbols, with whether any were left out
+fn limited(symbols: &[&Symbol], limit: Option<usize>) -> Value {
+    let shown = &symbols[..symbols.len().min(limit.unwrap_or(DEFAULT_SYMBOL_LIMIT))];
+    json!({ "symbols": shown, "total": symbols.len(), "truncated": shown.len() < symbols.len() })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Descriptions and input schemas of the tools the server provides
+fn tools() -> Vec<Value> {
+    let position = json!({
+        "type": "object",
+        "properties": {
+            "file": { "type": "string", "description": "File path, relative to the repository root or absolute" },
+            "line": { "type": "integer", "minimum": 0, "description": "Zero-based line number" },
+            "column": { "type": "integer", "minimum": 0, "description": "Zero-based column, as a UTF-8 byte offset" },
+        },
+        "required": ["file", "line", "column"],
+    });
+    let limit = json!({ "type": "integer", "minimum": 0, "description": "Most symbols to return (default 200)" });
+
+    vec![
+        json!({
+            "name": "index_repository",
+            "description": "Index a repository into a persistent stack graph database. Re-indexing only parses files that changed. Other tools query the most recently indexed repository.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Repository root directory" },
+                    "db": { "type": "string", "description": "Index database file; defaults to one in the system temp directory" },
+                    "include": { "type": "array", "items": { "type": "string" }, "description": "Only index files matching one of these globs" },
+                    "exclude": { "type": "array", "items": { "type": "string" }, "description": "Globs for files and directories to skip; defaults to common dependency and build directories" },
+                    "threads": { "type": "integer", "minimum": 0, "description": "Worker threads; 0 uses one per CPU" },
+                },
+                "required": ["path"],
+            },
+        }),
+        json!({
+            "name": "find_definition",
+            "description": "Find where the symbol at a position in a file is defined.",
+            "inputSchema": position,
+        }),
+        json!({
+            "name": "find_references",
+            "description": "Find every reference to the symbol at a position in a file, grouped by file.",
+            "inputSchema": position,
+        }),
+        json!({
+            "name": "list_symbols",
+            "description": "List the symbols defined in the repository, or in a single file.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "file": { "type": "string", "description": "Only list symbols defined in this file" },
+                    "limit": limit,
+                },
+            },
+        }),
+        json!({
+            "name": "file_outline",
+            "description": "Outline a file: the symbols defined in it as a tree, each with its name span, the range of its whole definition where known, and the symbols nested inside it.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "file": { "type": "string", "description": "File path, relative to the repository root or absolute" },
+                },
+                "required": ["file"],
+            },
+        }),
+        json!({
+            "name": "search_symbols",
+            "description": "Find symbols by name, best match first.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Text to match against symbol names" },
+                    "mode": {
+                        "type": "string",
+                        "enum": ["fuzzy", "prefix", "regex"],
+                        "description": "fuzzy (default) matches the query's characters in order, prefix matches the start of the name, regex matches a regular expression",
+                    },
+                    "limit": limit,
+                },
+                "required": ["query"],
+            },
+        }),
+    ]
+}
+
+/// Run an MCP server over stdin and stdout until stdin is closed
+///
+/// Messages are newline-delimited JSON-RPC, as in the MCP stdio transport.
+pub fn run_stdio_server(options: ServerOptions) -> Result<()> {
+    let mut server = McpServer::new(options);
+    let mut stdout = io::stdout().lock();
+
+    for line in io::stdin().lock().lines() {
+        let line = line.context("Failed to read from stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(response) = server.handle_message(&line) {
+            writeln!(stdout, "{}", response).context("Failed to write to stdout")?;
+            stdout.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::project;
+
+    fn request(server: &mut McpServer, method: &str, params: Value) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        server.handle_message(&message.to_string()).expect("requests get a response")
+    }
+
+    fn call(server: &mut McpServer, name: &str, arguments: Value) -> Value {
+        request(server, "tools/call", json!({ "name": name, "arguments": arguments }))
+    }
+
+    fn index(server: &mut McpServer, path: &Path, db: &Path) -> Value {
+        call(server, "index_repository", json!({ "path": path, "db": db }))
+    }
+
+    #[test]
+    fn initialize_negotiates_protocol_version() {
+        let mut server = McpServer::new(ServerOptions::default());
+
+        let supported = request(&mut server, "initialize", json!({ "protocolVersion": "2024-11-05" }));
+        assert_eq!(supported["result"]["protocolVersion"], "2024-11-05");
+
+        let unsupported = request(&mut server, "initialize", json!({ "protocolVersion": "1999-01-01" }));
+        assert_eq!(unsupported["result"]["protocolVersion"], PROTOCOL_VERSIONS[0]);
+        assert_eq!(unsupported["result"]["serverInfo"]["name"], env!("CARGO_PKG_NAME"));
+    }
+
+    #[test]
+    fn notifications_get_no_response() {
+        let mut server = McpServer::new(ServerOptions::default());
+        let message = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+
+        assert!(server.handle_message(&message.to_string()).is_none());
+    }
+
+    #[test]
+    fn lists_tools_with_schemas() {
+        let mut server = McpServer::new(ServerOptions::default());
+
+        let response = request(&mut server, "tools/list", json!({}));
+        let tools = response["result"]["tools"].as_array().unwrap();
+        let names: Vec<_> = tools.iter().map(|tool| tool["name"].as_str().unwrap()).collect();
+        assert_eq!(names, [
+            "index_repository", "find_definition", "find_references",
+            "list_symbols", "file_outline", "search_symbols",
+        ]);
+        assert!(tools.iter().all(|tool| tool["inputSchema"]["type"] == "object"));
+    }
+
+    #[test]
+    fn unknown_tool_and_method_are_protocol_errors() {
+        let mut server = McpServer::new(ServerOptions::default());
+
+        let tool = call(&mut server, "rename_symbol", json!({}));
+        assert_eq!(tool["error"]["code"], INVALID_PARAMS);
+
+        let method = request(&mut server, "resources/list", json!({}));
+        assert_eq!(method["error"]["code"], METHOD_NOT_FOUND);
+
+        let invalid = server.handle_message("{").unwrap();
+        assert_eq!(invalid["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn tool_failure_is_an_error_result() {
+        let mut server = McpServer::new(ServerOptions::default());
+
+        let response = call(&mut server, "find_definition", json!({ "file": "app.js", "line": 0, "column": 0 }));
+        assert_eq!(response["result"]["isError"], true);
+        let text = response["result"]["content"][0]["text"].as_str().unwrap();
+        assert!(text.contains("call index_repository first"), "{}", text);
+    }
+
+    #[test]
+    fn indexes_and_queries_repository() {
+        let project = project(&[("app.js", "function helper() {}\nhelper();\n")]);
+        let db = project.path().join("index.db");
+        let mut server = McpServer::new(ServerOptions::default());
+
+        let indexed = index(&mut server, project.path(), &db);
+        assert_eq!(indexed["result"]["isError"], false);
+
+        let response = call(&mut server, "find_definition", json!({ "file": "app.js", "line": 1, "column": 0 }));
+        assert_eq!(response["result"]["isError"], false);
+        let definitions: Value = serde_json::from_str(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(definitions[0]["file"], "app.js");
+    }
+
+    #[test]
+    fn failed_reindex_keeps_current_repository() {
+        let project = project(&[("app.js", "function helper() {}\n")]);
+        let mut server = McpServer::new(ServerOptions::default());
+        index(&mut server, project.path(), &project.path().join("index.db"));
+
+        let missing_dir = project.path().join("missing").join("index.db");
+        let failed = index(&mut server, project.path(), &missing_dir);
+        assert_eq!(failed["result"]["isError"], true);
+
+        let response = call(&mut server, "list_symbols", json!({}));
+        assert_eq!(response["result"]["isError"], false);
+        let symbols: Value = serde_json::from_str(response["result"]["content"][0]["text"].as_str().unwrap()).unwrap();
+        assert_eq!(symbols["total"], 1);
+    }
+}
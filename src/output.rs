@@ -1,26 +1,279 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io::{BufWriter, Write};
 use std::path::Path;
-use anyhow::Result;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
 use log::info;
-use stack_graphs::graph::StackGraph;
+#[cfg(feature = "scip")]
+use protobuf::Message;
+#[cfg(feature = "scip")]
+use scip::types as scip_types;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use stack_graphs::arena::Handle;
+use stack_graphs::graph::{File, Node, StackGraph};
+use stack_graphs::serde::StackGraph as SerializableGraph;
+
+use crate::languages::Language;
+use crate::query::{Location, QueryEngine, Span};
+use crate::stats;
+use crate::symbols;
+use crate::tsg_loader::is_builtins_file;
+
+/// A format the indexer can write a stack graph in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OutputFormat {
+    /// The whole graph as one JSON document
+    #[default]
+    Json,
+    /// One JSON record per line, streamed out while indexing
+    Ndjson,
+    /// Graphviz DOT, for drawing the graph
+    Dot,
+    /// A SCIP index for code intelligence platforms
+    #[cfg(feature = "scip")]
+    Scip,
+    /// A line-delimited LSIF dump
+    Lsif,
+    /// A Universal Ctags tags file
+    Ctags,
+    /// An Emacs TAGS file
+    Etags,
+    /// Cypher statements that load the graph into Neo4j
+    Cypher,
+    /// GraphML
+    Graphml,
+    /// `nodes.csv` and `relationships.csv` for `neo4j-admin import`, in a directory
+    Neo4j,
+}
+
+impl OutputFormat {
+    /// Every format, in the order they are listed in help and error messages
+    pub const ALL: &'static [Self] = &[
+        Self::Json, Self::Ndjson, Self::Dot,
+        #[cfg(feature = "scip")]
+        Self::Scip,
+        Self::Lsif, Self::Ctags, Self::Etags, Self::Cypher, Self::Graphml, Self::Neo4j,
+    ];
+
+    /// The name the format is selected by, e.g. `json`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+            Self::Dot => "dot",
+            #[cfg(feature = "scip")]
+            Self::Scip => "scip",
+            Self::Lsif => "lsif",
+            Self::Ctags => "ctags",
+            Self::Etags => "etags",
+            Self::Cypher => "cypher",
+            Self::Graphml => "graphml",
+            Self::Neo4j => "neo4j",
+        }
+    }
+
+    /// The writer for this format; only DOT output uses the filter
+    pub fn writer(&self, dot_filter: &DotFilter) -> Box<dyn GraphWriter> {
+        match self {
+            Self::Json => Box::new(JsonWriter),
+            Self::Ndjson => Box::new(NdjsonWriter),
+            Self::Dot => Box::new(DotWriter(dot_filter.clone())),
+            #[cfg(feature = "scip")]
+            Self::Scip => Box::new(ScipWriter),
+            Self::Lsif => Box::new(LsifWriter),
+            Self::Ctags => Box::new(CtagsWriter),
+            Self::Etags => Box::new(EtagsWriter),
+            Self::Cypher => Box::new(CypherWriter),
+            Self::Graphml => Box::new(GraphmlWriter),
+            Self::Neo4j => Box::new(Neo4jCsvWriter),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.to_lowercase();
+        if cfg!(not(feature = "scip")) && name == "scip" {
+            return Err(anyhow!("SCIP output needs tsg_indexer to be built with the `scip` feature"));
+        }
+
+        Self::ALL.iter()
+            .copied()
+            .find(|format| format.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(OutputFormat::name).collect();
+                anyhow!("Unknown output format `{}`, expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Writes a stack graph in some output format
+///
+/// Each [`OutputFormat`] has a writer, and library users can pass their own in
+/// [`IndexerConfig::writer`](crate::IndexerConfig::writer) to write formats of their own.
+pub trait GraphWriter: fmt::Debug + Send + Sync {
+    /// Write the graph to `output_path`, or to standard output if there is none; `root`
+    /// is the directory that file names in the graph are relative to
+    fn write(&self, stack_graph: &StackGraph, root: &Path, output_path: Option<&Path>) -> Result<()>;
+}
+
+/// Writes [`OutputFormat::Json`] with [`output_json`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonWriter;
+
+impl GraphWriter for JsonWriter {
+    fn write(&self, stack_graph: &StackGraph, _root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_json(stack_graph, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Ndjson`] with [`output_ndjson`], once the graph is built
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NdjsonWriter;
+
+impl GraphWriter for NdjsonWriter {
+    fn write(&self, stack_graph: &StackGraph, _root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_ndjson(stack_graph, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Dot`] with [`output_dot`], drawing the part of the graph the
+/// filter selects
+#[derive(Debug, Clone, Default)]
+pub struct DotWriter(pub DotFilter);
+
+impl GraphWriter for DotWriter {
+    fn write(&self, stack_graph: &StackGraph, _root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_dot(stack_graph, &self.0, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Scip`] with [`output_scip`]
+#[cfg(feature = "scip")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScipWriter;
+
+#[cfg(feature = "scip")]
+impl GraphWriter for ScipWriter {
+    fn write(&self, stack_graph: &StackGraph, root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_scip(stack_graph, root, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Lsif`] with [`output_lsif`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LsifWriter;
+
+impl GraphWriter for LsifWriter {
+    fn write(&self, stack_graph: &StackGraph, root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_lsif(stack_graph, root, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Ctags`] with [`output_ctags`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CtagsWriter;
+
+impl GraphWriter for CtagsWriter {
+    fn write(&self, stack_graph: &StackGraph, root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_ctags(stack_graph, root, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Etags`] with [`output_etags`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EtagsWriter;
+
+impl GraphWriter for EtagsWriter {
+    fn write(&self, stack_graph: &StackGraph, root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_etags(stack_graph, root, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Cypher`] with [`output_cypher`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CypherWriter;
+
+impl GraphWriter for CypherWriter {
+    fn write(&self, stack_graph: &StackGraph, _root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_cypher(stack_graph, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Graphml`] with [`output_graphml`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GraphmlWriter;
+
+impl GraphWriter for GraphmlWriter {
+    fn write(&self, stack_graph: &StackGraph, _root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_graphml(stack_graph, output_path)
+    }
+}
+
+/// Writes [`OutputFormat::Neo4j`] with [`output_neo4j_csv`]; the output path is the
+/// directory to write the CSV files to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Neo4jCsvWriter;
+
+impl GraphWriter for Neo4jCsvWriter {
+    fn write(&self, stack_graph: &StackGraph, _root: &Path, output_path: Option<&Path>) -> Result<()> {
+        output_neo4j_csv(stack_graph, output_path)
+    }
+}
+
+/// Counts of what a JSON export contains
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSummary {
+    pub files: usize,
+    pub nodes: usize,
+    pub edges: usize,
+    pub description: String,
+}
+
+/// JSON form of a stack graph: every file, node and edge, plus a summary
+///
+/// The `files`, `nodes` and `edges` fields use the `stack_graphs::serde` format, so the
+/// output can be read back with [`stack_graphs::serde::StackGraph`] and loaded into a
+/// graph with `load_into`. Nodes carry their id, kind, symbol, source span, definition
+/// or reference flag and debug info; edges carry their precedence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonGraph {
+    pub summary: JsonSummary,
+    #[serde(flatten)]
+    pub graph: SerializableGraph,
+}
+
+impl JsonGraph {
+    /// Capture the full contents of a stack graph
+    pub fn from_graph(stack_graph: &StackGraph) -> Self {
+        let graph = stack_graph.to_serializable();
+
+        Self {
+            summary: JsonSummary {
+                files: graph.files.data.len(),
+                nodes: graph.nodes.data.len(),
+                edges: graph.edges.data.len(),
+                description: "Tree-sitter Stack Graph representation of indexed code".to_string(),
+            },
+            graph,
+        }
+    }
+}
 
 /// Output the stack graph as JSON
 pub fn output_json(stack_graph: &StackGraph, output_path: Option<&Path>) -> Result<()> {
-    // Create a simplified JSON representation of the stack graph
-    // Since we can't directly access private fields, we'll just provide a summary
-    let output = serde_json::json!({
-        "summary": {
-            "files": stack_graph.iter_files().count(),
-            "description": "Tree-sitter Stack Graph representation of indexed code"
-        },
-        "files": stack_graph.iter_files().map(|file_handle| {
-            serde_json::json!({
-                "name": stack_graph[file_handle].to_string(),
-            })
-        }).collect::<Vec<_>>(),
-    });
-    
-    let json_str = serde_json::to_string_pretty(&output)?;
+    let json_str = serde_json::to_string_pretty(&JsonGraph::from_graph(stack_graph))?;
     
     match output_path {
         Some(path) => {
@@ -35,22 +288,188 @@ pub fn output_json(stack_graph: &StackGraph, output_path: Option<&Path>) -> Resu
     Ok(())
 }
 
-/// Output the stack graph as DOT format for visualization
-pub fn output_dot(stack_graph: &StackGraph, output_path: Option<&Path>) -> Result<()> {
-    // Create a simplified DOT graph representation
+/// Which part of the stack graph to draw in DOT output
+///
+/// Large graphs quickly become too big to render, so DOT output can be narrowed to a
+/// single file, to the neighborhood of a symbol, or both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DotFilter {
+    /// Only draw nodes that belong to this file
+    pub file: Option<String>,
+    /// Only draw nodes with this symbol and the nodes within `depth` edges of them
+    pub symbol: Option<String>,
+    /// Number of edges to follow, in either direction, from a node matching `symbol`
+    pub depth: usize,
+}
+
+impl Default for DotFilter {
+    fn default() -> Self {
+        Self {
+            file: None,
+            symbol: None,
+            depth: 2,
+        }
+    }
+}
+
+impl DotFilter {
+    /// Nodes of the graph that pass this filter
+    fn select(&self, stack_graph: &StackGraph) -> HashSet<Handle<Node>> {
+        let in_file = |node: Handle<Node>| match (&self.file, stack_graph[node].id().file()) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(name), Some(file)) => stack_graph[file].name() == name,
+        };
+        let mut selected: HashSet<_> = stack_graph.iter_nodes().filter(|&node| in_file(node)).collect();
+
+        if let Some(symbol) = &self.symbol {
+            let mut neighbors: HashMap<Handle<Node>, Vec<Handle<Node>>> = HashMap::new();
+            for node in stack_graph.iter_nodes() {
+                for edge in stack_graph.outgoing_edges(node) {
+                    neighbors.entry(edge.source).or_default().push(edge.sink);
+                    neighbors.entry(edge.sink).or_default().push(edge.source);
+                }
+            }
+
+            let mut reached: HashSet<_> = selected.iter()
+                .copied()
+                .filter(|&node| stack_graph[node].symbol().is_some_and(|s| &stack_graph[s] == symbol))
+                .collect();
+            let mut frontier: Vec<_> = reached.iter().copied().collect();
+
+            // Root and jump-to nodes are not in `selected`, so the search never spreads
+            // through them into unrelated files
+            for _ in 0..self.depth {
+                let mut next = Vec::new();
+                for node in frontier {
+                    for &neighbor in neighbors.get(&node).into_iter().flatten() {
+                        if selected.contains(&neighbor) && reached.insert(neighbor) {
+                            next.push(neighbor);
+                        }
+                    }
+                }
+                frontier = next;
+            }
+
+            selected = reached;
+        }
+
+        // Root and jump-to nodes are shared by every file, so only draw them when they
+        // connect to a drawn node
+        let mut globals = Vec::new();
+        for global in [StackGraph::root_node(), StackGraph::jump_to_node()] {
+            let connected = stack_graph.outgoing_edges(global).any(|edge| selected.contains(&edge.sink))
+                || selected.iter().any(|&node| stack_graph.outgoing_edges(node).any(|edge| edge.sink == global));
+            if connected {
+                globals.push(global);
+            }
+        }
+        selected.extend(globals);
+
+        selected
+    }
+}
+
+/// Render the stack graph in DOT format
+///
+/// Nodes are grouped into one cluster per file, shaped and colored by kind, and
+/// labeled with their symbol and 1-based line. Definitions get a double border and
+/// references a bold one. Edges with a non-zero precedence are labeled with it.
+pub fn to_dot(stack_graph: &StackGraph, filter: &DotFilter) -> String {
+    let selected = filter.select(stack_graph);
+
     let mut dot_graph = String::new();
     dot_graph.push_str("digraph StackGraph {\n");
-    
-    // Add file nodes
+    dot_graph.push_str("  rankdir=LR;\n");
+    dot_graph.push_str("  node [fontname=\"Helvetica\", fontsize=10, style=filled];\n");
+    dot_graph.push_str("  edge [fontname=\"Helvetica\", fontsize=9];\n");
+
+    for global in [StackGraph::root_node(), StackGraph::jump_to_node()] {
+        if selected.contains(&global) {
+            dot_graph.push_str(&format!("  {}\n", dot_node(stack_graph, global)));
+        }
+    }
+
+    // One cluster per file
     for file_handle in stack_graph.iter_files() {
-        let file_name = stack_graph[file_handle].to_string();
-        
-        // Create a node for each file
-        dot_graph.push_str(&format!("  file_{} [label=\"{}\"];\n", 
-            file_handle.as_u32(), file_name));
+        let nodes: Vec<_> = stack_graph.nodes_for_file(file_handle)
+            .filter(|node| selected.contains(node))
+            .collect();
+        if nodes.is_empty() {
+            continue;
+        }
+
+        dot_graph.push_str(&format!("  subgraph cluster_{} {{\n", file_handle.as_u32()));
+        dot_graph.push_str(&format!("    label=\"{}\";\n", escape_dot(stack_graph[file_handle].name())));
+        dot_graph.push_str("    style=rounded;\n");
+        for node in nodes {
+            dot_graph.push_str(&format!("    {}\n", dot_node(stack_graph, node)));
+        }
+        dot_graph.push_str("  }\n");
     }
-    
+
+    // Edges between drawn nodes
+    let mut sources: Vec<_> = selected.iter().copied().collect();
+    sources.sort();
+    for source in sources {
+        for edge in stack_graph.outgoing_edges(source) {
+            if !selected.contains(&edge.sink) {
+                continue;
+            }
+
+            let label = if edge.precedence != 0 {
+                format!(" [label=\"{}\"]", edge.precedence)
+            } else {
+                String::new()
+            };
+            dot_graph.push_str(&format!("  n{} -> n{}{};\n", source.as_u32(), edge.sink.as_u32(), label));
+        }
+    }
+
     dot_graph.push_str("}\n");
+    dot_graph
+}
+
+/// DOT statement for a single node, with its shape, color and label
+fn dot_node(stack_graph: &StackGraph, node: Handle<Node>) -> String {
+    let value = &stack_graph[node];
+    let symbol = value.symbol().map(|symbol| &stack_graph[symbol]).unwrap_or("");
+
+    let (shape, color, text) = match value {
+        Node::Root(_) => ("doublecircle", "gray80", "root".to_string()),
+        Node::JumpTo(_) => ("doublecircle", "gray90", "jump to".to_string()),
+        Node::Scope(_) if value.is_exported_scope() => ("circle", "gold", "scope".to_string()),
+        Node::Scope(_) => ("circle", "white", "scope".to_string()),
+        Node::DropScopes(_) => ("octagon", "lightpink", "drop scopes".to_string()),
+        Node::PushSymbol(_) => ("box", "lightskyblue", format!("push {}", symbol)),
+        Node::PushScopedSymbol(_) => ("box", "steelblue1", format!("push scoped {}", symbol)),
+        Node::PopSymbol(_) => ("ellipse", "palegreen", format!("pop {}", symbol)),
+        Node::PopScopedSymbol(_) => ("ellipse", "seagreen2", format!("pop scoped {}", symbol)),
+    };
+
+    let mut label = escape_dot(&text);
+    if let Some(span) = stack_graph.source_info(node).and_then(|info| Span::from_lsp(&info.span)) {
+        label.push_str(&format!("\\nline {}", span.start_line + 1));
+    }
+
+    let mut attributes = format!("shape={}, fillcolor=\"{}\", label=\"{}\"", shape, color, label);
+    if value.is_definition() {
+        attributes.push_str(", peripheries=2");
+    } else if value.is_reference() {
+        attributes.push_str(", style=\"filled,bold\"");
+    }
+
+    format!("n{} [{}];", node.as_u32(), attributes)
+}
+
+/// Escape a string for use inside a quoted DOT label
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Output the stack graph as DOT format for visualization
+pub fn output_dot(stack_graph: &StackGraph, filter: &DotFilter, output_path: Option<&Path>) -> Result<()> {
+    let dot_graph = to_dot(stack_graph, filter);
     
     match output_path {
         Some(path) => {
@@ -63,4 +482,1681 @@ pub fn output_dot(stack_graph: &StackGraph, output_path: Option<&Path>) -> Resul
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Name of the SCIP scheme that symbols of this indexer are given
+#[cfg(feature = "scip")]
+const SCIP_SCHEME: &str = "tsg_indexer";
+
+/// Convert the stack graph into a SCIP index
+///
+/// Every indexed source file becomes a document holding an occurrence for each
+/// definition and each reference that resolves to one, with positions as zero-based
+/// lines and UTF-8 byte offsets. A definition's symbol is made from its file and
+/// container path, e.g. ``tsg_indexer . . . `src/app.py`/Parser#parse().``, so a
+/// reference in another file carries the same symbol as the definition it resolves
+/// to. Definitions also get symbol information with their kind and container.
+///
+/// `project_root` is the directory that file names in the graph are relative to.
+#[cfg(feature = "scip")]
+pub fn to_scip(stack_graph: &StackGraph, project_root: &Path) -> Result<scip_types::Index> {
+    // Symbols of the definitions in each file, by the graph nodes that define them
+    let mut documents = Vec::new();
+    let mut node_symbols: HashMap<Handle<Node>, String> = HashMap::new();
+    for file in stack_graph.iter_files() {
+        let file_name = stack_graph[file].name();
+        if is_builtins_file(file_name) {
+            continue;
+        }
+
+        let definitions = symbols::nested_definitions(stack_graph, file);
+        let descriptors = scip_descriptors(file_name, &definitions);
+
+        let mut document = scip_types::Document {
+            language: language_id(file_name),
+            relative_path: file_name.to_string(),
+            position_encoding: scip_types::PositionEncoding::UTF8CodeUnitOffsetFromLineStart.into(),
+            ..Default::default()
+        };
+        for ((definition, parent), path) in definitions.iter().zip(&descriptors) {
+            let symbol = scip_symbol(path.clone());
+            for &node in &definition.nodes {
+                node_symbols.insert(node, symbol.clone());
+            }
+
+            document.occurrences.push(scip_types::Occurrence {
+                range: scip_range(&definition.symbol.span),
+                symbol: symbol.clone(),
+                symbol_roles: scip_types::SymbolRole::Definition as i32,
+                enclosing_range: definition.definiens.as_ref().map(scip_range).unwrap_or_default(),
+                ..Default::default()
+            });
+            if !document.symbols.iter().any(|info| info.symbol == symbol) {
+                document.symbols.push(scip_types::SymbolInformation {
+                    symbol,
+                    kind: scip_kind(definition.symbol.kind.as_deref()).into(),
+                    display_name: definition.symbol.name.clone(),
+                    enclosing_symbol: parent
+                        .map(|parent| scip_symbol(descriptors[parent].clone()))
+                        .unwrap_or_default(),
+                    ..Default::default()
+                });
+            }
+        }
+
+        documents.push(document);
+    }
+
+    // References take the symbol of every definition they resolve to
+    let resolved = QueryEngine::new(stack_graph)?.resolve_all()?;
+    for (reference, definition) in resolved {
+        let Some(symbol) = node_symbols.get(&definition) else {
+            continue;
+        };
+        let Some(location) = Location::from_node(stack_graph, reference) else {
+            continue;
+        };
+        let Some(document) = documents.iter_mut().find(|document| document.relative_path == location.file) else {
+            continue;
+        };
+
+        let range = scip_range(&location.span);
+        if !document.occurrences.iter().any(|occurrence| occurrence.range == range && &occurrence.symbol == symbol) {
+            document.occurrences.push(scip_types::Occurrence {
+                range,
+                symbol: symbol.clone(),
+                ..Default::default()
+            });
+        }
+    }
+
+    for document in &mut documents {
+        document.occurrences.sort_by(|a, b| (&a.range, &a.symbol).cmp(&(&b.range, &b.symbol)));
+    }
+    documents.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+
+    Ok(scip_types::Index {
+        metadata: Some(scip_types::Metadata {
+            tool_info: Some(scip_types::ToolInfo {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                ..Default::default()
+            }).into(),
+            project_root: root_uri(project_root),
+            text_document_encoding: scip_types::TextEncoding::UTF8.into(),
+            ..Default::default()
+        }).into(),
+        documents,
+        ..Default::default()
+    })
+}
+
+/// Descriptors of each definition's symbol: the file as a namespace, then the
+/// definitions it is nested in and the definition itself
+#[cfg(feature = "scip")]
+fn scip_descriptors(file_name: &str, definitions: &[(symbols::Definition, Option<usize>)]) -> Vec<Vec<scip_types::Descriptor>> {
+    let mut descriptors: Vec<Vec<scip_types::Descriptor>> = Vec::with_capacity(definitions.len());
+    for (definition, parent) in definitions {
+        // Parents come before the definitions nested in them
+        let mut path = match parent {
+            Some(parent) => descriptors[*parent].clone(),
+            None => vec![scip_descriptor(file_name, scip_types::descriptor::Suffix::Namespace)],
+        };
+        path.push(scip_descriptor(&definition.symbol.name, scip_suffix(definition.symbol.kind.as_deref())));
+        descriptors.push(path);
+    }
+    descriptors
+}
+
+#[cfg(feature = "scip")]
+fn scip_descriptor(name: &str, suffix: scip_types::descriptor::Suffix) -> scip_types::Descriptor {
+    scip_types::Descriptor {
+        name: name.to_string(),
+        suffix: suffix.into(),
+        ..Default::default()
+    }
+}
+
+/// Format a global symbol of this indexer from its descriptors
+#[cfg(feature = "scip")]
+fn scip_symbol(descriptors: Vec<scip_types::Descriptor>) -> String {
+    scip::symbol::format_symbol(scip_types::Symbol {
+        scheme: SCIP_SCHEME.to_string(),
+        descriptors,
+        ..Default::default()
+    })
+}
+
+/// SCIP range of a span: `[line, start, end]` on one line, or
+/// `[start line, start, end line, end]`
+#[cfg(feature = "scip")]
+fn scip_range(span: &Span) -> Vec<i32> {
+    if span.start_line == span.end_line {
+        vec![span.start_line as i32, span.start_column as i32, span.end_column as i32]
+    } else {
+        vec![span.start_line as i32, span.start_column as i32, span.end_line as i32, span.end_column as i32]
+    }
+}
+
+/// Descriptor suffix for a syntax type, which decides how the name is written in a symbol
+#[cfg(feature = "scip")]
+fn scip_suffix(syntax_type: Option<&str>) -> scip_types::descriptor::Suffix {
+    use scip_types::descriptor::Suffix;
+
+    match syntax_type.unwrap_or("") {
+        "function" | "method" | "constructor" => Suffix::Method,
+        "class" | "interface" | "trait" | "struct" | "enum" | "impl" | "type" => Suffix::Type,
+        "module" | "namespace" | "package" | "import" => Suffix::Namespace,
+        _ => Suffix::Term,
+    }
+}
+
+/// SCIP symbol kind for a syntax type
+#[cfg(feature = "scip")]
+fn scip_kind(syntax_type: Option<&str>) -> scip_types::symbol_information::Kind {
+    use scip_types::symbol_information::Kind;
+
+    match syntax_type.unwrap_or("") {
+        "function" => Kind::Function,
+        "method" => Kind::Method,
+        "constructor" => Kind::Constructor,
+        "class" => Kind::Class,
+        "interface" => Kind::Interface,
+        "trait" => Kind::Trait,
+        "struct" => Kind::Struct,
+        "enum" => Kind::Enum,
+        "module" | "import" => Kind::Module,
+        "namespace" => Kind::Namespace,
+        "package" => Kind::Package,
+        "impl" => Kind::Object,
+        "field" => Kind::Field,
+        "property" => Kind::Property,
+        "constant" => Kind::Constant,
+        "type" => Kind::Type,
+        _ => Kind::Variable,
+    }
+}
+
+/// `file://` URI of the project root, made absolute if it can be
+fn root_uri(project_root: &Path) -> String {
+    let project_root = project_root.canonicalize().unwrap_or_else(|_| project_root.to_path_buf());
+    format!("file://{}", project_root.display())
+}
+
+/// Lowercase language name of a file, from its extension, as SCIP and LSIF identify it
+fn language_id(file_name: &str) -> String {
+    let language = Path::new(file_name).extension()
+        .map(|ext| Language::from_extension(&ext.to_string_lossy()))
+        .unwrap_or(Language::Unknown);
+    match language {
+        Language::Unknown => String::new(),
+        language => language.name().to_lowercase(),
+    }
+}
+
+/// Output the stack graph as a SCIP index in protobuf format
+#[cfg(feature = "scip")]
+pub fn output_scip(stack_graph: &StackGraph, project_root: &Path, output_path: Option<&Path>) -> Result<()> {
+    let index = to_scip(stack_graph, project_root)?;
+    let bytes = index.write_to_bytes()?;
+
+    match output_path {
+        Some(path) => {
+            fs::write(path, bytes)?;
+            info!("SCIP index written to: {}", path.display());
+        }
+        None => {
+            std::io::stdout().write_all(&bytes)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Lines of an LSIF dump, numbering vertices and edges in the order they are added
+#[derive(Default)]
+struct LsifDump {
+    lines: Vec<Value>,
+}
+
+impl LsifDump {
+    fn add(&mut self, kind: &str, label: &str, fields: Value) -> usize {
+        let id = self.lines.len() + 1;
+        let mut line = json!({ "id": id, "type": kind, "label": label });
+        if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), fields) {
+            line.extend(fields);
+        }
+        self.lines.push(line);
+        id
+    }
+
+    fn vertex(&mut self, label: &str, fields: Value) -> usize {
+        self.add("vertex", label, fields)
+    }
+
+    /// A one-to-one edge
+    fn edge(&mut self, label: &str, out_v: usize, in_v: usize) {
+        self.add("edge", label, json!({ "outV": out_v, "inV": in_v }));
+    }
+
+    /// A one-to-many edge, such as `contains` or `item`, with any extra properties
+    fn edges(&mut self, label: &str, out_v: usize, in_vs: &[usize], mut fields: Value) {
+        fields["outV"] = json!(out_v);
+        fields["inVs"] = json!(in_vs);
+        self.add("edge", label, fields);
+    }
+}
+
+/// A definition in an LSIF dump and the ranges that refer to it
+struct LsifDefinition {
+    result_set: usize,
+    document: usize,
+    range: usize,
+    /// Reference ranges by the document they are in
+    references: Vec<(usize, usize)>,
+}
+
+/// Convert the stack graph into the lines of an LSIF dump
+///
+/// Each indexed source file becomes a document, with a range for each definition and
+/// each reference that resolves to one. Every definition has a result set with its
+/// definition, references and hover results, and its references point to that result
+/// set. A reference that resolves to several definitions goes to the first of them
+/// and is listed among the references of each. Hovers are a placeholder with the
+/// definition's kind and qualified name. Positions are UTF-16 based, as LSIF requires.
+///
+/// `project_root` is the directory that file names in the graph are relative to.
+pub fn to_lsif(stack_graph: &StackGraph, project_root: &Path) -> Result<Vec<Value>> {
+    let project_root = root_uri(project_root);
+    let mut dump = LsifDump::default();
+    dump.vertex("metaData", json!({
+        "version": "0.4.3",
+        "projectRoot": project_root,
+        "positionEncoding": "utf-16",
+        "toolInfo": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+    }));
+
+    let mut files: Vec<_> = stack_graph.iter_files()
+        .filter(|&file| !is_builtins_file(stack_graph[file].name()))
+        .collect();
+    files.sort_by_key(|&file| stack_graph[file].name());
+
+    // Ranges by document and position, so that a position is only given one range
+    let mut documents: HashMap<&str, usize> = HashMap::new();
+    let mut ranges: HashMap<(usize, Span), usize> = HashMap::new();
+    let mut contains: Vec<(usize, Vec<usize>)> = Vec::new();
+    let mut definitions: Vec<LsifDefinition> = Vec::new();
+    let mut node_definitions: HashMap<Handle<Node>, usize> = HashMap::new();
+
+    for file in files {
+        let file_name = stack_graph[file].name();
+        let language = language_id(file_name);
+        let document = dump.vertex("document", json!({
+            "uri": format!("{}/{}", project_root, file_name),
+            "languageId": language,
+        }));
+        documents.insert(file_name, document);
+        contains.push((document, Vec::new()));
+
+        for (definition, _) in symbols::nested_definitions(stack_graph, file) {
+            let Some(span) = definition.nodes.iter()
+                .find_map(|&node| stack_graph.source_info(node))
+                .map(|info| &info.span) else {
+                continue;
+            };
+            if ranges.contains_key(&(document, definition.symbol.span)) {
+                continue;
+            }
+
+            let range = dump.vertex("range", lsif_range(span));
+            ranges.insert((document, definition.symbol.span), range);
+            if let Some((_, document_ranges)) = contains.last_mut() {
+                document_ranges.push(range);
+            }
+
+            let result_set = dump.vertex("resultSet", json!({}));
+            dump.edge("next", range, result_set);
+
+            let definition_result = dump.vertex("definitionResult", json!({}));
+            dump.edge("textDocument/definition", result_set, definition_result);
+            dump.edges("item", definition_result, &[range], json!({ "document": document }));
+
+            let kind = definition.symbol.kind.as_deref().unwrap_or("definition");
+            let hover = dump.vertex("hoverResult", json!({
+                "result": {
+                    "contents": {
+                        "kind": "markdown",
+                        "value": format!("```{}\n{} {}\n```", language, kind, definition.symbol.qualified_name()),
+                    },
+                },
+            }));
+            dump.edge("textDocument/hover", result_set, hover);
+
+            for &node in &definition.nodes {
+                node_definitions.insert(node, definitions.len());
+            }
+            definitions.push(LsifDefinition { result_set, document, range, references: Vec::new() });
+        }
+    }
+
+    let resolved = QueryEngine::new(stack_graph)?.resolve_all()?;
+    for (reference, definition) in resolved {
+        let Some(&index) = node_definitions.get(&definition) else {
+            continue;
+        };
+        let Some(location) = Location::from_node(stack_graph, reference) else {
+            continue;
+        };
+        let Some(&document) = documents.get(location.file.as_str()) else {
+            continue;
+        };
+
+        let range = match ranges.get(&(document, location.span)) {
+            Some(&range) => range,
+            None => {
+                let Some(info) = stack_graph.source_info(reference) else {
+                    continue;
+                };
+                let range = dump.vertex("range", lsif_range(&info.span));
+                ranges.insert((document, location.span), range);
+                if let Some((_, document_ranges)) = contains.iter_mut().find(|(id, _)| *id == document) {
+                    document_ranges.push(range);
+                }
+                dump.edge("next", range, definitions[index].result_set);
+                range
+            }
+        };
+
+        let definition = &mut definitions[index];
+        if range != definition.range && !definition.references.contains(&(document, range)) {
+            definition.references.push((document, range));
+        }
+    }
+
+    for (document, document_ranges) in contains {
+        if !document_ranges.is_empty() {
+            dump.edges("contains", document, &document_ranges, json!({}));
+        }
+    }
+
+    for definition in definitions {
+        let reference_result = dump.vertex("referenceResult", json!({}));
+        dump.edge("textDocument/references", definition.result_set, reference_result);
+        dump.edges("item", reference_result, &[definition.range], json!({
+            "document": definition.document,
+            "property": "definitions",
+        }));
+
+        let mut references = definition.references;
+        references.sort();
+        for group in references.chunk_by(|a, b| a.0 == b.0) {
+            let in_vs: Vec<usize> = group.iter().map(|(_, range)| *range).collect();
+            dump.edges("item", reference_result, &in_vs, json!({
+                "document": group[0].0,
+                "property": "references",
+            }));
+        }
+    }
+
+    Ok(dump.lines)
+}
+
+/// Start and end of an LSIF range, in UTF-16 code units
+fn lsif_range(span: &lsp_positions::Span) -> Value {
+    json!({
+        "start": { "line": span.start.line, "character": span.start.column.utf16_offset },
+        "end": { "line": span.end.line, "character": span.end.column.utf16_offset },
+    })
+}
+
+/// Output the stack graph as an LSIF dump, one JSON vertex or edge per line
+pub fn output_lsif(stack_graph: &StackGraph, project_root: &Path, output_path: Option<&Path>) -> Result<()> {
+    let mut lsif = String::new();
+    for line in to_lsif(stack_graph, project_root)? {
+        lsif.push_str(&serde_json::to_string(&line)?);
+        lsif.push('\n');
+    }
+
+    match output_path {
+        Some(path) => {
+            fs::write(path, lsif)?;
+            info!("LSIF dump written to: {}", path.display());
+        }
+        None => {
+            print!("{}", lsif);
+        }
+    }
+
+    Ok(())
+}
+
+/// A definition as it appears in a ctags or etags file
+struct Tag {
+    name: String,
+    file: String,
+    /// One-based line of the name
+    line: usize,
+    /// Byte offset of the end of the name within its line
+    end_column: usize,
+    /// One-based last line of the whole definition, if its definiens is known
+    end_line: Option<usize>,
+    kind: Option<String>,
+    /// Kind and qualified name of the enclosing definition
+    scope: Option<(String, String)>,
+    language: Language,
+}
+
+/// One tag per definition in the graph's source files, sorted by name, file and line
+fn tags(stack_graph: &StackGraph) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    for file in stack_graph.iter_files() {
+        let file_name = stack_graph[file].name();
+        if is_builtins_file(file_name) {
+            continue;
+        }
+
+        let language = Path::new(file_name).extension()
+            .map(|ext| Language::from_extension(&ext.to_string_lossy()))
+            .unwrap_or(Language::Unknown);
+        let definitions = symbols::nested_definitions(stack_graph, file);
+        for (definition, parent) in &definitions {
+            let symbol = &definition.symbol;
+            let scope = parent.zip(symbol.container.clone()).map(|(parent, container)| {
+                let kind = definitions[parent].0.symbol.kind.clone().unwrap_or_else(|| "scope".to_string());
+                (kind, container)
+            });
+
+            tags.push(Tag {
+                name: symbol.name.clone(),
+                file: file_name.to_string(),
+                line: symbol.span.start_line + 1,
+                end_column: symbol.span.end_column,
+                end_line: definition.definiens.map(|definiens| definiens.end_line + 1),
+                kind: symbol.kind.clone(),
+                scope,
+                language,
+            });
+        }
+    }
+
+    tags.sort_by(|a, b| (&a.name, &a.file, a.line).cmp(&(&b.name, &b.file, b.line)));
+    tags
+}
+
+/// Single-letter ctags kind for a syntax type
+fn ctags_kind(syntax_type: Option<&str>) -> char {
+    match syntax_type.unwrap_or("") {
+        "function" => 'f',
+        "method" | "constructor" => 'm',
+        "class" | "impl" => 'c',
+        "interface" => 'i',
+        "trait" => 't',
+        "struct" => 's',
+        "enum" => 'g',
+        "module" | "namespace" | "package" => 'n',
+        "import" => 'x',
+        "field" | "property" => 'F',
+        "constant" => 'C',
+        "type" => 'T',
+        _ => 'v',
+    }
+}
+
+/// Render the definitions in the graph as a Universal Ctags tags file
+///
+/// Tags are sorted and addressed by a search pattern for their source line, read from
+/// the file under `project_root`, or by line number when the file cannot be read. They
+/// have the kind letter, `line`, `end` when the whole definition is known, the enclosing
+/// definition as a scope field such as `class:Parser`, and the `language` as extended
+/// fields.
+pub fn to_ctags(stack_graph: &StackGraph, project_root: &Path) -> String {
+    let mut sources: HashMap<String, Option<String>> = HashMap::new();
+    let mut ctags = String::new();
+    ctags.push_str("!_TAG_FILE_FORMAT\t2\t/extended format; --format=1 will not append ;\" to lines/\n");
+    ctags.push_str("!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/\n");
+    ctags.push_str(&format!("!_TAG_PROGRAM_NAME\t{}\t//\n", env!("CARGO_PKG_NAME")));
+    ctags.push_str(&format!("!_TAG_PROGRAM_VERSION\t{}\t//\n", env!("CARGO_PKG_VERSION")));
+
+    for tag in tags(stack_graph) {
+        let source = sources.entry(tag.file.clone())
+            .or_insert_with(|| fs::read_to_string(project_root.join(&tag.file)).ok());
+        let address = match source.as_deref().and_then(|source| source.lines().nth(tag.line - 1)) {
+            Some(line) => ctags_pattern(line),
+            None => tag.line.to_string(),
+        };
+        ctags.push_str(&format!("{}\t{}\t{};\"\t{}\tline:{}",
+            tag.name, tag.file, address, ctags_kind(tag.kind.as_deref()), tag.line));
+        if let Some(end_line) = tag.end_line.filter(|&end_line| end_line > tag.line) {
+            ctags.push_str(&format!("\tend:{}", end_line));
+        }
+        if let Some((kind, container)) = &tag.scope {
+            ctags.push_str(&format!("\t{}:{}", kind, container));
+        }
+        if tag.language != Language::Unknown {
+            ctags.push_str(&format!("\tlanguage:{}", tag.language.name()));
+        }
+        ctags.push('\n');
+    }
+
+    ctags
+}
+
+/// Search pattern for a whole source line, with the characters that are special in a
+/// tags file's patterns escaped
+fn ctags_pattern(line: &str) -> String {
+    let line = line.replace('\\', "\\\\").replace('/', "\\/");
+    format!("/^{}$/", line)
+}
+
+/// Render the definitions in the graph as an Emacs TAGS file
+///
+/// Each file gets a section with its tags in line order. The tag's pattern is its source
+/// line up to the end of the name, read from the file under `project_root`; when the file
+/// cannot be read the pattern is left empty and Emacs finds the tag by its line alone.
+pub fn to_etags(stack_graph: &StackGraph, project_root: &Path) -> String {
+    let mut tags = tags(stack_graph);
+    tags.sort_by(|a, b| (&a.file, a.line, &a.name).cmp(&(&b.file, b.line, &b.name)));
+
+    let mut etags = String::new();
+    for file_tags in tags.chunk_by(|a, b| a.file == b.file) {
+        let file_name = &file_tags[0].file;
+        let source = fs::read_to_string(project_root.join(file_name)).ok();
+        let lines: Vec<&str> = source.as_deref().map(|source| source.split_inclusive('\n').collect()).unwrap_or_default();
+        // Byte offset at which each line starts
+        let offsets: Vec<usize> = lines.iter()
+            .scan(0, |offset, line| {
+                let start = *offset;
+                *offset += line.len();
+                Some(start)
+            })
+            .collect();
+
+        let mut section = String::new();
+        for tag in file_tags {
+            let (pattern, offset) = match lines.get(tag.line - 1) {
+                Some(line) => {
+                    let line = line.trim_end_matches(['\n', '\r']);
+                    let pattern = line.get(..tag.end_column).unwrap_or(line);
+                    (pattern, offsets[tag.line - 1].to_string())
+                }
+                None => ("", String::new()),
+            };
+            section.push_str(&format!("{}\x7f{}\x01{},{}\n", pattern, tag.name, tag.line, offset));
+        }
+
+        etags.push_str(&format!("\x0c\n{},{}\n{}", file_name, section.len(), section));
+    }
+
+    etags
+}
+
+/// Output the definitions in the stack graph as a Universal Ctags tags file
+pub fn output_ctags(stack_graph: &StackGraph, project_root: &Path, output_path: Option<&Path>) -> Result<()> {
+    let ctags = to_ctags(stack_graph, project_root);
+
+    match output_path {
+        Some(path) => {
+            fs::write(path, ctags)?;
+            info!("Tags written to: {}", path.display());
+        }
+        None => {
+            print!("{}", ctags);
+        }
+    }
+
+    Ok(())
+}
+
+/// Output the definitions in the stack graph as an Emacs TAGS file
+pub fn output_etags(stack_graph: &StackGraph, project_root: &Path, output_path: Option<&Path>) -> Result<()> {
+    let etags = to_etags(stack_graph, project_root);
+
+    match output_path {
+        Some(path) => {
+            fs::write(path, etags)?;
+            info!("Tags written to: {}", path.display());
+        }
+        None => {
+            print!("{}", etags);
+        }
+    }
+
+    Ok(())
+}
+
+/// A node of the property graph that graph database exports are written from
+struct PropertyNode {
+    id: String,
+    label: &'static str,
+    properties: Vec<(&'static str, Value)>,
+}
+
+/// A relationship of the property graph that graph database exports are written from
+struct PropertyEdge {
+    source: String,
+    target: String,
+    label: &'static str,
+    properties: Vec<(&'static str, Value)>,
+}
+
+/// The stack graph as a property graph for graph databases
+///
+/// Files become `File` nodes, and every stack graph node becomes a `Definition`,
+/// `Reference`, `Scope` (including the root and jump-to nodes) or plain `Node` node with
+/// its kind, symbol, file, zero-based span, syntax type and qualified name. Files
+/// `CONTAIN` their nodes, stack graph edges become `EDGE` relationships with their
+/// precedence, and each reference has a `RESOLVES_TO` relationship to every definition
+/// its paths resolve to.
+struct PropertyGraph {
+    nodes: Vec<PropertyNode>,
+    edges: Vec<PropertyEdge>,
+}
+
+impl PropertyGraph {
+    fn from_graph(stack_graph: &StackGraph) -> Result<Self> {
+        let node_id = |node: Handle<Node>| format!("n{}", node.as_u32());
+        let file_id = |file: Handle<File>| format!("f{}", file.as_u32());
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+
+        for file in stack_graph.iter_files() {
+            let name = stack_graph[file].name();
+            if is_builtins_file(name) {
+                continue;
+            }
+
+            let language = Path::new(name).extension()
+                .map(|ext| Language::from_extension(&ext.to_string_lossy()))
+                .unwrap_or(Language::Unknown);
+            nodes.push(PropertyNode {
+                id: file_id(file),
+                label: "File",
+                properties: vec![("path", json!(name)), ("language", json!(language.name()))],
+            });
+        }
+
+        // Builtins are left out along with their files
+        let included = |node: Handle<Node>| match stack_graph[node].id().file() {
+            Some(file) => !is_builtins_file(stack_graph[file].name()),
+            None => true,
+        };
+
+        for node in stack_graph.iter_nodes().filter(|&node| included(node)) {
+            let value = &stack_graph[node];
+            let (label, kind) = match value {
+                _ if value.is_definition() => ("Definition", stats::node_kind(value)),
+                _ if value.is_reference() => ("Reference", stats::node_kind(value)),
+                Node::Root(_) | Node::JumpTo(_) | Node::Scope(_) => ("Scope", stats::node_kind(value)),
+                _ => ("Node", stats::node_kind(value)),
+            };
+
+            let mut properties = vec![("kind", json!(kind))];
+            if let Some(symbol) = value.symbol() {
+                properties.push(("symbol", json!(&stack_graph[symbol])));
+            }
+            if let Some(file) = value.id().file() {
+                properties.push(("file", json!(stack_graph[file].name())));
+                edges.push(PropertyEdge {
+                    source: file_id(file),
+                    target: node_id(node),
+                    label: "CONTAINS",
+                    properties: Vec::new(),
+                });
+            }
+            if let Some(source_info) = stack_graph.source_info(node) {
+                if let Some(span) = Span::from_lsp(&source_info.span) {
+                    properties.push(("start_line", json!(span.start_line)));
+                    properties.push(("start_column", json!(span.start_column)));
+                    properties.push(("end_line", json!(span.end_line)));
+                    properties.push(("end_column", json!(span.end_column)));
+                }
+                if let Some(syntax_type) = source_info.syntax_type.into_option() {
+                    properties.push(("syntax_type", json!(&stack_graph[syntax_type])));
+                }
+                if let Some(name) = source_info.fully_qualified_name.into_option() {
+                    properties.push(("qualified_name", json!(&stack_graph[name])));
+                }
+            }
+            nodes.push(PropertyNode { id: node_id(node), label, properties });
+
+            for edge in stack_graph.outgoing_edges(node).filter(|edge| included(edge.sink)) {
+                edges.push(PropertyEdge {
+                    source: node_id(edge.source),
+                    target: node_id(edge.sink),
+                    label: "EDGE",
+                    properties: vec![("precedence", json!(edge.precedence))],
+                });
+            }
+        }
+
+        let mut resolved = QueryEngine::new(stack_graph)?.resolve_all()?;
+        resolved.sort();
+        resolved.dedup();
+        for (reference, definition) in resolved {
+            if included(reference) && included(definition) {
+                edges.push(PropertyEdge {
+                    source: node_id(reference),
+                    target: node_id(definition),
+                    label: "RESOLVES_TO",
+                    properties: Vec::new(),
+                });
+            }
+        }
+
+        Ok(Self { nodes, edges })
+    }
+
+    /// Label of each node by id, for matching both ends of a relationship
+    fn labels(&self) -> HashMap<&str, &'static str> {
+        self.nodes.iter().map(|node| (node.id.as_str(), node.label)).collect()
+    }
+
+    /// Names of the node and edge properties in use, in order of first appearance, along
+    /// with whether their values are numbers
+    fn property_names<'a>(properties: impl Iterator<Item = &'a (&'static str, Value)>) -> Vec<(&'static str, bool)> {
+        let mut names: Vec<(&'static str, bool)> = Vec::new();
+        for (name, value) in properties {
+            if !names.iter().any(|(known, _)| known == name) {
+                names.push((name, value.is_number()));
+            }
+        }
+        names
+    }
+}
+
+/// Render the stack graph as Cypher statements that load it into Neo4j
+///
+/// Nodes are merged on their `id`, with an index on it for every label, and
+/// relationships are merged between nodes matched by label and id, so the script can be
+/// run again over the same database.
+pub fn to_cypher(stack_graph: &StackGraph) -> Result<String> {
+    let graph = PropertyGraph::from_graph(stack_graph)?;
+    let labels = graph.labels();
+
+    let mut cypher = String::new();
+    let mut used: Vec<&str> = graph.nodes.iter().map(|node| node.label).collect();
+    used.sort();
+    used.dedup();
+    for label in used {
+        cypher.push_str(&format!("CREATE INDEX IF NOT EXISTS FOR (n:{}) ON (n.id);\n", label));
+    }
+
+    for node in &graph.nodes {
+        cypher.push_str(&format!("MERGE (n:{} {{id: {}}}) SET n += {};\n",
+            node.label, cypher_value(&json!(node.id)), cypher_map(&node.properties)));
+    }
+    for edge in &graph.edges {
+        let properties = if edge.properties.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", cypher_map(&edge.properties))
+        };
+        cypher.push_str(&format!("MATCH (a:{} {{id: {}}}), (b:{} {{id: {}}}) MERGE (a)-[:{}{}]->(b);\n",
+            labels[edge.source.as_str()], cypher_value(&json!(edge.source)),
+            labels[edge.target.as_str()], cypher_value(&json!(edge.target)),
+            edge.label, properties));
+    }
+
+    Ok(cypher)
+}
+
+/// Cypher map literal of a list of properties
+fn cypher_map(properties: &[(&'static str, Value)]) -> String {
+    let entries: Vec<String> = properties.iter()
+        .map(|(name, value)| format!("{}: {}", name, cypher_value(value)))
+        .collect();
+    format!("{{{}}}", entries.join(", "))
+}
+
+/// Cypher literal of a string or number property
+fn cypher_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => format!("\"{}\"", text
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")),
+        other => other.to_string(),
+    }
+}
+
+/// Render the stack graph as GraphML
+///
+/// Each node's label is in its `labels` data as `:Definition` and so on, and each
+/// edge's in its `label` data, the way APOC's GraphML import reads them.
+pub fn to_graphml(stack_graph: &StackGraph) -> Result<String> {
+    let graph = PropertyGraph::from_graph(stack_graph)?;
+
+    let mut graphml = String::new();
+    graphml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    graphml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+
+    let node_properties = PropertyGraph::property_names(graph.nodes.iter().flat_map(|node| &node.properties));
+    let edge_properties = PropertyGraph::property_names(graph.edges.iter().flat_map(|edge| &edge.properties));
+    graphml.push_str("  <key id=\"labels\" for=\"node\" attr.name=\"labels\" attr.type=\"string\"/>\n");
+    for (name, number) in &node_properties {
+        graphml.push_str(&format!("  <key id=\"v_{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"{1}\"/>\n",
+            name, if *number { "long" } else { "string" }));
+    }
+    graphml.push_str("  <key id=\"label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n");
+    for (name, number) in &edge_properties {
+        graphml.push_str(&format!("  <key id=\"e_{0}\" for=\"edge\" attr.name=\"{0}\" attr.type=\"{1}\"/>\n",
+            name, if *number { "long" } else { "string" }));
+    }
+
+    graphml.push_str("  <graph id=\"stack_graph\" edgedefault=\"directed\">\n");
+    for node in &graph.nodes {
+        graphml.push_str(&format!("    <node id=\"{}\" labels=\":{1}\">\n", escape_xml(&node.id), node.label));
+        graphml.push_str(&format!("      <data key=\"labels\">:{}</data>\n", node.label));
+        for (name, value) in &node.properties {
+            graphml.push_str(&format!("      <data key=\"v_{}\">{}</data>\n", name, escape_xml(&property_text(value))));
+        }
+        graphml.push_str("    </node>\n");
+    }
+    for (index, edge) in graph.edges.iter().enumerate() {
+        graphml.push_str(&format!("    <edge id=\"e{}\" source=\"{}\" target=\"{}\" label=\"{}\">\n",
+            index, escape_xml(&edge.source), escape_xml(&edge.target), edge.label));
+        graphml.push_str(&format!("      <data key=\"label\">{}</data>\n", edge.label));
+        for (name, value) in &edge.properties {
+            graphml.push_str(&format!("      <data key=\"e_{}\">{}</data>\n", name, escape_xml(&property_text(value))));
+        }
+        graphml.push_str("    </edge>\n");
+    }
+    graphml.push_str("  </graph>\n");
+    graphml.push_str("</graphml>\n");
+
+    Ok(graphml)
+}
+
+/// Text of a property value, without the quotes JSON puts around strings
+fn property_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Escape text for use in XML content and attribute values
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Render the stack graph as the node and relationship CSV files `neo4j-admin import`
+/// reads, with typed headers such as `id:ID`, `:LABEL` and `start_line:long`
+pub fn to_neo4j_csv(stack_graph: &StackGraph) -> Result<(String, String)> {
+    let graph = PropertyGraph::from_graph(stack_graph)?;
+
+    let header = |names: &[(&'static str, bool)]| -> Vec<String> {
+        names.iter()
+            .map(|(name, number)| if *number { format!("{}:long", name) } else { name.to_string() })
+            .collect()
+    };
+    let row = |names: &[(&'static str, bool)], properties: &[(&'static str, Value)]| -> Vec<String> {
+        names.iter()
+            .map(|(name, _)| properties.iter()
+                .find(|(property, _)| property == name)
+                .map(|(_, value)| escape_csv(&property_text(value)))
+                .unwrap_or_default())
+            .collect()
+    };
+
+    let node_properties = PropertyGraph::property_names(graph.nodes.iter().flat_map(|node| &node.properties));
+    let mut nodes = [vec!["id:ID".to_string(), ":LABEL".to_string()], header(&node_properties)].concat().join(",");
+    nodes.push('\n');
+    for node in &graph.nodes {
+        let fields = [vec![escape_csv(&node.id), node.label.to_string()], row(&node_properties, &node.properties)].concat();
+        nodes.push_str(&fields.join(","));
+        nodes.push('\n');
+    }
+
+    let edge_properties = PropertyGraph::property_names(graph.edges.iter().flat_map(|edge| &edge.properties));
+    let mut relationships = [
+        vec![":START_ID".to_string(), ":END_ID".to_string(), ":TYPE".to_string()],
+        header(&edge_properties),
+    ].concat().join(",");
+    relationships.push('\n');
+    for edge in &graph.edges {
+        let fields = [
+            vec![escape_csv(&edge.source), escape_csv(&edge.target), edge.label.to_string()],
+            row(&edge_properties, &edge.properties),
+        ].concat();
+        relationships.push_str(&fields.join(","));
+        relationships.push('\n');
+    }
+
+    Ok((nodes, relationships))
+}
+
+/// Quote a CSV field if it holds a separator, quote or line break
+fn escape_csv(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Output the stack graph as Cypher statements
+pub fn output_cypher(stack_graph: &StackGraph, output_path: Option<&Path>) -> Result<()> {
+    let cypher = to_cypher(stack_graph)?;
+
+    match output_path {
+        Some(path) => {
+            fs::write(path, cypher)?;
+            info!("Cypher script written to: {}", path.display());
+        }
+        None => {
+            print!("{}", cypher);
+        }
+    }
+
+    Ok(())
+}
+
+/// Output the stack graph as GraphML
+pub fn output_graphml(stack_graph: &StackGraph, output_path: Option<&Path>) -> Result<()> {
+    let graphml = to_graphml(stack_graph)?;
+
+    match output_path {
+        Some(path) => {
+            fs::write(path, graphml)?;
+            info!("Stack graph written to: {}", path.display());
+        }
+        None => {
+            print!("{}", graphml);
+        }
+    }
+
+    Ok(())
+}
+
+/// Output the stack graph as `nodes.csv` and `relationships.csv` in a directory, for
+/// `neo4j-admin database import full --nodes=nodes.csv --relationships=relationships.csv`
+pub fn output_neo4j_csv(stack_graph: &StackGraph, output_dir: Option<&Path>) -> Result<()> {
+    let Some(output_dir) = output_dir else {
+        anyhow::bail!("Neo4j CSV output needs an output directory");
+    };
+    let (nodes, relationships) = to_neo4j_csv(stack_graph)?;
+
+    fs::create_dir_all(output_dir)?;
+    fs::write(output_dir.join("nodes.csv"), nodes)?;
+    fs::write(output_dir.join("relationships.csv"), relationships)?;
+    info!("Neo4j CSV files written to: {}", output_dir.display());
+
+    Ok(())
+}
+
+/// One line of NDJSON output
+///
+/// Nodes and edges use the `stack_graphs::serde` format of the JSON output, with a
+/// `record` field added to tell the kinds of line apart.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum NdjsonRecord {
+    File { name: String },
+    Node(stack_graphs::serde::Node),
+    Edge(stack_graphs::serde::Edge),
+    /// A reference and a definition that a complete path resolves it to
+    Path {
+        reference: stack_graphs::serde::NodeID,
+        definition: stack_graphs::serde::NodeID,
+    },
+    /// Counts of everything written, as the last line
+    Summary { files: usize, nodes: usize, edges: usize, paths: usize },
+}
+
+/// Writes a stack graph as NDJSON, one record per line, a file at a time
+///
+/// Each file is written with its nodes, their outgoing edges and the edges into it from
+/// the root and jump-to nodes. The file can come from its own graph, such as one built by
+/// [`crate::indexer::index_file_isolated`], so files can be written as they are indexed
+/// without keeping them around. The root and jump-to nodes come before the first file,
+/// and [`NdjsonStream::finish`] writes any files left, such as builtins, every resolved
+/// path and a summary.
+pub struct NdjsonStream<W: Write> {
+    writer: W,
+    /// Names of the files written so far
+    written: HashSet<String>,
+    started: bool,
+    nodes: usize,
+    edges: usize,
+}
+
+impl<W: Write> NdjsonStream<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            written: HashSet::new(),
+            started: false,
+            nodes: 0,
+            edges: 0,
+        }
+    }
+
+    /// Write a file along with its nodes and their edges, unless a file of the same name
+    /// has been written already
+    pub fn write_file(&mut self, stack_graph: &StackGraph, file: Handle<File>) -> Result<()> {
+        if self.written.contains(stack_graph[file].name()) {
+            return Ok(());
+        }
+        self.start(stack_graph)?;
+        self.write_file_records(stack_graph, file)?;
+        for global in [StackGraph::root_node(), StackGraph::jump_to_node()] {
+            self.write_edges(stack_graph, global, |sink| stack_graph[sink].id().file() == Some(file))?;
+        }
+
+        // Let readers see each file as soon as it is written
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the files of the stack graph not written yet, the resolved paths and a
+    /// summary, and hand back the writer
+    pub fn finish(self, stack_graph: &StackGraph) -> Result<W> {
+        self.finish_with(&mut QueryEngine::new(stack_graph)?)
+    }
+
+    /// [`NdjsonStream::finish`], resolving paths with a query engine, such as one over
+    /// the index database the written files were stored in
+    pub fn finish_with(mut self, engine: &mut QueryEngine) -> Result<W> {
+        let mut resolved = engine.resolve_all()?;
+        resolved.sort();
+        resolved.dedup();
+
+        let stack_graph = engine.graph();
+        self.start(stack_graph)?;
+        let files: HashSet<Handle<File>> = stack_graph.iter_files()
+            .filter(|&file| !self.written.contains(stack_graph[file].name()))
+            .collect();
+        for file in stack_graph.iter_files().filter(|file| files.contains(file)) {
+            self.write_file_records(stack_graph, file)?;
+        }
+        for global in [StackGraph::root_node(), StackGraph::jump_to_node()] {
+            self.write_edges(stack_graph, global, |sink| {
+                stack_graph[sink].id().file().is_some_and(|file| files.contains(&file))
+            })?;
+        }
+
+        for &(reference, definition) in &resolved {
+            self.write(&NdjsonRecord::Path {
+                reference: stack_graphs::serde::NodeID::from_node(stack_graph, reference),
+                definition: stack_graphs::serde::NodeID::from_node(stack_graph, definition),
+            })?;
+        }
+
+        self.write(&NdjsonRecord::Summary {
+            files: self.written.len(),
+            nodes: self.nodes,
+            edges: self.edges,
+            paths: resolved.len(),
+        })?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Write the root and jump-to nodes, which edges of every file lead to
+    fn start(&mut self, stack_graph: &StackGraph) -> Result<()> {
+        if !self.started {
+            self.started = true;
+            for global in [StackGraph::root_node(), StackGraph::jump_to_node()] {
+                self.write_node(stack_graph, global)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a file record, the file's nodes and their outgoing edges
+    fn write_file_records(&mut self, stack_graph: &StackGraph, file: Handle<File>) -> Result<()> {
+        let name = stack_graph[file].name().to_string();
+        self.write(&NdjsonRecord::File { name: name.clone() })?;
+        self.written.insert(name);
+        for node in stack_graph.nodes_for_file(file) {
+            self.write_node(stack_graph, node)?;
+        }
+        for node in stack_graph.nodes_for_file(file) {
+            self.write_edges(stack_graph, node, |_| true)?;
+        }
+        Ok(())
+    }
+
+    fn write_node(&mut self, stack_graph: &StackGraph, node: Handle<Node>) -> Result<()> {
+        self.nodes += 1;
+        self.write(&NdjsonRecord::Node(serializable_node(stack_graph, node)))
+    }
+
+    /// Write the edges out of a node whose sink passes the filter
+    fn write_edges(&mut self, stack_graph: &StackGraph, node: Handle<Node>, filter: impl Fn(Handle<Node>) -> bool) -> Result<()> {
+        for edge in stack_graph.outgoing_edges(node).filter(|edge| filter(edge.sink)) {
+            self.edges += 1;
+            self.write(&NdjsonRecord::Edge(stack_graphs::serde::Edge {
+                source: stack_graphs::serde::NodeID::from_node(stack_graph, edge.source),
+                sink: stack_graphs::serde::NodeID::from_node(stack_graph, edge.sink),
+                precedence: edge.precedence,
+                debug_info: stack_graph.edge_debug_info(edge.source, edge.sink).map(|info| serializable_debug_info(stack_graph, info)),
+            }))?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, record: &NdjsonRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// A single node in the `stack_graphs::serde` format
+fn serializable_node(stack_graph: &StackGraph, node: Handle<Node>) -> stack_graphs::serde::Node {
+    use stack_graphs::serde::{Node as SerializableNode, NodeID, SourceInfo};
+
+    let value = &stack_graph[node];
+    let id = NodeID::from_node(stack_graph, node);
+    let source_info = stack_graph.source_info(node).map(|info| SourceInfo {
+        span: info.span.clone(),
+        syntax_type: info.syntax_type.into_option().map(|syntax_type| stack_graph[syntax_type].to_string()),
+    });
+    let debug_info = stack_graph.node_debug_info(node).map(|info| serializable_debug_info(stack_graph, info));
+    let symbol = || value.symbol().map(|symbol| stack_graph[symbol].to_string()).unwrap_or_default();
+
+    match value {
+        Node::DropScopes(_) => SerializableNode::DropScopes { id, source_info, debug_info },
+        Node::JumpTo(_) => SerializableNode::JumpToScope { id, source_info, debug_info },
+        Node::PopScopedSymbol(pop) => SerializableNode::PopScopedSymbol {
+            id, symbol: symbol(), is_definition: pop.is_definition, source_info, debug_info,
+        },
+        Node::PopSymbol(pop) => SerializableNode::PopSymbol {
+            id, symbol: symbol(), is_definition: pop.is_definition, source_info, debug_info,
+        },
+        Node::PushScopedSymbol(push) => SerializableNode::PushScopedSymbol {
+            id,
+            symbol: symbol(),
+            scope: NodeID::from_node_id(stack_graph, push.scope),
+            is_reference: push.is_reference,
+            source_info,
+            debug_info,
+        },
+        Node::PushSymbol(push) => SerializableNode::PushSymbol {
+            id, symbol: symbol(), is_reference: push.is_reference, source_info, debug_info,
+        },
+        Node::Root(_) => SerializableNode::Root { id, source_info, debug_info },
+        Node::Scope(scope) => SerializableNode::Scope {
+            id, is_exported: scope.is_exported, source_info, debug_info,
+        },
+    }
+}
+
+fn serializable_debug_info(stack_graph: &StackGraph, info: &stack_graphs::graph::DebugInfo) -> stack_graphs::serde::DebugInfo {
+    stack_graphs::serde::DebugInfo {
+        data: info.iter()
+            .map(|entry| stack_graphs::serde::DebugEntry {
+                key: stack_graph[entry.key].to_string(),
+                value: stack_graph[entry.value].to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Where NDJSON goes: the output file, or standard output
+pub fn ndjson_stream(output_path: Option<&Path>) -> Result<NdjsonStream<Box<dyn Write>>> {
+    let writer: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+    Ok(NdjsonStream::new(writer))
+}
+
+/// Output the stack graph as NDJSON, streaming one record per file, node, edge and
+/// resolved path
+pub fn output_ndjson(stack_graph: &StackGraph, output_path: Option<&Path>) -> Result<()> {
+    ndjson_stream(output_path)?.finish(stack_graph)?;
+    if let Some(path) = output_path {
+        info!("Stack graph written to: {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stack_graphs::serde::Node as SerializedNode;
+    use crate::test_support::{graph, project};
+
+    #[test]
+    fn formats_round_trip_through_display() {
+        for format in OutputFormat::ALL.iter() {
+            assert_eq!(format.to_string().parse::<OutputFormat>().unwrap(), *format);
+        }
+    }
+
+    #[test]
+    fn format_names_ignore_case() {
+        assert_eq!("GraphML".parse::<OutputFormat>().unwrap(), OutputFormat::Graphml);
+        assert_eq!("NDJSON".parse::<OutputFormat>().unwrap(), OutputFormat::Ndjson);
+    }
+
+    #[test]
+    fn unknown_format_lists_the_known_ones() {
+        let error = "yaml".parse::<OutputFormat>().unwrap_err().to_string();
+        assert!(error.starts_with("Unknown output format `yaml`"), "{}", error);
+        assert!(error.contains("json, ndjson, dot"), "{}", error);
+    }
+
+    #[cfg(not(feature = "scip"))]
+    #[test]
+    fn scip_without_feature_names_the_feature() {
+        let error = "scip".parse::<OutputFormat>().unwrap_err().to_string();
+        assert!(error.contains("`scip` feature"), "{}", error);
+    }
+
+    /// Source, sink and precedence of every edge, with nodes named by file and local id
+    fn edges(graph: &StackGraph) -> Vec<(String, String, i32)> {
+        let id = |node: Handle<Node>| {
+            let id = graph[node].id();
+            let file = id.file().map_or("", |file| graph[file].name());
+            format!("{}#{}", file, id.local_id())
+        };
+        let mut edges: Vec<_> = graph.iter_nodes()
+            .flat_map(|node| graph.outgoing_edges(node))
+            .map(|edge| (id(edge.source), id(edge.sink), edge.precedence))
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn json_graph_round_trips() {
+        let graph = graph(&[("app.py", "import os\n\ndef helper():\n    pass\n\nhelper()\n")]);
+        let json = serde_json::to_string(&JsonGraph::from_graph(&graph)).unwrap();
+        let read: JsonGraph = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(read.summary.files, graph.iter_files().count());
+        assert_eq!(read.summary.nodes, read.graph.nodes.data.len());
+        assert_eq!(read.summary.edges, read.graph.edges.data.len());
+        assert!(read.graph.files.data.contains(&"app.py".to_string()));
+
+        // The definition keeps its symbol, flag, span and syntax type
+        let definition = read.graph.nodes.data.iter()
+            .find_map(|node| match node {
+                SerializedNode::PopSymbol { symbol, is_definition: true, source_info: Some(source_info), .. }
+                    if symbol == "helper" => Some(source_info),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!((definition.span.start.line, definition.span.start.column.utf8_offset), (2, 4));
+        assert_eq!(definition.syntax_type.as_deref(), Some("function"));
+
+        let mut loaded = StackGraph::new();
+        read.graph.load_into(&mut loaded).unwrap();
+        assert_eq!(loaded.iter_nodes().count(), graph.iter_nodes().count());
+        assert_eq!(edges(&loaded), edges(&graph));
+        assert!(edges(&graph).iter().any(|(_, _, precedence)| *precedence != 0));
+
+        let definitions = QueryEngine::new(&loaded).unwrap().find_definitions("app.py", 5, 0).unwrap();
+        assert_eq!(definitions.len(), 1);
+    }
+
+    /// A graph with a chain of scopes in `a.js` from the root down to a definition of
+    /// `x`, and a reference to `x` in `b.js` that leads to the root, with the nodes in
+    /// source order: `[definition, a1, a2, a3, reference, b1]`
+    fn dot_fixture() -> (StackGraph, [Handle<Node>; 6]) {
+        let mut graph = StackGraph::new();
+        let a = graph.add_file("a.js").unwrap();
+        let b = graph.add_file("b.js").unwrap();
+        let x = graph.add_symbol("x");
+
+        let id = graph.new_node_id(a);
+        let definition = graph.add_pop_symbol_node(id, x, true).unwrap();
+        let mut scopes = Vec::new();
+        for _ in 0..3 {
+            let id = graph.new_node_id(a);
+            scopes.push(graph.add_scope_node(id, false).unwrap());
+        }
+        let id = graph.new_node_id(b);
+        let reference = graph.add_push_symbol_node(id, x, true).unwrap();
+        let id = graph.new_node_id(b);
+        let b1 = graph.add_scope_node(id, false).unwrap();
+
+        let [a1, a2, a3] = [scopes[0], scopes[1], scopes[2]];
+        graph.add_edge(StackGraph::root_node(), a3, 0);
+        graph.add_edge(a3, a2, 0);
+        graph.add_edge(a2, a1, 1);
+        graph.add_edge(a1, definition, 0);
+        graph.add_edge(b1, reference, 0);
+        graph.add_edge(reference, StackGraph::root_node(), 0);
+
+        (graph, [definition, a1, a2, a3, reference, b1])
+    }
+
+    fn dot_filter(file: Option<&str>, symbol: Option<&str>, depth: usize) -> DotFilter {
+        DotFilter { file: file.map(str::to_string), symbol: symbol.map(str::to_string), depth }
+    }
+
+    #[test]
+    fn dot_filter_by_file() {
+        let (graph, [definition, a1, a2, a3, reference, b1]) = dot_fixture();
+        let root = StackGraph::root_node();
+
+        let all = DotFilter::default().select(&graph);
+        assert_eq!(all, HashSet::from([root, definition, a1, a2, a3, reference, b1]));
+
+        let a = dot_filter(Some("a.js"), None, 2).select(&graph);
+        assert_eq!(a, HashSet::from([root, definition, a1, a2, a3]));
+
+        assert!(dot_filter(Some("c.js"), None, 2).select(&graph).is_empty());
+    }
+
+    #[test]
+    fn dot_filter_by_symbol_and_depth() {
+        let (graph, [definition, a1, a2, _, reference, b1]) = dot_fixture();
+        let root = StackGraph::root_node();
+
+        let nearest = dot_filter(None, Some("x"), 0).select(&graph);
+        assert_eq!(nearest, HashSet::from([root, definition, reference]));
+
+        // The search does not spread through the root into the other file
+        let near = dot_filter(Some("a.js"), Some("x"), 2).select(&graph);
+        assert_eq!(near, HashSet::from([definition, a1, a2]));
+
+        let near = dot_filter(None, Some("x"), 1).select(&graph);
+        assert_eq!(near, HashSet::from([root, definition, a1, reference, b1]));
+
+        assert!(dot_filter(None, Some("y"), 2).select(&graph).is_empty());
+    }
+
+    #[test]
+    fn dot_draws_only_edges_between_drawn_nodes() {
+        let (graph, [definition, a1, a2, a3, ..]) = dot_fixture();
+        let dot = to_dot(&graph, &dot_filter(Some("a.js"), Some("x"), 2));
+        let edges: Vec<&str> = dot.lines().filter(|line| line.contains("->")).collect();
+
+        let edge = |source: Handle<Node>, sink: Handle<Node>| format!("n{} -> n{}", source.as_u32(), sink.as_u32());
+        assert_eq!(edges, [
+            format!("  {};", edge(a1, definition)),
+            format!("  {} [label=\"1\"];", edge(a2, a1)),
+        ]);
+        assert!(!dot.contains(&format!("n{} [", a3.as_u32())));
+        assert!(dot.contains("label=\"a.js\""));
+        assert!(!dot.contains("label=\"b.js\""));
+    }
+
+    #[cfg(feature = "scip")]
+    #[test]
+    fn scip_index_has_documents_symbols_and_occurrences() {
+        let graph = graph(&[
+            (
+                "shapes.py",
+                "class Shape:\n    def area(self):\n        pass\n",
+            ),
+            ("main.py", "from shapes import Shape\n\nunit = Shape()\n"),
+        ]);
+        let index = to_scip(&graph, Path::new("/project")).unwrap();
+
+        let paths: Vec<&str> = index
+            .documents
+            .iter()
+            .map(|document| document.relative_path.as_str())
+            .collect();
+        assert_eq!(paths, ["main.py", "shapes.py"]);
+        let (main, shapes) = (&index.documents[0], &index.documents[1]);
+
+        // A nested definition's symbol goes through the file and its class
+        let shape = "tsg_indexer . . . `shapes.py`/Shape#";
+        let area = "tsg_indexer . . . `shapes.py`/Shape#area().";
+        let definition = shapes
+            .occurrences
+            .iter()
+            .find(|occurrence| occurrence.symbol == area)
+            .unwrap();
+        assert_eq!(
+            definition.symbol_roles,
+            scip_types::SymbolRole::Definition as i32
+        );
+        assert_eq!(definition.range, [1, 8, 12]);
+        assert_eq!(definition.enclosing_range, [1, 4, 2, 12]);
+        let information = shapes
+            .symbols
+            .iter()
+            .find(|information| information.symbol == area)
+            .unwrap();
+        assert_eq!(information.enclosing_symbol, shape);
+        assert_eq!(
+            information.kind,
+            scip_types::symbol_information::Kind::Method.into()
+        );
+
+        let reference = main
+            .occurrences
+            .iter()
+            .find(|occurrence| occurrence.range == [2, 7, 12] && occurrence.symbol == shape)
+            .unwrap();
+        assert_eq!(reference.symbol_roles, 0);
+    }
+
+    #[test]
+    fn lsif_dump_links_results_of_one_reference() {
+        let graph = graph(&[("a.js", "function f() {}\nf();\n")]);
+        let lines = to_lsif(&graph, Path::new("/project")).unwrap();
+
+        assert_eq!(lines[0]["id"], 1);
+        assert_eq!(lines[0]["label"], "metaData");
+        assert_eq!(lines[0]["projectRoot"], "file:///project");
+        let range = |line: u32, start: u32, end: u32| json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        });
+        let line = |id: usize, kind: &str, label: &str, mut fields: Value| {
+            fields["id"] = json!(id);
+            fields["type"] = json!(kind);
+            fields["label"] = json!(label);
+            fields
+        };
+        let vertex = |id, label, fields| line(id, "vertex", label, fields);
+        let edge = |id, label, fields| line(id, "edge", label, fields);
+        assert_eq!(lines[1..], [
+            vertex(2, "document", json!({ "uri": "file:///project/a.js", "languageId": "javascript" })),
+            vertex(3, "range", range(0, 9, 10)),
+            vertex(4, "resultSet", json!({})),
+            edge(5, "next", json!({ "outV": 3, "inV": 4 })),
+            vertex(6, "definitionResult", json!({})),
+            edge(7, "textDocument/definition", json!({ "outV": 4, "inV": 6 })),
+            edge(8, "item", json!({ "outV": 6, "inVs": [3], "document": 2 })),
+            vertex(9, "hoverResult", json!({
+                "result": { "contents": { "kind": "markdown", "value": "```javascript\nfunction f\n```" } },
+            })),
+            edge(10, "textDocument/hover", json!({ "outV": 4, "inV": 9 })),
+            // The reference gets its own range, pointing at the definition's result set
+            vertex(11, "range", range(1, 0, 1)),
+            edge(12, "next", json!({ "outV": 11, "inV": 4 })),
+            edge(13, "contains", json!({ "outV": 2, "inVs": [3, 11] })),
+            vertex(14, "referenceResult", json!({})),
+            edge(15, "textDocument/references", json!({ "outV": 4, "inV": 14 })),
+            edge(16, "item", json!({ "outV": 14, "inVs": [3], "document": 2, "property": "definitions" })),
+            edge(17, "item", json!({ "outV": 14, "inVs": [11], "document": 2, "property": "references" })),
+        ]);
+    }
+
+    /// The stack graph of a project's files and the project directory they are read from
+    fn project_graph(files: &[(&str, &str)]) -> (StackGraph, tempfile::TempDir) {
+        let project = project(files);
+        let mut stack_graph = StackGraph::new();
+        for (name, _) in files {
+            crate::indexer::index_file(&mut stack_graph, project.path(), &project.path().join(name)).unwrap();
+        }
+        (stack_graph, project)
+    }
+
+    #[test]
+    fn ctags_are_sorted_with_escaped_patterns_and_fields() {
+        let (graph, project) = project_graph(&[
+            ("shapes.js", "class Shape {\n  area() { return 1 / 2; }\n}\n"),
+            ("util.js", "function Zeta() { return '\\\\'; }\nfunction alpha() {}\n"),
+        ]);
+
+        let ctags = to_ctags(&graph, project.path());
+        let lines: Vec<&str> = ctags.lines().collect();
+        assert_eq!(lines[..4], [
+            "!_TAG_FILE_FORMAT\t2\t/extended format; --format=1 will not append ;\" to lines/",
+            "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/",
+            "!_TAG_PROGRAM_NAME\ttsg_indexer\t//",
+            &format!("!_TAG_PROGRAM_VERSION\t{}\t//", env!("CARGO_PKG_VERSION")),
+        ]);
+        // Sorted by bytes, so upper case names come first
+        assert_eq!(lines[4..], [
+            "Shape\tshapes.js\t/^class Shape {$/;\"\tc\tline:1\tend:3\tlanguage:JavaScript",
+            "Zeta\tutil.js\t/^function Zeta() { return '\\\\\\\\'; }$/;\"\tf\tline:1\tlanguage:JavaScript",
+            "alpha\tutil.js\t/^function alpha() {}$/;\"\tf\tline:2\tlanguage:JavaScript",
+            "area\tshapes.js\t/^  area() { return 1 \\/ 2; }$/;\"\tm\tline:2\tclass:Shape\tlanguage:JavaScript",
+        ]);
+
+        // Without the source, tags are addressed by line number
+        fs::remove_file(project.path().join("util.js")).unwrap();
+        let ctags = to_ctags(&graph, project.path());
+        assert!(ctags.contains("\nalpha\tutil.js\t2;\"\tf\tline:2\tlanguage:JavaScript\n"));
+    }
+
+    #[test]
+    fn etags_sections_count_their_bytes() {
+        let (graph, project) = project_graph(&[
+            ("shapes.js", "class Shape {\n  area() { return 1 / 2; }\n}\n"),
+            ("util.js", "function alpha() {}\nfunction beta() {}\n"),
+        ]);
+
+        let etags = to_etags(&graph, project.path());
+        let shapes = "class Shape\x7fShape\x011,0\n  area\x7farea\x012,14\n";
+        let util = "function alpha\x7falpha\x011,0\nfunction beta\x7fbeta\x012,20\n";
+        assert_eq!(etags, format!(
+            "\x0c\nshapes.js,{}\n{}\x0c\nutil.js,{}\n{}",
+            shapes.len(), shapes, util.len(), util,
+        ));
+        assert_eq!(shapes.len(), 39);
+    }
+
+    #[test]
+    fn property_values_are_escaped() {
+        assert_eq!(cypher_value(&json!("a \"b\" \\ c\r\nd")), r#""a \"b\" \\ c\r\nd""#);
+        assert_eq!(cypher_value(&json!(3)), "3");
+        assert_eq!(escape_xml("<a href=\"x\">'&'</a>"), "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;");
+        assert_eq!(escape_csv("plain"), "plain");
+        assert_eq!(escape_csv("a, \"b\""), r#""a, ""b""""#);
+        assert_eq!(escape_csv("a\nb"), "\"a\nb\"");
+    }
+
+    /// Text between the first `start` and the next `end` after it
+    fn between<'a>(text: &'a str, start: &str, end: &str) -> &'a str {
+        let text = &text[text.find(start).unwrap() + start.len()..];
+        &text[..text.find(end).unwrap()]
+    }
+
+    #[test]
+    fn property_graph_formats_agree_on_node_and_edge_ids() {
+        let graph = graph(&[("a.js", "function f() {}\nf();\n")]);
+
+        let cypher = to_cypher(&graph).unwrap();
+        let cypher_nodes: Vec<(&str, &str)> = cypher.lines()
+            .filter(|line| line.starts_with("MERGE "))
+            .map(|line| (between(line, "{id: \"", "\""), between(line, "(n:", " ")))
+            .collect();
+        let cypher_edges: Vec<(&str, &str, &str)> = cypher.lines()
+            .filter(|line| line.starts_with("MATCH "))
+            .map(|line| (
+                between(line, "(a:", ")").split('"').nth(1).unwrap(),
+                between(line, "(b:", ")").split('"').nth(1).unwrap(),
+                between(line, "[:", "]").split(' ').next().unwrap(),
+            ))
+            .collect();
+
+        let graphml = to_graphml(&graph).unwrap();
+        let graphml_nodes: Vec<(&str, &str)> = graphml.lines()
+            .filter(|line| line.trim_start().starts_with("<node "))
+            .map(|line| (between(line, "id=\"", "\""), between(line, "labels=\":", "\"")))
+            .collect();
+        let graphml_edges: Vec<(&str, &str, &str)> = graphml.lines()
+            .filter(|line| line.trim_start().starts_with("<edge "))
+            .map(|line| (between(line, "source=\"", "\""), between(line, "target=\"", "\""), between(line, "label=\"", "\"")))
+            .collect();
+
+        let (nodes, relationships) = to_neo4j_csv(&graph).unwrap();
+        let csv_nodes: Vec<(&str, &str)> = nodes.lines().skip(1)
+            .map(|line| {
+                let mut fields = line.splitn(3, ',');
+                (fields.next().unwrap(), fields.next().unwrap())
+            })
+            .collect();
+        let csv_edges: Vec<(&str, &str, &str)> = relationships.lines().skip(1)
+            .map(|line| {
+                let mut fields = line.splitn(4, ',');
+                (fields.next().unwrap(), fields.next().unwrap(), fields.next().unwrap())
+            })
+            .collect();
+
+        assert_eq!(cypher_nodes, graphml_nodes);
+        assert_eq!(cypher_nodes, csv_nodes);
+        assert_eq!(cypher_edges, graphml_edges);
+        assert_eq!(cypher_edges, csv_edges);
+
+        // Every edge joins nodes that were written, and the reference resolves
+        let ids: HashSet<&str> = cypher_nodes.iter().map(|(id, _)| *id).collect();
+        assert!(cypher_edges.iter().all(|(source, target, _)| ids.contains(source) && ids.contains(target)));
+        let file = cypher_nodes.iter().find(|(_, label)| *label == "File").unwrap().0;
+        assert!(cypher_edges.iter().any(|&(source, _, label)| source == file && label == "CONTAINS"));
+        assert!(cypher_edges.iter().any(|&(_, _, label)| label == "RESOLVES_TO"));
+    }
+
+    /// Lines of NDJSON written by `write` into a stream, sorted and without debug info,
+    /// whose order differs from one build of a graph to the next, or empty source info,
+    /// which a graph reports for nodes that have none once a later node has some
+    fn ndjson_lines(write: impl FnOnce(NdjsonStream<Vec<u8>>) -> Result<Vec<u8>>) -> Vec<String> {
+        let empty = serde_json::to_value(stack_graphs::serde::SourceInfo {
+            span: lsp_positions::Span::default(),
+            syntax_type: None,
+        }).unwrap();
+        let output = String::from_utf8(write(NdjsonStream::new(Vec::new())).unwrap()).unwrap();
+        let mut lines: Vec<String> = output.lines()
+            .map(|line| {
+                let mut record: Value = serde_json::from_str(line).unwrap();
+                let record_fields = record.as_object_mut().unwrap();
+                record_fields.remove("debug_info");
+                if record_fields.get("source_info") == Some(&empty) {
+                    record_fields.remove("source_info");
+                }
+                record.to_string()
+            })
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn ndjson_streamed_from_stored_files_matches_whole_graph() {
+        let files = [
+            ("a.js", "function f() {}\n"),
+            ("b.js", "function g() {}\ng();\n"),
+        ];
+        let project = project(&files);
+        let graph = graph(&files);
+        let whole = ndjson_lines(|stream| stream.finish(&graph));
+
+        // Each file is written from its own graph as it is stored, and the rest, along
+        // with the paths, from the database at the end
+        let mut database = crate::storage::IndexDatabase::open(project.path().join("index.db")).unwrap();
+        let discovery = crate::discovery::FileDiscovery::new(&[], &[]).unwrap();
+        let mut streamed_files = Vec::new();
+        let streamed = ndjson_lines(|mut stream| {
+            database.update_with(project.path(), project.path(), &discovery, true, 1, |stack_graph, file| {
+                assert_eq!(stack_graph.iter_files().count(), 1);
+                streamed_files.push(stack_graph[file].name().to_string());
+                stream.write_file(stack_graph, file)
+            })?;
+            stream.finish_with(&mut QueryEngine::from_database(&database)?)
+        });
+
+        assert_eq!(streamed_files, ["a.js", "b.js"]);
+        assert_eq!(streamed, whole);
+        assert!(whole.iter().any(|line| line.contains(r#""record":"path""#)));
+        assert!(whole.iter().any(|line| line.contains(r#""record":"summary""#)));
+    }
+}
@@ -0,0 +1,549 @@
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use serde::Serialize;
+use stack_graphs::arena::Handle;
+use stack_graphs::graph::{Node, StackGraph};
+use stack_graphs::partial::{PartialPath, PartialPaths};
+use stack_graphs::stitching::{Database, DatabaseCandidates, ForwardPartialPathStitcher, StitcherConfig};
+use stack_graphs::storage::{FileStatus, SQLiteReader};
+use stack_graphs::NoCancellation;
+
+use crate::indexer;
+use crate::storage::{self, IndexDatabase};
+use crate::symbols::{self, OutlineItem, Symbol};
+
+/// A zero-based source range; columns are UTF-8 byte offsets within the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// Convert a stack graph source span, returning `None` for the all-zero "no span" value
+    pub fn from_lsp(span: &lsp_positions::Span) -> Option<Self> {
+        if *span == lsp_positions::Span::default() {
+            return None;
+        }
+
+        Some(Self {
+            start_line: span.start.line,
+            start_column: span.start.column.utf8_offset,
+            end_line: span.end.line,
+            end_column: span.end.column.utf8_offset,
+        })
+    }
+
+    /// Whether the position falls inside this span, treating the end as inclusive so
+    /// that a cursor placed just after an identifier still hits it
+    pub fn contains(&self, line: usize, column: usize) -> bool {
+        (line, column) >= (self.start_line, self.start_column)
+            && (line, column) <= (self.end_line, self.end_column)
+    }
+
+    /// Start line and column, for ordering spans by position
+    pub fn start(&self) -> (usize, usize) {
+        (self.start_line, self.start_column)
+    }
+
+    /// End line and column
+    pub fn end(&self) -> (usize, usize) {
+        (self.end_line, self.end_column)
+    }
+
+    /// Whether another span lies entirely within this one
+    pub fn encloses(&self, other: &Span) -> bool {
+        self.start() <= other.start() && other.end() <= self.end()
+    }
+}
+
+/// A definition or reference location in the indexed graph
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Location {
+    /// Project-relative path of the file the node belongs to
+    pub file: String,
+    /// Source range of the node
+    pub span: Span,
+    /// Syntax type recorded for the node (function, class, ...), if any
+    pub syntax_type: Option<String>,
+}
+
+impl Location {
+    /// Build a location for a node, if it belongs to a file and has a source span
+    pub fn from_node(graph: &StackGraph, node: Handle<Node>) -> Option<Self> {
+        let file = graph[node].file()?;
+        let source_info = graph.source_info(node)?;
+        let span = Span::from_lsp(&source_info.span)?;
+
+        Some(Self {
+            file: graph[file].name().to_string(),
+            span,
+            syntax_type: source_info.syntax_type.into_option().map(|s| graph[s].to_string()),
+        })
+    }
+}
+
+/// A reference to a definition, with the source line it appears on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Reference {
+    /// Where the reference appears
+    pub location: Location,
+    /// The trimmed source line containing the reference, if it was recorded
+    pub snippet: Option<String>,
+}
+
+/// All references found in a single file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileReferences {
+    /// Project-relative path of the file the references belong to
+    pub file: String,
+    /// References in source order
+    pub references: Vec<Reference>,
+}
+
+/// The graph of an in-memory query engine, either borrowed or shared with its owner
+enum MemoryGraph<'a> {
+    Borrowed(&'a StackGraph),
+    Shared(Arc<StackGraph>),
+}
+
+impl Deref for MemoryGraph<'_> {
+    type Target = StackGraph;
+
+    fn deref(&self) -> &StackGraph {
+        match self {
+            Self::Borrowed(graph) => graph,
+            Self::Shared(graph) => graph,
+        }
+    }
+}
+
+/// Where a query engine gets its graph and partial paths from
+enum Backend<'a> {
+    /// An in-memory graph whose partial paths were computed up front
+    Memory {
+        graph: MemoryGraph<'a>,
+        partials: PartialPaths,
+        database: Box<Database>,
+    },
+    /// An index database that graphs and partial paths are loaded from on demand
+    Storage(Box<SQLiteReader>),
+}
+
+/// Resolves queries against a stack graph using a database of partial paths
+pub struct QueryEngine<'a> {
+    backend: Backend<'a>,
+    /// Result of [`QueryEngine::resolve_all`], which does not change for the engine's lifetime
+    resolved: Option<Vec<(Handle<Node>, Handle<Node>)>>,
+}
+
+impl<'a> QueryEngine<'a> {
+    /// Create a query engine, computing the minimal partial path set of every file in the graph
+    pub fn new(graph: &'a StackGraph) -> Result<Self> {
+        Self::in_memory(MemoryGraph::Borrowed(graph))
+    }
+
+    /// [`QueryEngine::new`] for a graph shared with the engine, so that the engine can be
+    /// kept alongside the graph's owner
+    pub fn shared(graph: Arc<StackGraph>) -> Result<QueryEngine<'static>> {
+        QueryEngine::in_memory(MemoryGraph::Shared(graph))
+    }
+
+    fn in_memory(graph: MemoryGraph<'a>) -> Result<Self> {
+        let mut partials = PartialPaths::new();
+        let mut database = Database::new();
+
+        for file in graph.iter_files() {
+            ForwardPartialPathStitcher::find_minimal_partial_path_set_in_file(
+                &graph,
+                &mut partials,
+                file,
+                StitcherConfig::default(),
+                &NoCancellation,
+                |graph, partials, path| {
+                    database.add_partial_path(graph, partials, path.clone());
+                },
+            ).map_err(|e| anyhow!("Failed to compute partial paths for {}: {:?}", graph[file], e))?;
+        }
+
+        Ok(Self {
+            backend: Backend::Memory {
+                graph,
+                partials,
+                database: Box::new(database),
+            },
+            resolved: None,
+        })
+    }
+
+    /// Create a query engine over an index database, loading file graphs and partial
+    /// paths lazily as queries need them
+    pub fn from_database(database: &IndexDatabase) -> Result<QueryEngine<'static>> {
+        Ok(QueryEngine {
+            backend: Backend::Storage(Box::new(database.reader()?)),
+            resolved: None,
+        })
+    }
+
+    /// The stack graph this engine answers queries for, as far as it has been loaded
+    pub fn graph(&mut self) -> &StackGraph {
+        match &mut self.backend {
+            Backend::Memory { graph, .. } => graph,
+            Backend::Storage(reader) => reader.get().0,
+        }
+    }
+
+    /// Find the definitions of the reference at a zero-based line and column in a file
+    ///
+    /// If the position is on a definition rather than a reference, that definition is
+    /// returned as-is.
+    pub fn find_definitions(&mut self, file_name: &str, line: usize, column: usize) -> Result<Vec<Location>> {
+        let nodes = self.nodes_at(file_name, line, column, |graph, node| {
+            graph[node].is_reference() || graph[node].is_definition()
+        })?;
+
+        let graph = self.graph();
+        let (definitions, references): (Vec<_>, Vec<_>) = nodes.into_iter()
+            .partition(|node| graph[*node].is_definition());
+        if references.is_empty() {
+            return Ok(definitions.into_iter()
+                .filter_map(|node| Location::from_node(graph, node))
+                .collect());
+        }
+
+        let resolved = self.resolve(references)?;
+        let graph = self.graph();
+        let mut definitions: Vec<Location> = Vec::new();
+        for (_, definition) in resolved {
+            if let Some(location) = Location::from_node(graph, definition)
+                && !definitions.contains(&location) {
+                definitions.push(location);
+            }
+        }
+
+        debug!("Found {} definition(s) for {}:{}:{}", definitions.len(), file_name, line, column);
+        Ok(definitions)
+    }
+
+    /// Find every reference that resolves to the definition at a zero-based line and column
+    ///
+    /// If the position is on a reference, it is first resolved to its definitions. Results
+    /// are grouped by file, with files and references in order.
+    pub fn find_references(&mut self, file_name: &str, line: usize, column: usize) -> Result<Vec<FileReferences>> {
+        let nodes = self.nodes_at(file_name, line, column, |graph, node| {
+            graph[node].is_reference() || graph[node].is_definition()
+        })?;
+
+        let graph = self.graph();
+        let (mut definitions, references): (Vec<_>, Vec<_>) = nodes.into_iter()
+            .partition(|node| graph[*node].is_definition());
+        if !references.is_empty() {
+            definitions.extend(self.resolve(references)?.into_iter().map(|(_, definition)| definition));
+        }
+        if definitions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let resolved = self.resolve_all()?;
+        let graph = self.graph();
+        let mut found: Vec<Reference> = Vec::new();
+        for (reference, definition) in resolved {
+            if !definitions.contains(&definition) {
+                continue;
+            }
+
+            if let Some(location) = Location::from_node(graph, reference)
+                && !found.iter().any(|r| r.location == location) {
+                let snippet = graph.source_info(reference)
+                    .and_then(|info| info.containing_line.into_option())
+                    .map(|line| graph[line].trim().to_string());
+                found.push(Reference { location, snippet });
+            }
+        }
+
+        found.sort_by(|a, b| {
+            (&a.location.file, a.location.span.start_line, a.location.span.start_column)
+                .cmp(&(&b.location.file, b.location.span.start_line, b.location.span.start_column))
+        });
+
+        let mut grouped: Vec<FileReferences> = Vec::new();
+        for reference in found {
+            match grouped.last_mut() {
+                Some(group) if group.file == reference.location.file => group.references.push(reference),
+                _ => grouped.push(FileReferences {
+                    file: reference.location.file.clone(),
+                    references: vec![reference],
+                }),
+            }
+        }
+
+        debug!("Found references in {} file(s) for {}:{}:{}", grouped.len(), file_name, line, column);
+        Ok(grouped)
+    }
+
+    /// Every reference in the indexed files paired with each definition it resolves to
+    ///
+    /// The pairs are computed once per engine; create a new engine once the indexed
+    /// files change.
+    pub fn resolve_all(&mut self) -> Result<Vec<(Handle<Node>, Handle<Node>)>> {
+        if let Some(resolved) = &self.resolved {
+            return Ok(resolved.clone());
+        }
+
+        // Any file may hold a reference, so every graph has to be available
+        self.load_all_files()?;
+        let graph = self.graph();
+        let references = graph.iter_nodes()
+            .filter(|node| graph[*node].is_reference())
+            .collect::<Vec<_>>();
+
+        let resolved = self.resolve(references)?;
+        self.resolved = Some(resolved.clone());
+        Ok(resolved)
+    }
+
+    /// Every definition in the indexed source files, in file and source order
+    pub fn symbols(&mut self) -> Result<Vec<Symbol>> {
+        self.load_all_files()?;
+        Ok(symbols::symbols(self.graph()))
+    }
+
+    /// The definitions of a file as a tree of nested definitions, in source order
+    pub fn outline(&mut self, file_name: &str) -> Result<Vec<OutlineItem>> {
+        self.load_file(file_name)?;
+        let graph = self.graph();
+        let file = graph.get_file(file_name)
+            .ok_or_else(|| anyhow!("File is not indexed: {}", file_name))?;
+        Ok(symbols::outline(graph, file))
+    }
+
+    /// Find the nodes at a position in a file that match the filter, keeping only those
+    /// with the narrowest span since TSG rules often emit several nodes per identifier
+    fn nodes_at<F>(&mut self, file_name: &str, line: usize, column: usize, filter: F) -> Result<Vec<Handle<Node>>>
+    where
+        F: Fn(&StackGraph, Handle<Node>) -> bool,
+    {
+        self.load_file(file_name)?;
+        let graph = self.graph();
+        let file = graph.get_file(file_name)
+            .ok_or_else(|| anyhow!("File is not indexed: {}", file_name))?;
+
+        let candidates = graph.nodes_for_file(file)
+            .filter(|node| filter(graph, *node))
+            .filter_map(|node| {
+                let span = Span::from_lsp(&graph.source_info(node)?.span)?;
+                span.contains(line, column).then_some((node, span))
+            })
+            .collect::<Vec<_>>();
+
+        let width = |span: &Span| (span.end_line - span.start_line, span.end_column.abs_diff(span.start_column));
+        let narrowest = match candidates.iter().map(|(_, span)| width(span)).min() {
+            Some(narrowest) => narrowest,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(candidates.into_iter()
+            .filter(|(_, span)| width(span) == narrowest)
+            .map(|(node, _)| node)
+            .collect())
+    }
+
+    /// Make sure a file's graph is available, loading it from the index database if needed
+    fn load_file(&mut self, file_name: &str) -> Result<()> {
+        if let Backend::Storage(reader) = &mut self.backend {
+            let mut entries = reader.list_file_or_directory(Path::new(file_name))?;
+            let indexed = entries.try_iter()?
+                .filter_map(Result::ok)
+                .any(|entry| entry.path == Path::new(file_name) && matches!(entry.status, FileStatus::Indexed));
+            drop(entries);
+
+            // Files that failed to index are stored without a graph
+            if indexed {
+                reader.load_graph_for_file(file_name)
+                    .with_context(|| format!("Failed to load {} from index database", file_name))?;
+                storage::restore_source_info(reader.get().0, file_name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the graphs of all indexed files are available
+    fn load_all_files(&mut self) -> Result<()> {
+        if let Backend::Storage(reader) = &mut self.backend {
+            let mut files = reader.list_all()?;
+            let indexed = files.try_iter()?
+                .filter_map(|entry| match entry {
+                    Ok(entry) if matches!(entry.status, FileStatus::Indexed) => {
+                        Some(Ok(entry.path.to_string_lossy().to_string()))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            drop(files);
+
+            for file in indexed {
+                reader.load_graph_for_file(&file)
+                    .with_context(|| format!("Failed to load {} from index database", file))?;
+                storage::restore_source_info(reader.get().0, &file);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolve references to definitions by stitching partial paths from the database
+    fn resolve(&mut self, references: Vec<Handle<Node>>) -> Result<Vec<(Handle<Node>, Handle<Node>)>> {
+        let mut resolved = Vec::new();
+        let mut visit = |_: &StackGraph, _: &mut PartialPaths, path: &PartialPath| {
+            resolved.push((path.start_node, path.end_node));
+        };
+
+        match &mut self.backend {
+            Backend::Memory { graph, partials, database } => {
+                ForwardPartialPathStitcher::find_all_complete_partial_paths(
+                    &mut DatabaseCandidates::new(graph, partials, database),
+                    references,
+                    StitcherConfig::default(),
+                    &NoCancellation,
+                    &mut visit,
+                ).map_err(|e| anyhow!("Path stitching failed: {:?}", e))?;
+            }
+            Backend::Storage(reader) => {
+                ForwardPartialPathStitcher::find_all_complete_partial_paths(
+                    reader.as_mut(),
+                    references,
+                    StitcherConfig::default(),
+                    &NoCancellation,
+                    &mut visit,
+                ).map_err(|e| anyhow!("Path stitching failed: {}", e))?;
+            }
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Find the definitions of the reference at a zero-based line and column in an indexed file
+pub fn find_definitions(stack_graph: &StackGraph, root: &Path, path: &Path, line: usize, column: usize) -> Result<Vec<Location>> {
+    QueryEngine::new(stack_graph)?.find_definitions(&indexer::file_name_for(root, path), line, column)
+}
+
+/// Find every reference to the definition at a zero-based line and column in an indexed file
+pub fn find_references(stack_graph: &StackGraph, root: &Path, path: &Path, line: usize, column: usize) -> Result<Vec<FileReferences>> {
+    QueryEngine::new(stack_graph)?.find_references(&indexer::file_name_for(root, path), line, column)
+}
+
+/// Outline of an indexed file: its definitions as a tree of nested definitions
+pub fn outline(stack_graph: &StackGraph, root: &Path, path: &Path) -> Result<Vec<OutlineItem>> {
+    let file_name = indexer::file_name_for(root, path);
+    let file = stack_graph.get_file(&file_name)
+        .ok_or_else(|| anyhow!("File is not indexed: {}", file_name))?;
+    Ok(symbols::outline(stack_graph, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::graph;
+
+    fn start(location: &Location) -> (usize, usize) {
+        (location.span.start_line, location.span.start_column)
+    }
+
+    #[test]
+    fn finds_definition_in_same_file() {
+        let graph = graph(&[("app.js", "function helper() {}\nhelper();\n")]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        let definitions = engine.find_definitions("app.js", 1, 0).unwrap();
+        assert_eq!(definitions.len(), 1);
+        assert_eq!(definitions[0].file, "app.js");
+        assert_eq!(start(&definitions[0]), (0, 9));
+    }
+
+    #[test]
+    fn finds_definition_in_other_file() {
+        let graph = graph(&[
+            ("shapes.py", "class Circle:\n    pass\n"),
+            ("main.py", "from shapes import Circle\n\nCircle()\n"),
+        ]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        let definitions = engine.find_definitions("main.py", 2, 0).unwrap();
+        assert!(definitions.iter().any(|d| d.file == "shapes.py" && start(d) == (0, 6)), "{:?}", definitions);
+    }
+
+    #[test]
+    fn definition_position_returns_itself() {
+        let graph = graph(&[("app.js", "function helper() {}\n")]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        let definitions = engine.find_definitions("app.js", 0, 10).unwrap();
+        assert_eq!(definitions.len(), 1);
+        assert_eq!(start(&definitions[0]), (0, 9));
+    }
+
+    #[test]
+    fn position_without_symbol_finds_nothing() {
+        let graph = graph(&[("app.js", "function helper() {}\n\nhelper();\n")]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        assert!(engine.find_definitions("app.js", 1, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_references_grouped_by_file_with_snippets() {
+        let graph = graph(&[
+            ("shapes.py", "class Circle:\n    pass\n\nunit = Circle()\n"),
+            ("main.py", "from shapes import Circle\n\nsmall = Circle()\n"),
+        ]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        let files = engine.find_references("shapes.py", 0, 6).unwrap();
+        let found: Vec<(&str, usize, Option<&str>)> = files.iter()
+            .flat_map(|file| file.references.iter().map(move |r| {
+                (file.file.as_str(), r.location.span.start_line, r.snippet.as_deref())
+            }))
+            .collect();
+
+        assert_eq!(files.iter().map(|file| file.file.as_str()).collect::<Vec<_>>(), ["main.py", "shapes.py"]);
+        assert!(found.contains(&("main.py", 2, Some("small = Circle()"))), "{:?}", found);
+        assert!(found.contains(&("shapes.py", 3, Some("unit = Circle()"))), "{:?}", found);
+    }
+
+    #[test]
+    fn finds_references_from_a_reference() {
+        let graph = graph(&[("app.js", "function helper() {}\nhelper();\n  helper();\n")]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        let files = engine.find_references("app.js", 2, 3).unwrap();
+        assert_eq!(files.len(), 1);
+        let lines: Vec<_> = files[0].references.iter()
+            .map(|r| (r.location.span.start_line, r.snippet.clone()))
+            .collect();
+        assert_eq!(lines, [(1, Some("helper();".to_string())), (2, Some("helper();".to_string()))]);
+    }
+
+    #[test]
+    fn unreferenced_definition_has_no_references() {
+        let graph = graph(&[("app.js", "function helper() {}\n")]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        assert!(engine.find_references("app.js", 0, 9).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unknown_file_is_an_error() {
+        let graph = graph(&[("app.js", "function helper() {}\n")]);
+        let mut engine = QueryEngine::new(&graph).unwrap();
+
+        assert!(engine.find_definitions("missing.js", 0, 0).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+
+/// What happened when a single file was handed to the indexer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// The file was parsed and added to the graph; `parse_errors` counts the error and
+    /// missing nodes tree-sitter recovered from
+    Indexed { parse_errors: usize },
+    /// The file's stored content hash matched, so it was not parsed again
+    Unchanged,
+    /// The file was not indexed, e.g. because its language is unsupported
+    Skipped(String),
+}
+
+/// A file paired with the reason it was skipped or failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileIssue {
+    pub file: String,
+    pub reason: String,
+}
+
+/// A file that was indexed despite containing syntax errors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParseErrors {
+    pub file: String,
+    /// Number of error and missing nodes in the file's syntax tree
+    pub count: usize,
+}
+
+/// Per-file summary of an indexing run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IndexReport {
+    /// Files that were parsed and added to the graph
+    pub indexed: Vec<String>,
+    /// Files left as they were because their content did not change
+    pub unchanged: Vec<String>,
+    /// Files that were not indexed, with the reason
+    pub skipped: Vec<FileIssue>,
+    /// Files whose indexing failed, with the error
+    pub failed: Vec<FileIssue>,
+    /// Indexed files whose syntax trees contain errors
+    pub parse_errors: Vec<ParseErrors>,
+    /// Files removed from the index database because they no longer exist
+    pub removed: Vec<String>,
+}
+
+impl IndexReport {
+    /// Record the result of indexing a file
+    pub fn record(&mut self, file: String, result: Result<FileOutcome>) {
+        match result {
+            Ok(FileOutcome::Indexed { parse_errors }) => {
+                if parse_errors > 0 {
+                    self.parse_errors.push(ParseErrors { file: file.clone(), count: parse_errors });
+                }
+                self.indexed.push(file);
+            }
+            Ok(FileOutcome::Unchanged) => self.unchanged.push(file),
+            Ok(FileOutcome::Skipped(reason)) => self.skipped.push(FileIssue { file, reason }),
+            Err(e) => {
+                warn!("Failed to index {}: {:#}", file, e);
+                self.failed.push(FileIssue { file, reason: format!("{:#}", e) });
+            }
+        }
+    }
+
+    /// Fold another report into this one
+    pub fn merge(&mut self, other: IndexReport) {
+        self.indexed.extend(other.indexed);
+        self.unchanged.extend(other.unchanged);
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+        self.parse_errors.extend(other.parse_errors);
+        self.removed.extend(other.removed);
+    }
+
+    /// Whether any file failed to index
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+    }
+
+    /// One-line summary of the counts in this report
+    pub fn summary(&self) -> String {
+        format!("{} indexed, {} unchanged, {} skipped, {} failed, {} with parse errors, {} removed",
+            self.indexed.len(), self.unchanged.len(), self.skipped.len(),
+            self.failed.len(), self.parse_errors.len(), self.removed.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn record_sorts_outcomes() {
+        let mut report = IndexReport::default();
+        report.record("a.py".to_string(), Ok(FileOutcome::Indexed { parse_errors: 0 }));
+        report.record("b.py".to_string(), Ok(FileOutcome::Indexed { parse_errors: 2 }));
+        report.record("c.py".to_string(), Ok(FileOutcome::Unchanged));
+        report.record("d.txt".to_string(), Ok(FileOutcome::Skipped("unsupported language".to_string())));
+        report.record("e.py".to_string(), Err(anyhow!("read failed").context("Failed to index")));
+
+        assert_eq!(report.indexed, ["a.py", "b.py"]);
+        assert_eq!(report.parse_errors, [ParseErrors { file: "b.py".to_string(), count: 2 }]);
+        assert_eq!(report.unchanged, ["c.py"]);
+        assert_eq!(report.skipped, [FileIssue { file: "d.txt".to_string(), reason: "unsupported language".to_string() }]);
+        // The whole error chain is kept as the reason
+        assert_eq!(report.failed, [FileIssue { file: "e.py".to_string(), reason: "Failed to index: read failed".to_string() }]);
+    }
+
+    #[test]
+    fn merge_appends_every_list() {
+        let mut report = IndexReport::default();
+        report.record("a.py".to_string(), Ok(FileOutcome::Indexed { parse_errors: 0 }));
+
+        let mut other = IndexReport::default();
+        other.record("b.py".to_string(), Ok(FileOutcome::Indexed { parse_errors: 1 }));
+        other.record("c.py".to_string(), Err(anyhow!("boom")));
+        other.removed.push("old.py".to_string());
+        report.merge(other);
+
+        assert_eq!(report.indexed, ["a.py", "b.py"]);
+        assert_eq!(report.parse_errors.len(), 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.removed, ["old.py"]);
+        assert_eq!(report.summary(), "2 indexed, 0 unchanged, 0 skipped, 1 failed, 1 with parse errors, 1 removed");
+    }
+
+    #[test]
+    fn only_failures_count_as_failures() {
+        let mut report = IndexReport::default();
+        assert!(!report.has_failures());
+
+        report.record("a.py".to_string(), Ok(FileOutcome::Indexed { parse_errors: 3 }));
+        report.record("b.txt".to_string(), Ok(FileOutcome::Skipped("unsupported language".to_string())));
+        assert!(!report.has_failures());
+
+        report.record("c.py".to_string(), Err(anyhow!("boom")));
+        assert!(report.has_failures());
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use stack_graphs::graph::{Node, StackGraph};
+
+use crate::tsg_loader::is_builtins_file;
+
+/// Counts describing the contents of a stack graph
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GraphStats {
+    /// Indexed source files, not counting language builtins
+    pub files: usize,
+    pub nodes: usize,
+    pub edges: usize,
+    /// Nodes that define a symbol
+    pub definitions: usize,
+    /// Nodes that reference a symbol
+    pub references: usize,
+    /// Number of nodes of each kind, e.g. `push_symbol` or `scope`
+    pub nodes_by_kind: BTreeMap<String, usize>,
+}
+
+impl GraphStats {
+    /// Count the files, nodes and edges of a stack graph
+    pub fn from_graph(stack_graph: &StackGraph) -> Self {
+        let mut stats = Self {
+            files: stack_graph.iter_files()
+                .filter(|&file| !is_builtins_file(stack_graph[file].name()))
+                .count(),
+            ..Self::default()
+        };
+
+        for node in stack_graph.iter_nodes() {
+            let value = &stack_graph[node];
+            stats.nodes += 1;
+            stats.edges += stack_graph.outgoing_edges(node).count();
+            if value.is_definition() {
+                stats.definitions += 1;
+            }
+            if value.is_reference() {
+                stats.references += 1;
+            }
+            *stats.nodes_by_kind.entry(node_kind(value).to_string()).or_default() += 1;
+        }
+
+        stats
+    }
+}
+
+/// Snake-case name of a node's kind, as used in the JSON output
+pub fn node_kind(node: &Node) -> &'static str {
+    match node {
+        Node::DropScopes(_) => "drop_scopes",
+        Node::JumpTo(_) => "jump_to_scope",
+        Node::PopScopedSymbol(_) => "pop_scoped_symbol",
+        Node::PopSymbol(_) => "pop_symbol",
+        Node::PushScopedSymbol(_) => "push_scoped_symbol",
+        Node::PushSymbol(_) => "push_symbol",
+        Node::Root(_) => "root",
+        Node::Scope(_) => "scope",
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info};
+use sha1::{Digest, Sha1};
+use stack_graphs::arena::Handle;
+use stack_graphs::graph::{File, Node, StackGraph};
+use stack_graphs::partial::{PartialPath, PartialPaths};
+use stack_graphs::stitching::{ForwardPartialPathStitcher, StitcherConfig};
+use stack_graphs::storage::{FileStatus, SQLiteReader, SQLiteWriter};
+use stack_graphs::NoCancellation;
+
+use crate::discovery::FileDiscovery;
+use crate::indexer::{self, IsolatedFile};
+use crate::languages::Language;
+use crate::query::Span;
+use crate::report::{FileOutcome, IndexReport};
+use crate::tsg_loader::{self, is_builtins_file};
+
+/// Persistent index of per-file stack graphs and partial paths, stored in SQLite
+///
+/// Files are keyed by their stack graph file name and tagged with a hash of their
+/// content, so an update only re-parses files that changed since the last run.
+pub struct IndexDatabase {
+    path: PathBuf,
+    writer: SQLiteWriter,
+}
+
+impl IndexDatabase {
+    /// Open a database, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let writer = SQLiteWriter::open(&path)
+            .with_context(|| format!("Failed to open index database: {}", path.display()))?;
+
+        Ok(Self { path, writer })
+    }
+
+    /// Path of the database file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bring the database in line with a file or directory on disk
+    ///
+    /// New and changed files are re-indexed, and files that are stored but no longer
+    /// found under `path` are removed, including files that are now ignored or excluded
+    /// by `discovery`. File names are relative to `root`. Failures are recorded in the
+    /// report unless `fail_fast` is set. Files are built on `threads` worker threads
+    /// (0 for one per CPU) and written to the database in path order as they are built.
+    pub fn update(
+        &mut self,
+        root: &Path,
+        path: &Path,
+        discovery: &FileDiscovery,
+        fail_fast: bool,
+        threads: usize,
+    ) -> Result<IndexReport> {
+        self.update_with(root, path, discovery, fail_fast, threads, |_, _| Ok(()))
+    }
+
+    /// [`IndexDatabase::update`], calling `on_file` with each re-indexed file's own graph
+    /// as soon as it has been stored
+    ///
+    /// Unchanged files are not built, so `on_file` is not called for them. An error from
+    /// `on_file` aborts the update.
+    pub fn update_with<F>(
+        &mut self,
+        root: &Path,
+        path: &Path,
+        discovery: &FileDiscovery,
+        fail_fast: bool,
+        threads: usize,
+        mut on_file: F,
+    ) -> Result<IndexReport>
+    where
+        F: FnMut(&StackGraph, Handle<File>) -> Result<()>,
+    {
+        let files = if path.is_file() {
+            vec![path.to_path_buf()]
+        } else {
+            discovery.find_source_files(path)?
+        };
+
+        let mut report = IndexReport::default();
+        let mut seen = HashSet::new();
+
+        // Hash every file and keep the ones whose stored tag no longer matches
+        let mut stale = Vec::new();
+        for file in &files {
+            let file_name = indexer::file_name_for(root, file);
+            seen.insert(file_name.clone());
+
+            match self.stale_tag(&file_name, file) {
+                Ok(Some(tag)) => stale.push((file, file_name, tag)),
+                Ok(None) => report.record(file_name, Ok(FileOutcome::Unchanged)),
+                Err(e) if fail_fast => return Err(e),
+                Err(e) => report.record(file_name, Err(e)),
+            }
+        }
+
+        // Build graphs and partial paths on the workers; SQLite writes stay on this thread
+        // and happen as each file is built
+        let pool = indexer::thread_pool(threads)?;
+        indexer::build_in_order(&pool, stale.len(), |index| BuiltFile::build(root, stale[index].0), |index, result| {
+            let (file, file_name, tag) = &stale[index];
+            let result = match result {
+                Ok(mut built) => {
+                    let result = self.store(&mut built, tag);
+                    if result.is_ok() && let Some(file) = built.file {
+                        on_file(&built.stack_graph, file)?;
+                    }
+                    result
+                }
+                Err(e) => self.store_error(file_name, tag, e),
+            };
+
+            if fail_fast {
+                let outcome = result.with_context(|| format!("Failed to index file: {}", file.display()))?;
+                report.record(file_name.clone(), Ok(outcome));
+            } else {
+                report.record(file_name.clone(), result);
+            }
+            Ok(())
+        })?;
+
+        if path.is_dir() {
+            // Only files under the updated directory can have disappeared from it
+            let prefix = match indexer::file_name_for(root, path) {
+                dir if dir.is_empty() || path == root => String::new(),
+                dir => format!("{}/", dir),
+            };
+
+            for stored in self.stored_files()? {
+                if stored.starts_with(&prefix) && !seen.contains(&stored) && !is_builtins_file(&stored) {
+                    self.remove_file(&stored)?;
+                    report.removed.push(stored);
+                }
+            }
+        }
+
+        info!("Index database updated: {}", report.summary());
+        Ok(report)
+    }
+
+    /// Index a single file into the database unless its stored content hash still matches
+    pub fn index_file(&mut self, root: &Path, path: &Path) -> Result<FileOutcome> {
+        let file_name = indexer::file_name_for(root, path);
+        let Some(tag) = self.stale_tag(&file_name, path)? else {
+            return Ok(FileOutcome::Unchanged);
+        };
+
+        match BuiltFile::build(root, path) {
+            Ok(mut built) => self.store(&mut built, &tag),
+            Err(e) => self.store_error(&file_name, &tag, e),
+        }
+    }
+
+    /// Index `content` as the content of a file, e.g. an editor buffer with unsaved
+    /// changes, unless the stored content hash already matches it
+    pub fn index_source(&mut self, root: &Path, path: &Path, content: &str) -> Result<FileOutcome> {
+        let file_name = indexer::file_name_for(root, path);
+        let tag = content_tag(content.as_bytes());
+        if !self.is_stale(&file_name, &tag)? {
+            return Ok(FileOutcome::Unchanged);
+        }
+
+        match BuiltFile::build_source(root, path, content) {
+            Ok(mut built) => self.store(&mut built, &tag),
+            Err(e) => self.store_error(&file_name, &tag, e),
+        }
+    }
+
+    /// Tag for a file's current content, or `None` if the stored copy is up to date
+    fn stale_tag(&mut self, file_name: &str, path: &Path) -> Result<Option<String>> {
+        let content = fs::read(path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        let tag = content_tag(&content);
+
+        Ok(self.is_stale(file_name, &tag)?.then_some(tag))
+    }
+
+    /// Whether the stored copy of a file is missing or has a different tag
+    ///
+    /// A file that failed to index and still has the same tag is not parsed again; its
+    /// stored error is returned instead.
+    fn is_stale(&mut self, file_name: &str, tag: &str) -> Result<bool> {
+        match self.writer.status_for_file(file_name, Some(tag))? {
+            FileStatus::Indexed => {
+                debug!("Unchanged, skipping: {}", file_name);
+                Ok(false)
+            }
+            FileStatus::Error(error) => {
+                debug!("Unchanged since it failed to index, skipping: {}", file_name);
+                Err(anyhow!(error))
+            }
+            FileStatus::Missing => Ok(true),
+        }
+    }
+
+    /// Write a built file to the database, along with its language's builtins if they
+    /// are not stored yet
+    fn store(&mut self, built: &mut BuiltFile, tag: &str) -> Result<FileOutcome> {
+        let Some(file) = built.file else {
+            return Ok(built.outcome.clone());
+        };
+
+        self.writer.store_result_for_file(&built.stack_graph, file, tag, &mut built.partials, &built.paths)
+            .with_context(|| format!("Failed to store {} in index database", built.stack_graph[file].name()))?;
+
+        // Builtins are shared by every file of a language and only stored once
+        let mut builtins = StackGraph::new();
+        if let Some(tsg_language) = tsg_loader::get_tsg_language(&built.language) {
+            tsg_language.add_builtins_to(&mut builtins);
+        }
+
+        let builtins_tag = builtins_tag();
+        for file in builtins.iter_files() {
+            let name = builtins[file].name();
+            if matches!(self.writer.status_for_file(name, Some(&builtins_tag))?, FileStatus::Indexed) {
+                continue;
+            }
+
+            let mut partials = PartialPaths::new();
+            let paths = minimal_paths(&builtins, &mut partials, file)?;
+            self.writer.store_result_for_file(&builtins, file, &builtins_tag, &mut partials, &paths)
+                .with_context(|| format!("Failed to store {} in index database", name))?;
+        }
+
+        debug!("Stored in index database: {}", built.stack_graph[file].name());
+        Ok(built.outcome.clone())
+    }
+
+    /// Record a file that failed to index, so it is retried only once its content changes
+    fn store_error(&mut self, file_name: &str, tag: &str, error: anyhow::Error) -> Result<FileOutcome> {
+        self.writer.clean_file(Path::new(file_name))?;
+        self.writer.store_error_for_file(Path::new(file_name), tag, &format!("{:#}", error))?;
+        Err(error)
+    }
+
+    /// Remove a file's graph and partial paths from the database
+    pub fn remove_file(&mut self, file_name: &str) -> Result<()> {
+        debug!("Removing from index database: {}", file_name);
+        self.writer.clean_file(Path::new(file_name))?;
+        Ok(())
+    }
+
+    /// Names of all files stored in the database
+    pub fn stored_files(&self) -> Result<Vec<String>> {
+        Ok(self.stored_entries()?.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Names of all stored files, with whether they were indexed successfully
+    fn stored_entries(&self) -> Result<Vec<(String, bool)>> {
+        let mut reader = self.reader()?;
+        let mut files = reader.list_all()?;
+        let entries = files.try_iter()?
+            .map(|entry| entry.map(|entry| {
+                let indexed = matches!(entry.status, FileStatus::Indexed);
+                (entry.path.to_string_lossy().to_string(), indexed)
+            }))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(entries)
+    }
+
+    /// Open a reader that loads graphs and partial paths lazily from this database
+    pub fn reader(&self) -> Result<SQLiteReader> {
+        SQLiteReader::open(&self.path)
+            .with_context(|| format!("Failed to open index database: {}", self.path.display()))
+    }
+
+    /// Load the graphs of all stored files into a single stack graph
+    pub fn load_stack_graph(&self) -> Result<StackGraph> {
+        let mut reader = self.reader()?;
+        for (file, indexed) in self.stored_entries()? {
+            // Files that failed to index are stored without a graph
+            if !indexed {
+                continue;
+            }
+
+            reader.load_graph_for_file(&file)
+                .with_context(|| format!("Failed to load {} from index database", file))?;
+            restore_source_info(reader.get().0, &file);
+        }
+
+        Ok(std::mem::take(reader.get().0))
+    }
+}
+
+/// A single file's stack graph and partial paths, built off the database thread
+struct BuiltFile {
+    stack_graph: StackGraph,
+    language: Language,
+    outcome: FileOutcome,
+    /// The indexed file, or `None` if it was skipped
+    file: Option<Handle<File>>,
+    partials: PartialPaths,
+    paths: Vec<PartialPath>,
+}
+
+impl BuiltFile {
+    /// Build the file on its own so that only its nodes and paths get stored
+    fn build(root: &Path, path: &Path) -> Result<Self> {
+        Self::from_isolated(root, path, indexer::index_file_isolated(root, path)?)
+    }
+
+    /// Build the file from `content` rather than from what is on disk
+    fn build_source(root: &Path, path: &Path, content: &str) -> Result<Self> {
+        Self::from_isolated(root, path, indexer::index_source_isolated(root, path, content)?)
+    }
+
+    fn from_isolated(root: &Path, path: &Path, isolated: IsolatedFile) -> Result<Self> {
+        let (mut stack_graph, language, outcome) = isolated;
+        let file = stack_graph.get_file(&indexer::file_name_for(root, path));
+        if let Some(file) = file {
+            record_source_info(&mut stack_graph, file);
+        }
+
+        let mut partials = PartialPaths::new();
+        let paths = match file {
+            Some(file) => minimal_paths(&stack_graph, &mut partials, file)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self { stack_graph, language, outcome, file, partials, paths })
+    }
+}
+
+/// Minimal set of partial paths for a file, which is what the database stores
+fn minimal_paths(stack_graph: &StackGraph, partials: &mut PartialPaths, file: Handle<File>) -> Result<Vec<PartialPath>> {
+    let mut paths = Vec::new();
+    ForwardPartialPathStitcher::find_minimal_partial_path_set_in_file(
+        stack_graph,
+        partials,
+        file,
+        StitcherConfig::default(),
+        &NoCancellation,
+        |_, _, path| paths.push(path.clone()),
+    ).map_err(|e| anyhow!("Failed to compute partial paths for {}: {:?}", stack_graph[file].name(), e))?;
+
+    Ok(paths)
+}
+
+/// Debug info keys that carry source info through the database, which otherwise only
+/// keeps a node's own span and syntax type
+const DEFINIENS_KEY: &str = "tsg_indexer.definiens";
+const FULLY_QUALIFIED_NAME_KEY: &str = "tsg_indexer.fully_qualified_name";
+const CONTAINING_LINE_KEY: &str = "tsg_indexer.containing_line";
+
+/// Copy the definiens spans, fully qualified names and containing lines of a file's
+/// nodes into their debug info before storing it
+fn record_source_info(stack_graph: &mut StackGraph, file: Handle<File>) {
+    let recorded: Vec<(Handle<Node>, &str, String)> = stack_graph.nodes_for_file(file)
+        .filter_map(|node| Some((node, stack_graph.source_info(node)?)))
+        .flat_map(|(node, source_info)| {
+            let definiens = Span::from_lsp(&source_info.definiens_span).map(|span| {
+                format!("{}:{}-{}:{}", span.start_line, span.start_column, span.end_line, span.end_column)
+            });
+            let name = source_info.fully_qualified_name.into_option().map(|name| stack_graph[name].to_string());
+            let line = source_info.containing_line.into_option().map(|line| stack_graph[line].to_string());
+            [(DEFINIENS_KEY, definiens), (FULLY_QUALIFIED_NAME_KEY, name), (CONTAINING_LINE_KEY, line)]
+                .into_iter()
+                .filter_map(move |(key, value)| Some((node, key, value?)))
+        })
+        .collect();
+
+    for (node, key, value) in recorded {
+        let key = stack_graph.add_string(key);
+        let value = stack_graph.add_string(&value);
+        stack_graph.node_debug_info_mut(node).add(key, value);
+    }
+}
+
+/// Restore the fully qualified names and containing lines of a file's nodes after
+/// loading it from the database
+pub(crate) fn restore_source_info(stack_graph: &mut StackGraph, file_name: &str) {
+    let Some(file) = stack_graph.get_file(file_name) else {
+        return;
+    };
+
+    let restored: Vec<(Handle<Node>, Option<String>, Option<String>)> = stack_graph.nodes_for_file(file)
+        .filter_map(|node| {
+            let name = debug_value(stack_graph, node, FULLY_QUALIFIED_NAME_KEY).map(str::to_string);
+            let line = debug_value(stack_graph, node, CONTAINING_LINE_KEY).map(str::to_string);
+            (name.is_some() || line.is_some()).then_some((node, name, line))
+        })
+        .collect();
+    for (node, name, line) in restored {
+        if let Some(name) = name {
+            let name = stack_graph.add_string(&name);
+            stack_graph.source_info_mut(node).fully_qualified_name = name.into();
+        }
+        if let Some(line) = line {
+            let line = stack_graph.add_string(&line);
+            stack_graph.source_info_mut(node).containing_line = line.into();
+        }
+    }
+}
+
+/// Value of a node's debug info entry
+fn debug_value<'a>(stack_graph: &'a StackGraph, node: Handle<Node>, key: &str) -> Option<&'a str> {
+    let entry = stack_graph.node_debug_info(node)?.iter()
+        .find(|entry| &stack_graph[entry.key] == key)?;
+    Some(&stack_graph[entry.value])
+}
+
+/// Definiens span of a node loaded from the index database, as recorded when it was stored
+pub(crate) fn stored_definiens(stack_graph: &StackGraph, node: Handle<Node>) -> Option<Span> {
+    let (start, end) = debug_value(stack_graph, node, DEFINIENS_KEY)?.split_once('-')?;
+    let (start_line, start_column) = start.split_once(':')?;
+    let (end_line, end_column) = end.split_once(':')?;
+    Some(Span {
+        start_line: start_line.parse().ok()?,
+        start_column: start_column.parse().ok()?,
+        end_line: end_line.parse().ok()?,
+        end_column: end_column.parse().ok()?,
+    })
+}
+
+/// Index database for a project root when none is configured, kept in the system
+/// temp directory and named after a hash of the root
+pub fn default_db_path(root: &Path) -> PathBuf {
+    let key = Sha1::digest(root.to_string_lossy().as_bytes());
+    std::env::temp_dir().join(format!("tsg_indexer-{:x}.sqlite", key))
+}
+
+/// Tag stored alongside a file, derived from its content and the indexer version
+fn content_tag(content: &[u8]) -> String {
+    format!("{}:{:x}", env!("CARGO_PKG_VERSION"), Sha1::digest(content))
+}
+
+/// Tag stored alongside builtins files, which only change with the indexer version
+fn builtins_tag() -> String {
+    format!("{}:builtins", env!("CARGO_PKG_VERSION"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::QueryEngine;
+    use crate::report::FileIssue;
+    use crate::symbols;
+    use crate::test_support::project;
+
+    /// The graph of `files` after storing them in an index database and loading it back
+    fn stored_graph(files: &[(&str, &str)]) -> StackGraph {
+        let project = project(files);
+        let db_dir = tempfile::tempdir().unwrap();
+        let mut database = IndexDatabase::open(db_dir.path().join("index.sqlite")).unwrap();
+        database.update(project.path(), project.path(), &FileDiscovery::default(), false, 2).unwrap();
+        database.load_stack_graph().unwrap()
+    }
+
+    #[test]
+    fn update_reindexes_only_changed_files() {
+        let project = project(&[("a.py", "x = 1\n"), ("b.py", "y = 2\n")]);
+        let dir = project.path();
+        let db_dir = tempfile::tempdir().unwrap();
+        let mut database = IndexDatabase::open(db_dir.path().join("index.sqlite")).unwrap();
+        let discovery = FileDiscovery::default();
+
+        let report = database.update(dir, dir, &discovery, false, 2).unwrap();
+        assert_eq!(report.indexed, ["a.py", "b.py"]);
+
+        fs::write(dir.join("a.py"), "x = 3\n").unwrap();
+        fs::remove_file(dir.join("b.py")).unwrap();
+        let report = database.update(dir, dir, &discovery, false, 2).unwrap();
+        assert_eq!(report.indexed, ["a.py"]);
+        assert_eq!(report.removed, ["b.py"]);
+
+        let report = database.update(dir, dir, &discovery, false, 2).unwrap();
+        assert!(report.indexed.is_empty());
+        assert_eq!(report.unchanged, ["a.py"]);
+
+        // Builtins are stored alongside the first file of their language
+        assert_eq!(database.stored_files().unwrap(), ["<builtins:python>", "a.py"]);
+    }
+
+    #[test]
+    fn failed_file_is_retried_only_once_it_changes() {
+        let project = project(&[("a.py", "x = 1\n")]);
+        let dir = project.path();
+        let db_dir = tempfile::tempdir().unwrap();
+        let mut database = IndexDatabase::open(db_dir.path().join("index.sqlite")).unwrap();
+        let discovery = FileDiscovery::default();
+
+        // A failure stored for the file's current content is reported without building it
+        let tag = content_tag(b"x = 1\n");
+        database.store_error("a.py", &tag, anyhow!("Failed to parse file")).unwrap_err();
+        let report = database.update(dir, dir, &discovery, false, 2).unwrap();
+        assert_eq!(report.failed, [FileIssue { file: "a.py".to_string(), reason: "Failed to parse file".to_string() }]);
+        assert!(database.index_file(dir, &dir.join("a.py")).is_err());
+
+        fs::write(dir.join("a.py"), "x = 2\n").unwrap();
+        let report = database.update(dir, dir, &discovery, false, 2).unwrap();
+        assert!(report.failed.is_empty());
+        assert_eq!(report.indexed, ["a.py"]);
+    }
+
+    #[test]
+    fn stored_graph_resolves_across_files() {
+        let graph = stored_graph(&[
+            ("shapes.py", "class Circle:\n    pass\n"),
+            ("main.py", "from shapes import Circle\n\nCircle()\n"),
+        ]);
+
+        let definitions = QueryEngine::new(&graph).unwrap().find_definitions("main.py", 2, 0).unwrap();
+        assert!(definitions.iter().any(|d| d.file == "shapes.py"), "{:?}", definitions);
+    }
+
+    #[test]
+    fn stored_references_keep_snippets() {
+        let graph = stored_graph(&[("app.py", "class Parser:\n    pass\n\nparser = Parser()\n")]);
+
+        let references = QueryEngine::new(&graph).unwrap().find_references("app.py", 0, 6).unwrap();
+        let snippets: Vec<_> = references.iter()
+            .flat_map(|file| &file.references)
+            .map(|reference| reference.snippet.as_deref())
+            .collect();
+        assert_eq!(snippets, [Some("parser = Parser()")]);
+    }
+
+    #[test]
+    fn stored_definitions_keep_containers_and_qualified_names() {
+        let graph = stored_graph(&[("app.py", "class Parser:\n    def parse(self):\n        pass\n")]);
+
+        let file = graph.get_file("app.py").unwrap();
+        let parse = symbols::file_symbols(&graph, file).into_iter()
+            .find(|symbol| symbol.name == "parse")
+            .unwrap();
+        assert_eq!(parse.qualified_name(), "Parser.parse");
+
+        let qualified: Vec<&str> = graph.nodes_for_file(file)
+            .filter(|&node| graph[node].is_definition())
+            .filter_map(|node| graph.source_info(node)?.fully_qualified_name.into_option())
+            .map(|name| &graph[name])
+            .collect();
+        assert!(qualified.contains(&"Parser.parse"), "{:?}", qualified);
+    }
+}
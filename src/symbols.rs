@@ -0,0 +1,584 @@
+use std::cmp::Reverse;
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::Serialize;
+use stack_graphs::arena::Handle;
+use stack_graphs::graph::{File, Node, StackGraph};
+
+use crate::query::Span;
+use crate::storage;
+use crate::tsg_loader::is_builtins_file;
+
+/// A definition in the indexed source, as listed in a symbol table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    /// Syntax type recorded for the definition (function, class, ...), if any
+    pub kind: Option<String>,
+    /// Names of the definitions enclosing this one, outermost first and joined with `.`
+    pub container: Option<String>,
+    /// Project-relative path of the file the definition is in
+    pub file: String,
+    /// Source range of the defined name
+    pub span: Span,
+}
+
+impl Symbol {
+    /// Name including its container path, e.g. `Parser.parse`
+    pub fn qualified_name(&self) -> String {
+        match &self.container {
+            Some(container) => format!("{}.{}", container, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Every definition in the graph's source files whose name appears in the source, in
+/// file and source order
+pub fn symbols(graph: &StackGraph) -> Vec<Symbol> {
+    graph.iter_files()
+        .filter(|&file| !is_builtins_file(graph[file].name()))
+        .flat_map(|file| file_symbols(graph, file))
+        .collect()
+}
+
+/// A definition in a file's outline, along with the definitions nested inside it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutlineItem {
+    pub name: String,
+    /// Syntax type recorded for the definition (function, class, ...), if any
+    pub kind: Option<String>,
+    /// Source range of the defined name
+    pub span: Span,
+    /// Source range of the whole definition, such as a function along with its body,
+    /// if the language's rules record it
+    pub range: Option<Span>,
+    /// Definitions inside this one, in source order
+    pub children: Vec<OutlineItem>,
+}
+
+/// The definitions that make up a single file's structure, in source order
+///
+/// A definition's container is every definition whose definiens span, i.e. the whole
+/// class or function body rather than just its name, encloses it. Parameters, local
+/// variables and imports are left out.
+pub fn file_symbols(graph: &StackGraph, file: Handle<File>) -> Vec<Symbol> {
+    let definitions = structural_definitions(graph, file);
+    let containers = containers(&definitions);
+
+    definitions.into_iter()
+        .zip(containers)
+        .map(|(definition, container)| Symbol { container, ..definition.symbol })
+        .collect()
+}
+
+/// Give each definition in a file that has no fully qualified name one made from its
+/// container path and name, e.g. `Parser.parse`
+pub(crate) fn qualify_definitions(graph: &mut StackGraph, file: Handle<File>) {
+    let definitions = definitions(graph, file);
+    let containers = containers(&definitions);
+
+    for (definition, container) in definitions.into_iter().zip(containers) {
+        let name = Symbol { container, ..definition.symbol }.qualified_name();
+        let name = graph.add_string(&name);
+        for node in definition.nodes {
+            let source_info = graph.source_info_mut(node);
+            if source_info.fully_qualified_name.is_none() {
+                source_info.fully_qualified_name = name.into();
+            }
+        }
+    }
+}
+
+/// The definitions that make up a single file's structure as a tree, each nested in
+/// the definition whose definiens encloses it, e.g. methods in their class
+///
+/// Parameters, local variables and imports are left out, as in [`file_symbols`].
+pub fn outline(graph: &StackGraph, file: Handle<File>) -> Vec<OutlineItem> {
+    let definitions = structural_definitions(graph, file);
+    let parents = parents(&definitions);
+
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); definitions.len()];
+    let mut roots = Vec::new();
+    for (index, parent) in parents.iter().enumerate() {
+        match parent {
+            Some(parent) => children[*parent].push(index),
+            None => roots.push(index),
+        }
+    }
+
+    fn build(index: usize, definitions: &[Definition], children: &[Vec<usize>]) -> OutlineItem {
+        let Definition { symbol, definiens, .. } = &definitions[index];
+        OutlineItem {
+            name: symbol.name.clone(),
+            kind: symbol.kind.clone(),
+            span: symbol.span,
+            range: *definiens,
+            children: children[index].iter()
+                .map(|&child| build(child, definitions, children))
+                .collect(),
+        }
+    }
+
+    roots.into_iter()
+        .map(|index| build(index, &definitions, &children))
+        .collect()
+}
+
+/// The definitions of a file in source order, each with its container filled in and
+/// the index of the definition it is nested in
+///
+/// This is what [`file_symbols`] and [`outline`] are built from, along with the graph
+/// nodes behind each definition so that exporters can match resolved paths to them.
+pub(crate) fn nested_definitions(graph: &StackGraph, file: Handle<File>) -> Vec<(Definition, Option<usize>)> {
+    let mut definitions = definitions(graph, file);
+    let parents = parents(&definitions);
+    let containers = containers(&definitions);
+    for (definition, container) in definitions.iter_mut().zip(containers) {
+        definition.symbol.container = container;
+    }
+
+    definitions.into_iter().zip(parents).collect()
+}
+
+/// A definition found in a file, before its container is known
+pub(crate) struct Definition {
+    pub(crate) symbol: Symbol,
+    pub(crate) definiens: Option<Span>,
+    /// The graph nodes that define the name, since TSG rules can emit several
+    pub(crate) nodes: Vec<Handle<Node>>,
+}
+
+/// Syntax types of the definitions that make up a file's structure, as opposed to
+/// parameters, local variables, imports and definitions with no syntax type at all
+const STRUCTURAL_KINDS: &[&str] = &[
+    "class", "interface", "trait", "struct", "enum", "impl", "type", "type_alias", "module", "namespace",
+    "function", "method", "singleton_method", "interface_method", "constructor",
+    "field", "property", "constant", "variable", "class_variable", "global_variable",
+];
+
+/// Syntax types of definitions whose bodies only hold local definitions
+const CALLABLE_KINDS: &[&str] = &["function", "method", "singleton_method", "constructor"];
+
+/// Definitions of a file that make up its structure, in source order
+///
+/// Variables inside functions are left out even where the language's rules give them
+/// the same syntax type as variables at the top of a file.
+fn structural_definitions(graph: &StackGraph, file: Handle<File>) -> Vec<Definition> {
+    let is_kind = |definition: &Definition, kinds: &[&str]| {
+        definition.symbol.kind.as_deref().is_some_and(|kind| kinds.contains(&kind))
+    };
+
+    let mut definitions = definitions(graph, file);
+    definitions.retain(|definition| is_kind(definition, STRUCTURAL_KINDS));
+
+    let parents = parents(&definitions);
+    let local: Vec<bool> = definitions.iter()
+        .zip(&parents)
+        .map(|(definition, parent)| is_kind(definition, &["variable", "constant"])
+            && parent.is_some_and(|parent| is_kind(&definitions[parent], CALLABLE_KINDS)))
+        .collect();
+    let mut local = local.into_iter();
+    definitions.retain(|_| !local.next().unwrap_or(false));
+
+    definitions
+}
+
+/// Definitions of a file whose names appear in the source, in source order
+fn definitions(graph: &StackGraph, file: Handle<File>) -> Vec<Definition> {
+    let mut definitions: Vec<Definition> = graph.nodes_for_file(file)
+        .filter_map(|node| {
+            let value = &graph[node];
+            let name = value.symbol().filter(|_| value.is_definition())?;
+            let name = &graph[name];
+            let source_info = graph.source_info(node)?;
+
+            // Spans of definitions that do not name something in the source, such as a
+            // file's module or the plumbing of JavaScript exports, are empty or cover
+            // some larger syntax node
+            let span = Span::from_lsp(&source_info.span).filter(|span| {
+                span.start_line == span.end_line && span.end_column - span.start_column == name.len()
+            })?;
+
+            let symbol = Symbol {
+                name: name.to_string(),
+                kind: source_info.syntax_type.into_option().map(|kind| graph[kind].to_string()),
+                container: None,
+                file: graph[file].name().to_string(),
+                span,
+            };
+            // Graphs loaded from the index database keep the definiens in debug info
+            let definiens = Span::from_lsp(&source_info.definiens_span)
+                .or_else(|| storage::stored_definiens(graph, node));
+            Some(Definition { symbol, definiens, nodes: vec![node] })
+        })
+        .collect();
+
+    // Merge the nodes TSG rules emit for one name, not all of which have a definiens
+    definitions.sort_by(|a, b| {
+        (a.symbol.span.start(), &a.symbol.name).cmp(&(b.symbol.span.start(), &b.symbol.name))
+    });
+    definitions.dedup_by(|duplicate, kept| {
+        let same = duplicate.symbol == kept.symbol;
+        if same {
+            kept.definiens = kept.definiens.or(duplicate.definiens);
+            kept.nodes.append(&mut duplicate.nodes);
+        }
+        same
+    });
+
+    definitions
+}
+
+/// Container path of each definition, joined with `.`
+fn containers(definitions: &[Definition]) -> Vec<Option<String>> {
+    let parents = parents(definitions);
+    (0..definitions.len())
+        .map(|index| {
+            let mut path = Vec::new();
+            let mut parent = parents[index];
+            while let Some(index) = parent {
+                path.push(definitions[index].symbol.name.as_str());
+                parent = parents[index];
+            }
+            path.reverse();
+            (!path.is_empty()).then(|| path.join("."))
+        })
+        .collect()
+}
+
+/// Index of the innermost definition enclosing each definition, if there is one
+///
+/// A parent's definiens has to enclose the definition's name and, if it has one, its
+/// definiens as well, so parents always form a tree.
+fn parents(definitions: &[Definition]) -> Vec<Option<usize>> {
+    // Definiens spans nest, so walking the definitions in order only needs the stack
+    // of scopes that are still open
+    let mut scopes: Vec<(Span, usize)> = definitions.iter()
+        .enumerate()
+        .filter_map(|(index, definition)| Some((definition.definiens?, index)))
+        .collect();
+    scopes.sort_by_key(|(definiens, _)| (definiens.start(), Reverse(definiens.end())));
+
+    let mut parents = Vec::with_capacity(definitions.len());
+    let mut open: Vec<(Span, usize)> = Vec::new();
+    let mut next_scope = 0;
+    for definition in definitions {
+        let span = definition.symbol.span;
+        while next_scope < scopes.len() && scopes[next_scope].0.start() <= span.start() {
+            open.push(scopes[next_scope]);
+            next_scope += 1;
+        }
+        // Definitions come in order, so a scope that ends before this one never reopens
+        open.retain(|(definiens, _)| definiens.encloses(&span));
+
+        // A definition's own definiens encloses its name, and so may an identical one
+        // of another definition, like a named function expression assigned to a variable
+        let parent = open.iter().rev()
+            .find(|(definiens, _)| definition.definiens
+                .is_none_or(|own| *definiens != own && definiens.encloses(&own)))
+            .map(|(_, index)| *index);
+        parents.push(parent);
+    }
+
+    parents
+}
+
+/// How a search query is matched against symbol names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// The query's characters appear in the name in order, ignoring case, so `gdef`
+    /// matches `get_definition`
+    #[default]
+    Fuzzy,
+    /// The name starts with the query, ignoring case
+    Prefix,
+    /// The name matches the query as a regular expression
+    Regex,
+}
+
+impl FromStr for MatchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fuzzy" => Ok(Self::Fuzzy),
+            "prefix" => Ok(Self::Prefix),
+            "regex" => Ok(Self::Regex),
+            _ => Err(anyhow!("Unknown match mode `{}`, expected fuzzy, prefix or regex", s)),
+        }
+    }
+}
+
+/// Symbols whose name matches a query, best match first
+///
+/// Exact matches rank first, then matches that differ only in case, then the mode's
+/// own ranking; ties go to shorter names, then to file and source order.
+pub fn search<'a>(symbols: &'a [Symbol], query: &str, mode: MatchMode) -> Result<Vec<&'a Symbol>> {
+    let regex = match mode {
+        MatchMode::Regex => Some(Regex::new(query).with_context(|| format!("Invalid regex: {}", query))?),
+        _ => None,
+    };
+
+    let mut matches: Vec<(i64, &Symbol)> = symbols.iter()
+        .filter_map(|symbol| {
+            let name = symbol.name.as_str();
+            let score = match &regex {
+                Some(regex) => regex_score(regex, name)?,
+                None if mode == MatchMode::Prefix => prefix_score(name, query)?,
+                None => fuzzy_score(name, query)?,
+            };
+
+            let exact = if name == query {
+                2
+            } else if name.to_lowercase() == query.to_lowercase() {
+                1
+            } else {
+                0
+            };
+            Some((exact * 10_000 + score, symbol))
+        })
+        .collect();
+
+    matches.sort_by(|(a_score, a), (b_score, b)| {
+        b_score.cmp(a_score)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| (&a.name, &a.file, a.span.start()).cmp(&(&b.name, &b.file, b.span.start())))
+    });
+
+    Ok(matches.into_iter().map(|(_, symbol)| symbol).collect())
+}
+
+/// Score a prefix match, favoring names whose case matches the query
+fn prefix_score(name: &str, query: &str) -> Option<i64> {
+    if name.starts_with(query) {
+        Some(1)
+    } else if name.to_lowercase().starts_with(&query.to_lowercase()) {
+        Some(0)
+    } else {
+        None
+    }
+}
+
+/// Score a regex match, favoring matches that cover the whole name and start early
+fn regex_score(regex: &Regex, name: &str) -> Option<i64> {
+    let found = regex.find(name)?;
+    let whole = found.start() == 0 && found.end() == name.len();
+    Some(if whole { 1_000 } else { -(found.start() as i64) })
+}
+
+/// Score a fuzzy match, or `None` if the query's characters do not all appear in order
+///
+/// Each matched character scores, with bonuses for runs of consecutive characters,
+/// characters that start a word, and characters whose case matches; characters
+/// skipped before a match cost a little.
+fn fuzzy_score(name: &str, query: &str) -> Option<i64> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+
+    for wanted in query.chars() {
+        let index = (position..chars.len())
+            .find(|&index| chars[index].to_lowercase().eq(wanted.to_lowercase()))?;
+
+        score += 10;
+        if previous.is_some_and(|previous| previous + 1 == index) {
+            score += 15;
+        }
+        if is_word_start(&chars, index) {
+            score += 20;
+        }
+        if chars[index] == wanted {
+            score += 1;
+        }
+        score -= (index - position).min(10) as i64;
+
+        previous = Some(index);
+        position = index + 1;
+    }
+
+    Some(score)
+}
+
+/// Whether the character at `index` starts a word in a snake_case, kebab-case or
+/// camelCase name
+fn is_word_start(chars: &[char], index: usize) -> bool {
+    let Some(&before) = index.checked_sub(1).and_then(|before| chars.get(before)) else {
+        return true;
+    };
+    let current = chars[index];
+
+    !before.is_alphanumeric() && current.is_alphanumeric()
+        || before.is_lowercase() && current.is_uppercase()
+        || !before.is_numeric() && current.is_numeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::graph;
+
+    fn symbol(name: &str, line: usize) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            kind: None,
+            container: None,
+            file: "app.py".to_string(),
+            span: Span { start_line: line, start_column: 0, end_line: line, end_column: name.len() },
+        }
+    }
+
+    fn names<'a>(symbols: &'a [Symbol], query: &str, mode: MatchMode) -> Vec<&'a str> {
+        search(symbols, query, mode).unwrap()
+            .into_iter()
+            .map(|symbol| symbol.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn fuzzy_ranks_exact_then_case_then_word_starts() {
+        let symbols = [
+            symbol("get_definition", 0),
+            symbol("GDef", 1),
+            symbol("gdef", 2),
+            symbol("goodness_defined", 3),
+            symbol("unrelated", 4),
+        ];
+        assert_eq!(
+            names(&symbols, "gdef", MatchMode::Fuzzy),
+            ["gdef", "GDef", "get_definition", "goodness_defined"],
+        );
+    }
+
+    #[test]
+    fn fuzzy_needs_characters_in_order() {
+        let symbols = [symbol("parse", 0), symbol("spare", 1)];
+        assert_eq!(names(&symbols, "pr", MatchMode::Fuzzy), ["parse", "spare"]);
+        assert_eq!(names(&symbols, "ep", MatchMode::Fuzzy), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn prefix_prefers_matching_case_then_shorter_names() {
+        let symbols = [
+            symbol("parse_file", 0),
+            symbol("ParseError", 1),
+            symbol("parse", 2),
+            symbol("reparse", 3),
+        ];
+        assert_eq!(
+            names(&symbols, "parse", MatchMode::Prefix),
+            ["parse", "parse_file", "ParseError"],
+        );
+    }
+
+    #[test]
+    fn regex_prefers_whole_then_earlier_matches() {
+        let symbols = [
+            symbol("load_config", 0),
+            symbol("config", 1),
+            symbol("reload_config_file", 2),
+            symbol("settings", 3),
+        ];
+        assert_eq!(
+            names(&symbols, "config", MatchMode::Regex),
+            ["config", "load_config", "reload_config_file"],
+        );
+        assert_eq!(names(&symbols, "^[a-z]+$", MatchMode::Regex), ["config", "settings"]);
+    }
+
+    #[test]
+    fn ties_go_to_source_order() {
+        let symbols = [symbol("run", 4), symbol("run", 1)];
+        let found = search(&symbols, "run", MatchMode::Fuzzy).unwrap();
+        assert_eq!(found.iter().map(|symbol| symbol.span.start_line).collect::<Vec<_>>(), [1, 4]);
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(search(&[], "(", MatchMode::Regex).is_err());
+    }
+
+    #[test]
+    fn match_mode_parses_ignoring_case() {
+        assert_eq!("Prefix".parse::<MatchMode>().unwrap(), MatchMode::Prefix);
+        assert!("glob".parse::<MatchMode>().is_err());
+    }
+
+    #[test]
+    fn containers_follow_nesting() {
+        let source = "class Parser:\n    def parse(self):\n        def step():\n            pass\n\ndef main():\n    pass\n";
+        let graph = graph(&[("app.py", source)]);
+
+        let file = graph.get_file("app.py").unwrap();
+        let qualified: Vec<String> = file_symbols(&graph, file).iter()
+            .map(Symbol::qualified_name)
+            .collect();
+        assert_eq!(qualified, ["Parser", "Parser.parse", "Parser.parse.step", "main"]);
+    }
+
+    /// Each outline item as its name, kind and nesting depth, in tree order
+    fn flat_outline(name: &str, source: &str) -> Vec<(String, String, usize)> {
+        fn flatten(items: &[OutlineItem], depth: usize, flat: &mut Vec<(String, String, usize)>) {
+            for item in items {
+                flat.push((item.name.clone(), item.kind.clone().unwrap_or_default(), depth));
+                flatten(&item.children, depth + 1, flat);
+            }
+        }
+
+        let graph = graph(&[(name, source)]);
+        let mut flat = Vec::new();
+        flatten(&outline(&graph, graph.get_file(name).unwrap()), 0, &mut flat);
+        flat
+    }
+
+    fn item(name: &str, kind: &str, depth: usize) -> (String, String, usize) {
+        (name.to_string(), kind.to_string(), depth)
+    }
+
+    #[test]
+    fn outline_leaves_out_parameters_locals_and_imports() {
+        let source = "import os\n\nclass User:\n    def __init__(self, id, name):\n        self.id = id\n        self.name = name\n\n\
+                      def helper(x):\n    y = x\n    return y\n\nLIMIT = 10\n";
+
+        assert_eq!(flat_outline("user.py", source), [
+            item("User", "class", 0),
+            item("__init__", "method", 1),
+            item("helper", "function", 0),
+            item("LIMIT", "variable", 0),
+        ]);
+    }
+
+    #[test]
+    fn outline_nests_javascript_methods_in_their_class() {
+        let source = "class Shape {\n  constructor(name) {\n    this.name = name;\n  }\n  area(scale) {\n    let result = 0;\n    return result;\n  }\n}\n\
+                      const VALUE = 1;\n";
+
+        assert_eq!(flat_outline("shapes.js", source), [
+            item("Shape", "class", 0),
+            item("area", "method", 1),
+            item("VALUE", "variable", 0),
+        ]);
+    }
+
+    #[test]
+    fn outline_nests_java_members_in_their_class() {
+        let source = "package shapes;\n\npublic class Shape {\n    private int width;\n\n    public int area(int scale) {\n        int result = width * scale;\n        return result;\n    }\n\n\
+                      class Inner {\n        void run() {}\n    }\n}\n";
+
+        assert_eq!(flat_outline("Shape.java", source), [
+            item("Shape", "class", 0),
+            item("width", "field", 1),
+            item("area", "method", 1),
+            item("Inner", "class", 1),
+            item("run", "method", 2),
+        ]);
+
+        let graph = graph(&[("Shape.java", source)]);
+        let qualified: Vec<String> = file_symbols(&graph, graph.get_file("Shape.java").unwrap()).iter()
+            .map(Symbol::qualified_name)
+            .collect();
+        assert_eq!(qualified, ["Shape", "Shape.width", "Shape.area", "Shape.Inner", "Shape.Inner.run"]);
+    }
+}
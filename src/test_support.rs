@@ -0,0 +1,26 @@
+use std::fs;
+use stack_graphs::graph::StackGraph;
+use tempfile::TempDir;
+
+use crate::indexer;
+
+/// A temporary project directory holding `files`, given as relative paths and contents
+pub(crate) fn project(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, content) in files {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+/// A stack graph of `files`, indexed one by one from a temporary project directory
+pub(crate) fn graph(files: &[(&str, &str)]) -> StackGraph {
+    let project = project(files);
+    let mut stack_graph = StackGraph::new();
+    for (name, _) in files {
+        indexer::index_file(&mut stack_graph, project.path(), &project.path().join(name)).unwrap();
+    }
+    stack_graph
+}
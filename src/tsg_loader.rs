@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, warn};
+use stack_graphs::graph::StackGraph;
+use tree_sitter_stack_graphs::loader::Loader;
+use tree_sitter_stack_graphs::{NoCancellation, StackGraphLanguage, Variables, FILE_PATH_VAR};
+
+use crate::languages::Language;
+
+/// A language whose stack graph construction rules were loaded from its bundled TSG files
+pub struct TsgLanguage {
+    /// The language these rules belong to
+    pub language: Language,
+    /// The compiled stack graph construction rules
+    pub sgl: StackGraphLanguage,
+    /// Stack graph built from the language's builtins source, if it has one
+    pub builtins: StackGraph,
+}
+
+impl TsgLanguage {
+    /// Load the `stack-graphs.tsg`, `builtins.cfg` and builtins source for a language
+    pub fn load(language: &Language, tsg_root: &Path) -> Result<Self> {
+        let sg_name = language.get_stack_graphs_language()
+            .ok_or_else(|| anyhow!("No stack graphs definition for {}", language.name()))?;
+        let grammar = language.get_tree_sitter_language()
+            .ok_or_else(|| anyhow!("No tree-sitter grammar for {}", language.name()))?;
+
+        let src_dir = tsg_root
+            .join(format!("tree-sitter-stack-graphs-{}", sg_name))
+            .join("src");
+        let tsg_path = src_dir.join("stack-graphs.tsg");
+        let tsg_source = fs::read_to_string(&tsg_path)
+            .with_context(|| format!("Failed to read TSG file: {}", tsg_path.display()))?;
+
+        let sgl = StackGraphLanguage::from_source(grammar, tsg_path.clone(), &tsg_source)
+            .map_err(|e| anyhow!("{}", e.display_pretty(&tsg_path, &tsg_source)))?;
+
+        // Builtins are built into their own graph once and copied into every graph that
+        // indexes a file of this language
+        let mut builtins = StackGraph::new();
+        let builtins_path = src_dir.join(format!("builtins.{}", language.get_extension()));
+        if let Ok(builtins_source) = fs::read_to_string(&builtins_path) {
+            let mut globals = Variables::new();
+
+            let cfg_path = src_dir.join("builtins.cfg");
+            if let Ok(builtins_config) = fs::read_to_string(&cfg_path) {
+                Loader::load_globals_from_config_str(&builtins_config, &mut globals)
+                    .with_context(|| format!("Failed to load builtins config: {}", cfg_path.display()))?;
+            }
+
+            let file_name = builtins_file_name(language);
+            globals.add(FILE_PATH_VAR.into(), file_name.as_str().into()).unwrap_or_default();
+
+            let file = builtins.get_or_create_file(&file_name);
+            if let Err(e) = sgl.build_stack_graph_into(&mut builtins, file, &builtins_source, &globals, &NoCancellation) {
+                warn!("Failed to build builtins for {}, continuing without them: {}",
+                    language.name(), e.display_pretty(&builtins_path, &builtins_source, &tsg_path, &tsg_source));
+                builtins = StackGraph::new();
+            }
+        }
+
+        debug!("Loaded TSG rules for {} from {}", language.name(), tsg_path.display());
+        Ok(Self {
+            language: *language,
+            sgl,
+            builtins,
+        })
+    }
+
+    /// Copy this language's builtins into the stack graph, unless they are already present
+    pub fn add_builtins_to(&self, stack_graph: &mut StackGraph) {
+        if self.builtins.iter_files().next().is_none()
+            || stack_graph.get_file(&builtins_file_name(&self.language)).is_some() {
+            return;
+        }
+
+        if let Err(file) = stack_graph.add_from_graph(&self.builtins) {
+            warn!("Builtins file already present in stack graph: {}", stack_graph[file]);
+        }
+    }
+}
+
+/// Name of the stack graph file that holds a language's builtins
+fn builtins_file_name(language: &Language) -> String {
+    format!("<builtins:{}>", language.get_stack_graphs_language().unwrap_or("unknown"))
+}
+
+/// Whether a stack graph file holds a language's builtins rather than indexed source
+pub fn is_builtins_file(file_name: &str) -> bool {
+    file_name.starts_with("<builtins:")
+}
+
+/// Directory holding the bundled `tree-sitter-stack-graphs-*` language definitions
+///
+/// Prefers a `languages` directory relative to the working directory, which is where
+/// the TSG generator writes, and falls back to the one shipped with this crate.
+pub fn default_tsg_root() -> PathBuf {
+    let local = PathBuf::from("languages");
+    if local.is_dir() {
+        local
+    } else {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("languages")
+    }
+}
+
+/// Get the TSG rules for a language, loading and caching them on first use
+///
+/// Returns `None` if the rules could not be loaded; the failure is logged once and
+/// callers are expected to fall back to query-based processing.
+pub fn get_tsg_language(language: &Language) -> Option<Arc<TsgLanguage>> {
+    static CACHE: OnceLock<Mutex<HashMap<Language, Option<Arc<TsgLanguage>>>>> = OnceLock::new();
+
+    let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+
+    cache.entry(*language)
+        .or_insert_with(|| match TsgLanguage::load(language, &default_tsg_root()) {
+            Ok(tsg_language) => Some(Arc::new(tsg_language)),
+            Err(e) => {
+                warn!("Failed to load TSG rules for {}, falling back to query-based indexing: {}", language.name(), e);
+                None
+            }
+        })
+        .clone()
+}
@@ -0,0 +1,502 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use notify_debouncer_full::notify::event::{AccessKind, AccessMode};
+use notify_debouncer_full::notify::{EventKind, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, RecommendedCache};
+use serde::Serialize;
+use stack_graphs::graph::StackGraph;
+
+use crate::discovery::{FileDiscovery, TSG_IGNORE_FILE};
+use crate::indexer::{self, IsolatedFile};
+use crate::report::{FileOutcome, IndexReport};
+use crate::storage::IndexDatabase;
+
+/// How long file changes have to settle before they are re-indexed, by default
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Files whose changes can select or deselect files anywhere below them
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore", TSG_IGNORE_FILE];
+
+/// An update the watcher made to the index for a single file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WatchEvent {
+    /// The file was created or modified and has been re-indexed
+    Indexed { file: String, parse_errors: usize },
+    /// The file changed on disk but its content is what was already indexed
+    Unchanged { file: String },
+    /// The file was not indexed, e.g. because its language is unsupported
+    Skipped { file: String, reason: String },
+    /// Re-indexing the file failed, so it is no longer in the index
+    Failed { file: String, error: String },
+    /// The file was deleted, or is now ignored or excluded, and left the index
+    Removed { file: String },
+}
+
+impl WatchEvent {
+    fn from_result(file: String, result: Result<FileOutcome>) -> Self {
+        match result {
+            Ok(FileOutcome::Indexed { parse_errors }) => Self::Indexed { file, parse_errors },
+            Ok(FileOutcome::Unchanged) => Self::Unchanged { file },
+            Ok(FileOutcome::Skipped(reason)) => Self::Skipped { file, reason },
+            Err(e) => Self::Failed { file, error: format!("{:#}", e) },
+        }
+    }
+
+    /// One event per file in an indexing report
+    fn from_report(report: IndexReport) -> Vec<Self> {
+        let parse_errors = |file: &str| report.parse_errors.iter()
+            .find(|errors| errors.file == file)
+            .map_or(0, |errors| errors.count);
+
+        let mut events = Vec::new();
+        events.extend(report.indexed.iter().map(|file| Self::Indexed { file: file.clone(), parse_errors: parse_errors(file) }));
+        events.extend(report.unchanged.iter().map(|file| Self::Unchanged { file: file.clone() }));
+        events.extend(report.skipped.iter().map(|issue| Self::Skipped { file: issue.file.clone(), reason: issue.reason.clone() }));
+        events.extend(report.failed.iter().map(|issue| Self::Failed { file: issue.file.clone(), error: issue.reason.clone() }));
+        events.extend(report.removed.iter().map(|file| Self::Removed { file: file.clone() }));
+        events
+    }
+
+    /// Name of the file the event is about
+    pub fn file(&self) -> &str {
+        match self {
+            Self::Indexed { file, .. }
+            | Self::Unchanged { file }
+            | Self::Skipped { file, .. }
+            | Self::Failed { file, .. }
+            | Self::Removed { file } => file,
+        }
+    }
+}
+
+/// Source files affected by a batch of file system events
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Files that were created or modified
+    pub modified: Vec<PathBuf>,
+    /// Files that were deleted, or that are now ignored or excluded
+    pub removed: Vec<PathBuf>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Watches a directory for changes to the source files that `discovery` selects
+///
+/// File system events are debounced, so a burst of writes, such as an editor saving
+/// through a temporary file, is reported as one batch of changes.
+pub struct IndexWatcher {
+    root: PathBuf,
+    discovery: FileDiscovery,
+    /// Source files found under the root as of the last batch of changes
+    files: BTreeSet<PathBuf>,
+    /// Files and directories whose changes are not reported, such as the indexer's output
+    ignored: Vec<PathBuf>,
+    events: Receiver<DebounceEventResult>,
+    _debouncer: Debouncer<RecommendedWatcher, RecommendedCache>,
+}
+
+impl IndexWatcher {
+    /// Start watching a directory, waiting `debounce` for changes to settle before
+    /// reporting them
+    pub fn new(root: &Path, discovery: FileDiscovery, debounce: Duration) -> Result<Self> {
+        if !root.is_dir() {
+            bail!("Not a directory: {}", root.display());
+        }
+
+        // Events carry absolute paths, so discovery has to produce them as well
+        let root = root.canonicalize()
+            .with_context(|| format!("Failed to resolve path: {}", root.display()))?;
+
+        let (sender, events) = mpsc::channel();
+        let mut debouncer = new_debouncer(debounce, None, sender).context("Failed to start file watcher")?;
+        debouncer.watch(&root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch directory: {}", root.display()))?;
+
+        let files = discovery.find_source_files(&root)?.into_iter().collect();
+        info!("Watching {}", root.display());
+
+        Ok(Self {
+            root,
+            discovery,
+            files,
+            ignored: Vec::new(),
+            events,
+            _debouncer: debouncer,
+        })
+    }
+
+    /// The watched directory, which file names in events are relative to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Stop reporting changes to a file or directory, e.g. one the watcher's updates are
+    /// written to, which would otherwise trigger another update; it need not exist yet
+    pub fn ignore(&mut self, path: &Path) -> Result<()> {
+        let path = absolute_path(path)?;
+        self.files.retain(|file| !file.starts_with(&path));
+        self.ignored.push(path);
+        Ok(())
+    }
+
+    /// Block until source files change, returning the affected files, or `None` once
+    /// the watcher has stopped
+    ///
+    /// Only the changed paths are rediscovered, so files in new or renamed directories
+    /// are picked up as well. A change to an ignore file rediscovers all source files.
+    pub fn next_changes(&mut self) -> Result<Option<Changes>> {
+        loop {
+            let Ok(result) = self.events.recv() else {
+                return Ok(None);
+            };
+
+            let mut touched = BTreeSet::new();
+            collect_paths(result, &mut touched);
+            // Batches that arrived while the last one was being indexed are handled together
+            while let Ok(result) = self.events.try_recv() {
+                collect_paths(result, &mut touched);
+            }
+            touched.retain(|path| !self.ignored.iter().any(|ignored| path.starts_with(ignored)));
+            if touched.is_empty() {
+                continue;
+            }
+
+            let rewalk = touched.iter().any(|path| {
+                path.file_name().is_some_and(|name| IGNORE_FILES.iter().any(|ignore| name == *ignore))
+            });
+            let (current, previous): (BTreeSet<PathBuf>, BTreeSet<PathBuf>) = if rewalk {
+                (self.discover(&[])?, self.files.clone())
+            } else {
+                let touched: Vec<PathBuf> = touched.iter().cloned().collect();
+                let previous = self.files.iter()
+                    .filter(|file| touched.iter().any(|path| file.starts_with(path)))
+                    .cloned()
+                    .collect();
+                (self.discover(&touched)?, previous)
+            };
+
+            let changes = Changes {
+                modified: current.iter()
+                    .filter(|path| touched.contains(*path) || !previous.contains(*path))
+                    .cloned()
+                    .collect(),
+                removed: previous.difference(&current).cloned().collect(),
+            };
+            for path in &changes.removed {
+                self.files.remove(path);
+            }
+            self.files.extend(current);
+
+            if !changes.is_empty() {
+                debug!("{} modified and {} removed file(s)", changes.modified.len(), changes.removed.len());
+                return Ok(Some(changes));
+            }
+        }
+    }
+
+    /// Source files under the root, among `paths` unless it is empty, leaving out ignored paths
+    fn discover(&self, paths: &[PathBuf]) -> Result<BTreeSet<PathBuf>> {
+        let files = if paths.is_empty() {
+            self.discovery.find_source_files(&self.root)?
+        } else {
+            self.discovery.find_source_files_among(&self.root, paths)?
+        };
+
+        Ok(files.into_iter()
+            .filter(|file| !self.ignored.iter().any(|ignored| file.starts_with(ignored)))
+            .collect())
+    }
+
+    /// Keep an index database up to date with the watched directory
+    ///
+    /// The database is first brought up to date on `threads` worker threads (0 for one
+    /// per CPU). After that and after every batch of changes, `on_update` is called with
+    /// the database and an event for each file that was updated. Watching stops when
+    /// `on_update` returns [`ControlFlow::Break`].
+    pub fn watch_database<F>(&mut self, database: &mut IndexDatabase, threads: usize, mut on_update: F) -> Result<()>
+    where
+        F: FnMut(&IndexDatabase, &[WatchEvent]) -> ControlFlow<()>,
+    {
+        let report = database.update(&self.root, &self.root, &self.discovery, false, threads)?;
+        if on_update(database, &WatchEvent::from_report(report)).is_break() {
+            return Ok(());
+        }
+
+        while let Some(changes) = self.next_changes()? {
+            let mut events = Vec::new();
+            for path in &changes.removed {
+                let file = indexer::file_name_for(&self.root, path);
+                events.push(match database.remove_file(&file) {
+                    Ok(()) => WatchEvent::Removed { file },
+                    Err(e) => WatchEvent::Failed { file, error: format!("{:#}", e) },
+                });
+            }
+            for path in &changes.modified {
+                let file = indexer::file_name_for(&self.root, path);
+                events.push(WatchEvent::from_result(file, database.index_file(&self.root, path)));
+            }
+
+            if on_update(database, &events).is_break() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keep an in-memory stack graph up to date with the watched directory
+    ///
+    /// Only the files in each batch of changes are rebuilt, each into its own graph on
+    /// `threads` worker threads (0 for one per CPU). New files are merged into the stack
+    /// graph as they are; since a stack graph cannot drop a file's nodes, a batch that
+    /// modifies or removes files reassembles the stack graph from the per-file graphs.
+    /// `on_update` is called with the initial graph and after every batch, along with an
+    /// event for each file that was updated. Watching stops when `on_update` returns
+    /// [`ControlFlow::Break`].
+    pub fn watch_graph<F>(&mut self, threads: usize, mut on_update: F) -> Result<()>
+    where
+        F: FnMut(&StackGraph, &[WatchEvent]) -> ControlFlow<()>,
+    {
+        let pool = indexer::thread_pool(threads)?;
+        let mut graphs: BTreeMap<PathBuf, IsolatedFile> = BTreeMap::new();
+        let mut stack_graph = StackGraph::new();
+
+        let mut changes = Some(Changes {
+            modified: self.files.iter().cloned().collect(),
+            removed: Vec::new(),
+        });
+
+        while let Some(batch) = changes {
+            let mut events = Vec::new();
+            let mut reassemble = false;
+            for path in &batch.removed {
+                reassemble |= graphs.remove(path).is_some();
+                events.push(WatchEvent::Removed { file: indexer::file_name_for(&self.root, path) });
+            }
+
+            let root = &self.root;
+            let mut added = Vec::new();
+            indexer::build_in_order(&pool, batch.modified.len(), |index| indexer::index_file_isolated(root, &batch.modified[index]), |index, result| {
+                let path = &batch.modified[index];
+                let file = indexer::file_name_for(root, path);
+                // The file's old nodes are only gone once the graph is reassembled
+                reassemble |= graphs.contains_key(path);
+                let result = result.map(|isolated| {
+                    let outcome = isolated.2.clone();
+                    graphs.insert(path.clone(), isolated);
+                    added.push(path.clone());
+                    outcome
+                });
+                if result.is_err() {
+                    graphs.remove(path);
+                }
+                events.push(WatchEvent::from_result(file, result));
+                Ok(())
+            })?;
+
+            if reassemble {
+                stack_graph = StackGraph::new();
+                for (file_graph, language, _) in graphs.values() {
+                    indexer::merge_graph(&mut stack_graph, file_graph, language)?;
+                }
+            } else {
+                for path in &added {
+                    let (file_graph, language, _) = &graphs[path];
+                    indexer::merge_graph(&mut stack_graph, file_graph, language)?;
+                }
+            }
+
+            if on_update(&stack_graph, &events).is_break() {
+                break;
+            }
+            changes = self.next_changes()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Absolute form of a path that may not exist yet, resolving symbolic links in the
+/// part that does, as file system events do
+fn absolute_path(path: &Path) -> Result<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Ok(path);
+    }
+
+    let absolute = std::path::absolute(path)
+        .with_context(|| format!("Failed to resolve path: {}", path.display()))?;
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => Ok(absolute_path(parent)?.join(name)),
+        _ => Ok(absolute),
+    }
+}
+
+/// Add the paths of a batch of debounced events to `paths`
+fn collect_paths(result: DebounceEventResult, paths: &mut BTreeSet<PathBuf>) {
+    match result {
+        Ok(events) => {
+            // Reading files and directories to index them is reported too, and must not
+            // trigger another update; only closing a file after writing it is a change
+            let changed = events.into_iter().filter(|event| match event.kind {
+                EventKind::Access(kind) => kind == AccessKind::Close(AccessMode::Write),
+                _ => true,
+            });
+            paths.extend(changed.flat_map(|event| event.event.paths));
+        }
+        Err(errors) => {
+            for e in errors {
+                warn!("File watcher error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::query::QueryEngine;
+    use crate::test_support::project;
+
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    fn watcher(root: &Path) -> IndexWatcher {
+        IndexWatcher::new(root, FileDiscovery::default(), DEBOUNCE).unwrap()
+    }
+
+    /// Modified and removed file names, relative to the watched root, from batches of
+    /// changes until all of `modified` and `removed` were reported; a single write can
+    /// arrive in more than one batch
+    fn changes(watcher: &mut IndexWatcher, modified: &[&str], removed: &[&str]) -> (BTreeSet<String>, BTreeSet<String>) {
+        let (mut seen_modified, mut seen_removed) = (BTreeSet::new(), BTreeSet::new());
+        while !modified.iter().all(|file| seen_modified.contains(*file)) || !removed.iter().all(|file| seen_removed.contains(*file)) {
+            let changes = watcher.next_changes().unwrap().unwrap();
+            seen_modified.extend(changes.modified.iter().map(|path| indexer::file_name_for(watcher.root(), path)));
+            seen_removed.extend(changes.removed.iter().map(|path| indexer::file_name_for(watcher.root(), path)));
+        }
+        // Late events for the same writes would otherwise show up in the next step's changes
+        std::thread::sleep(DEBOUNCE * 10);
+        while watcher.events.try_recv().is_ok() {}
+        (seen_modified, seen_removed)
+    }
+
+    fn names(files: &[&str]) -> BTreeSet<String> {
+        files.iter().map(|file| file.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_created_modified_and_removed_files() {
+        let project = project(&[("a.py", "x = 1\n"), ("b.py", "y = 1\n")]);
+        let mut watcher = watcher(project.path());
+
+        fs::write(project.path().join("c.py"), "z = 1\n").unwrap();
+        assert_eq!(changes(&mut watcher, &["c.py"], &[]), (names(&["c.py"]), names(&[])));
+
+        fs::write(project.path().join("a.py"), "x = 2\n").unwrap();
+        assert_eq!(changes(&mut watcher, &["a.py"], &[]), (names(&["a.py"]), names(&[])));
+
+        fs::remove_file(project.path().join("b.py")).unwrap();
+        assert_eq!(changes(&mut watcher, &[], &["b.py"]), (names(&[]), names(&["b.py"])));
+    }
+
+    #[test]
+    fn picks_up_files_in_new_and_removed_directories() {
+        let project = project(&[("a.py", "x = 1\n")]);
+        let mut watcher = watcher(project.path());
+
+        fs::create_dir_all(project.path().join("pkg")).unwrap();
+        fs::write(project.path().join("pkg/b.py"), "y = 1\n").unwrap();
+        assert_eq!(changes(&mut watcher, &["pkg/b.py"], &[]), (names(&["pkg/b.py"]), names(&[])));
+
+        fs::remove_dir_all(project.path().join("pkg")).unwrap();
+        assert_eq!(changes(&mut watcher, &[], &["pkg/b.py"]), (names(&[]), names(&["pkg/b.py"])));
+    }
+
+    #[test]
+    fn ignore_file_change_rediscovers_all_files() {
+        let project = project(&[("a.py", "x = 1\n"), ("gen/b.py", "y = 1\n")]);
+        let mut watcher = watcher(project.path());
+
+        fs::write(project.path().join(TSG_IGNORE_FILE), "gen/\n").unwrap();
+        assert_eq!(changes(&mut watcher, &[], &["gen/b.py"]), (names(&[]), names(&["gen/b.py"])));
+
+        fs::write(project.path().join(TSG_IGNORE_FILE), "").unwrap();
+        assert_eq!(changes(&mut watcher, &["gen/b.py"], &[]), (names(&["gen/b.py"]), names(&[])));
+    }
+
+    #[test]
+    fn ignored_output_does_not_trigger_changes() {
+        let project = project(&[("a.py", "x = 1\n")]);
+        let mut watcher = watcher(project.path());
+        watcher.ignore(&project.path().join("out")).unwrap();
+
+        fs::create_dir_all(project.path().join("out")).unwrap();
+        fs::write(project.path().join("out/graph.py"), "z = 1\n").unwrap();
+        std::thread::sleep(DEBOUNCE * 4);
+        fs::write(project.path().join("a.py"), "x = 2\n").unwrap();
+
+        assert_eq!(changes(&mut watcher, &["a.py"], &[]), (names(&["a.py"]), names(&[])));
+    }
+
+    #[test]
+    fn absolute_path_resolves_paths_that_do_not_exist_yet() {
+        let project = project(&[]);
+        let root = project.path().canonicalize().unwrap();
+
+        assert_eq!(absolute_path(&project.path().join("out/index.db")).unwrap(), root.join("out/index.db"));
+        assert_eq!(absolute_path(project.path()).unwrap(), root);
+
+        let relative = Path::new("missing-dir/index.db");
+        let expected = std::env::current_dir().unwrap().canonicalize().unwrap().join(relative);
+        assert_eq!(absolute_path(relative).unwrap(), expected);
+    }
+
+    #[test]
+    fn watch_graph_drops_modified_and_removed_files() {
+        let project = project(&[("a.py", "def foo():\n    pass\n"), ("b.py", "from a import foo\nfoo()\n")]);
+        let mut watcher = watcher(project.path());
+        let a = project.path().join("a.py");
+        let indexed_a = WatchEvent::Indexed { file: "a.py".to_string(), parse_errors: 0 };
+        let removed_a = WatchEvent::Removed { file: "a.py".to_string() };
+
+        let mut step = 0;
+        watcher.watch_graph(1, |graph, events| {
+            let mut engine = QueryEngine::new(graph).unwrap();
+            let definitions: Vec<(String, usize)> = engine.find_definitions("b.py", 1, 0).unwrap().into_iter()
+                .map(|definition| (definition.file, definition.span.start_line))
+                .collect();
+
+            match step {
+                0 => {
+                    assert!(definitions.contains(&("a.py".to_string(), 0)), "{:?}", definitions);
+                    fs::write(&a, "def bar():\n    pass\n").unwrap();
+                }
+                1 if events.contains(&indexed_a) => {
+                    // The old definition must be gone rather than merged alongside the new file
+                    assert!(definitions.iter().all(|(file, _)| file != "a.py"), "{:?}", definitions);
+                    fs::remove_file(&a).unwrap();
+                }
+                2 if events.contains(&removed_a) => {
+                    assert!(graph.get_file("a.py").is_none());
+                    assert!(graph.get_file("b.py").is_some());
+                    return ControlFlow::Break(());
+                }
+                _ => return ControlFlow::Continue(()),
+            }
+            step += 1;
+            ControlFlow::Continue(())
+        }).unwrap();
+
+        assert_eq!(step, 2);
+    }
+}